@@ -0,0 +1,64 @@
+//! Animating a skeleton without any graphics: a hand-built skeleton and clip are driven by
+//! an AnimationController, and the resulting joint positions printed. Runs with the default
+//! features disabled, e.g. `cargo run --example headless --no-default-features`.
+
+extern crate skeletal_animation;
+
+use std::collections::HashMap;
+
+use skeletal_animation::blend_tree::BlendTreeNodeDef;
+use skeletal_animation::controller::{AnimationControllerDef, AnimationStateDef};
+use skeletal_animation::math::*;
+use skeletal_animation::{AnimationClipBuilder, AnimationController, Joint, QVTransform, Shared, Skeleton, Transform};
+
+fn main() {
+
+    // A two-joint arm, with the elbow one unit along the upper arm
+    let mut elbow_bind_pose = mat4_id();
+    elbow_bind_pose[0][3] = 1.0;
+
+    let skeleton = Shared::new(Skeleton::new(vec![
+        Joint::new("shoulder", None, mat4_id()),
+        Joint::new("elbow", Some(0), elbow_bind_pose),
+    ]));
+
+    // A one-second wave, swinging the shoulder about z
+    let mut builder = AnimationClipBuilder::new().with_samples_per_second(10.0);
+    for i in 0 .. 10 {
+        let mut shoulder = QVTransform::identity();
+        shoulder.set_rotation(quaternion::axis_angle([0.0, 0.0, 1.0], (i as f32 * 0.2 * std::f32::consts::PI).sin() * 0.5));
+
+        let mut elbow = QVTransform::identity();
+        elbow.set_translation([1.0, 0.0, 0.0]);
+
+        builder.push_sample(vec![shoulder, elbow]);
+    }
+
+    let mut animations = HashMap::new();
+    animations.insert("wave".to_string(), Shared::new(builder.build().unwrap()));
+
+    let controller_def = AnimationControllerDef {
+        name: "arm".to_string(),
+        parameters: Vec::new(),
+        parameter_ranges: None,
+        definitions: None,
+        states: vec![AnimationStateDef {
+            name: "waving".to_string(),
+            blend_tree: BlendTreeNodeDef::ClipNode { clip_source: "wave".to_string(), loop_mode: None, phase_param: None },
+            transitions: Vec::new(),
+        }],
+        initial_state: "waving".to_string(),
+    };
+
+    let mut controller = AnimationController::new(controller_def, skeleton.clone(), &animations).unwrap();
+
+    let mut global_poses = vec![mat4_id(); skeleton.joints.len()];
+
+    for _ in 0 .. 5 {
+        controller.update(0.1);
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+
+        let elbow_position = global_poses[1].transform_vector([0.0, 0.0, 0.0]);
+        println!("t = {:.1}s, elbow at {:?}", controller.current_time(), elbow_position);
+    }
+}
@@ -1,17 +1,22 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+#[cfg(not(target_arch = "wasm32"))]
 use std::path::Path;
-use std::rc::Rc;
 
 use collada::document::ColladaDocument;
 use collada;
-use float::Radians;
+use serde::{Deserialize, Serialize};
 
+use Shared;
+use compression::CompressedAnimationClip;
 use math::*;
-use skeleton::Skeleton;
-use transform::Transform;
+#[cfg(not(target_arch = "wasm32"))]
+use skeleton::read_collada_document;
+use skeleton::{ColladaError, Skeleton, parse_collada_document};
+use transform::{FromTransform, Transform};
 
 /// A single skeletal pose
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnimationSample<T: Transform> {
     /// Local pose transforms for each joint in the targeted skeleton
     /// (relative to parent joint)
@@ -19,65 +24,216 @@ pub struct AnimationSample<T: Transform> {
 
 }
 
-/// A sequence of skeletal pose samples at some sample rate
-#[derive(Debug)]
+/// The channels of a joint's pose that an AnimationClip drives. When sampling, channels
+/// that aren't driven are left as they were in the output pose. The scale channel only
+/// applies to transforms that have a scale. Channels left out when deserializing are driven.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelMask {
+    pub translation: bool,
+    pub rotation: bool,
+    pub scale: bool,
+}
+
+impl Default for ChannelMask {
+    fn default() -> Self {
+        Self { translation: true, rotation: true, scale: true }
+    }
+}
+
+/// How a joint's pose is interpolated between the samples of an AnimationClip
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    /// Blend linearly between samples
+    #[default]
+    Linear,
+
+    /// Hold each sample's pose until the next sample, e.g. for discrete or enum-like joints
+    Step,
+}
+
+/// A sequence of skeletal pose samples at some sample rate. Clips can be serialized, e.g.
+/// to save baked clips as JSON, and deserialized, with `sample_times`, `channel_masks`,
+/// `joint_interpolations` and `events` optional.
+///
+/// Clips are plain data, so they're `Send` and `Sync` for the transform types provided, and
+/// can be loaded or processed on a worker thread and sent to the thread that animates with
+/// them, to be shared there through `Shared`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnimationClip<T: Transform> {
     /// The sequence of skeletal poses
     pub samples: Vec<AnimationSample<T>>,
 
-    /// Sample rate for the clip. Assumes a constant sample rate.
+    /// Sample rate for the clip. For clips with `sample_times`, this is the average rate.
     pub samples_per_second: f32,
+
+    /// The time (in seconds) of each sample, for clips with irregular sample timing.
+    /// If None, samples are spaced evenly according to `samples_per_second`. Times are
+    /// absolute: if the first isn't 0, its sample is held from time 0 until then.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_times: Option<Vec<f32>>,
+
+    /// Which channels of each joint's pose the clip drives, by joint index. If None, or for
+    /// joints beyond the end of the list, all channels are driven.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_masks: Option<Vec<ChannelMask>>,
+
+    /// How each joint's pose is interpolated between samples, by joint index. If None, or
+    /// for joints beyond the end of the list, poses are interpolated linearly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub joint_interpolations: Option<Vec<Interpolation>>,
+
+    /// Named events, each with its time (in seconds) relative to the start of the clip
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<(f32, String)>,
+
+    /// Morph target (blend shape) weight tracks, each a target name with one weight per
+    /// sample, animated on the same timeline as the skeleton. Weights missing from the end
+    /// of a short track are 0.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub morph_weights: Vec<(String, Vec<f32>)>,
+}
+
+/// The handedness of a coordinate system. COLLADA is right-handed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Handedness {
+    #[default]
+    Right,
+
+    /// Left-handed, converted to from COLLADA's right-handed coordinates by mirroring the
+    /// z-axis (after the y-up correction), as with `mat4_flip_handedness`
+    Left,
 }
 
-#[derive(Debug, RustcDecodable)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AnimationClipDef {
     pub name: String,
     pub source: String,
-    pub duration: f32,
-    pub rotate_z: f32,
+    pub duration: Option<f32>,
+    pub rotate_z: Option<f32>,
+
+    /// The handedness to convert the clip's poses to, right-handed (as authored) if None
+    pub handedness: Option<Handedness>,
+
+    /// Named events as `[time, name]` pairs, with times in seconds relative to the
+    /// start of the clip (after any duration override)
+    pub events: Option<Vec<(f32, String)>>,
 }
 
-#[derive(Debug, RustcDecodable)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DifferenceClipDef {
     pub name: String,
     pub source_clip: String,
     pub reference_clip: String,
 }
 
+/// The axis normal to the plane an AnimationClip is mirrored across
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MirrorAxis {
+    /// Mirror across the YZ plane
+    X,
+
+    /// Mirror across the XZ plane
+    Y,
+
+    /// Mirror across the XY plane
+    Z,
+}
+
 impl<T: Transform> AnimationClip<T> {
 
-    pub fn from_def(clip_def: &AnimationClipDef) -> Self {
+    /// Load a clip from the COLLADA file named by `clip_def.source`, returning an error if
+    /// it can't be loaded or doesn't contain both a skeleton and animations.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn try_from_def(clip_def: &AnimationClipDef) -> Result<Self, ColladaError> {
+        // FIXME - load skeleton separately?
+        let collada_document = read_collada_document(Path::new(&clip_def.source[..]))?;
+        Self::from_def_and_document(clip_def, &collada_document)
+    }
+
+    /// Like `try_from_def`, but parsing the given COLLADA source text rather than reading
+    /// the file named by `clip_def.source`, e.g. for assets fetched over the network
+    pub fn try_from_def_with_source(clip_def: &AnimationClipDef, collada_source: &str) -> Result<Self, ColladaError> {
+        Self::from_def_and_document(clip_def, &parse_collada_document(collada_source)?)
+    }
+
+    pub(crate) fn from_def_and_document(clip_def: &AnimationClipDef, collada_document: &ColladaDocument) -> Result<Self, ColladaError> {
 
-        // Wacky. Shouldn't it be an error if the struct field isn't present?
-        // FIXME - use an Option
-        let adjust = if !clip_def.rotate_z.is_nan() {
-            mat4_rotate_z(clip_def.rotate_z.deg_to_rad())
-        } else {
-            mat4_id()
+        let adjust = match clip_def.rotate_z {
+            Some(rotate_z) => mat4_rotate_z(rotate_z.to_radians()),
+            None => mat4_id(),
         };
 
-        // FIXME - load skeleton separately?
-        let collada_document = ColladaDocument::from_path(&Path::new(&clip_def.source[..])).unwrap();
-        let animations = collada_document.get_animations().unwrap();
-        let skeleton_set = collada_document.get_skeletons().unwrap();
-        let skeleton = Skeleton::from_collada(&skeleton_set[0]);
+        let animations = collada_document.get_animations().unwrap_or_default();
+        if animations.is_empty() {
+            return Err(ColladaError::MissingAnimations);
+        }
 
-        let mut clip = Self::from_collada(&skeleton, &animations, &adjust);
+        let skeleton = Skeleton::from_collada_document(collada_document)?;
 
-        if !clip_def.duration.is_nan() {
-            clip.set_duration(clip_def.duration);
+        let handedness = clip_def.handedness.unwrap_or_default();
+        let mut clip = Self::from_collada_with_handedness(&skeleton, &animations, &adjust, handedness);
+        clip.joint_interpolations = get_joint_interpolations(collada_document, &skeleton);
+
+        if let Some(duration) = clip_def.duration {
+            clip.set_duration(duration);
         }
-        clip
+
+        if let Some(ref events) = clip_def.events {
+            clip.events = events.clone();
+        }
+
+        Ok(clip)
     }
 
     /// Overrides the sampling rate of the clip to give the given duration (in seconds).
+    /// Event times are scaled along with the samples.
     pub fn set_duration(&mut self, duration: f32) {
+        let scale = duration / self.get_duration();
+        for event in self.events.iter_mut() {
+            event.0 *= scale;
+        }
+        if let Some(ref mut sample_times) = self.sample_times {
+            for time in sample_times.iter_mut() {
+                *time *= scale;
+            }
+        }
         self.samples_per_second = self.samples.len() as f32 / duration;
     }
 
-    /// Return the duration of the clip in seconds
+    /// Return the duration of the clip in seconds. For clips with `sample_times`, this is
+    /// the time of the last sample.
     pub fn get_duration(&self) -> f32 {
-        self.samples.len() as f32 / self.samples_per_second
+        match self.sample_times {
+            Some(ref sample_times) => sample_times.last().cloned().unwrap_or(0.0),
+            None => self.samples.len() as f32 / self.samples_per_second,
+        }
+    }
+
+    /// Return the time of the last sample in seconds, after which sampling wraps
+    /// back towards the first sample
+    pub fn get_last_sample_time(&self) -> f32 {
+        match self.sample_times {
+            Some(ref sample_times) => sample_times.last().cloned().unwrap_or(0.0),
+            None => (self.samples.len().max(1) - 1) as f32 / self.samples_per_second,
+        }
+    }
+
+    /// Return the number of joints posed by each sample, i.e. the length of the output
+    /// slice to sample the clip into, or 0 if the clip has no samples
+    pub fn joint_count(&self) -> usize {
+        self.samples.first().map_or(0, |sample| sample.local_poses.len())
+    }
+
+    /// Return the time of the sample at the given index. Like the duration and sampling,
+    /// this is the sample's own time in `sample_times`, even if the first isn't 0.
+    fn get_sample_timestamp(&self, sample_index: usize) -> f32 {
+        match self.sample_times {
+            Some(ref sample_times) => sample_times[sample_index],
+            None => sample_index as f32 / self.samples_per_second,
+        }
     }
 
     /// Obtains the interpolated skeletal pose at the given sampling time.
@@ -87,8 +243,91 @@ impl<T: Transform> AnimationClip<T> {
     /// * `time` - The time to sample with, relative to the start of the animation
     /// * `blended_poses` - The output array slice of joint transforms that will be populated
     ///                     for each joint in the skeleton.
+    ///
+    /// A clip with no samples leaves `blended_poses` untouched.
     pub fn get_pose_at_time(&self, elapsed_time: f32, blended_poses: &mut [T]) {
 
+        if self.samples.is_empty() {
+            return;
+        }
+
+        let (index_1, index_2, blend_factor) = self.get_bracketing_samples(elapsed_time);
+
+        let sample_1 = &self.samples[index_1];
+        let sample_2 = &self.samples[index_2];
+
+        self.blend_samples(sample_1, sample_2, blend_factor, blended_poses);
+    }
+
+    /// Obtains the interpolated local pose of a single joint at the given sampling time,
+    /// as for `get_pose_at_time`, without interpolating the rest of the skeleton. Returns
+    /// None if the clip has no samples, or no pose for that joint.
+    pub fn interpolated_local_pose(&self, joint_index: usize, elapsed_time: f32) -> Option<T> {
+
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let (index_1, index_2, blend_factor) = self.get_bracketing_samples(elapsed_time);
+
+        let pose_1 = *self.samples[index_1].local_poses.get(joint_index)?;
+        let pose_2 = *self.samples[index_2].local_poses.get(joint_index)?;
+
+        let interpolation = self.joint_interpolations.as_ref()
+            .and_then(|joint_interpolations| joint_interpolations.get(joint_index).cloned())
+            .unwrap_or_default();
+
+        Some(match interpolation {
+            Interpolation::Linear => pose_1.lerp(pose_2, blend_factor),
+            Interpolation::Step if blend_factor >= 1.0 => pose_2,
+            Interpolation::Step => pose_1,
+        })
+    }
+
+    /// Obtains the interpolated weight of each of the clip's morph targets at the given
+    /// sampling time, in the order of `morph_weights`. Weights are interpolated linearly,
+    /// wrapping around as for `get_pose_at_time`. A clip with no samples has weights of 0.
+    pub fn morph_weights_at_time(&self, elapsed_time: f32) -> Vec<f32> {
+
+        if self.samples.is_empty() {
+            return vec![0.0; self.morph_weights.len()];
+        }
+
+        let (index_1, index_2, blend_factor) = self.get_bracketing_samples(elapsed_time);
+
+        self.morph_weights.iter().map(|(_, weights)| {
+            let weight_1 = weights.get(index_1).cloned().unwrap_or(0.0);
+            let weight_2 = weights.get(index_2).cloned().unwrap_or(0.0);
+            weight_1 + (weight_2 - weight_1) * blend_factor
+        }).collect()
+    }
+
+    /// The clip's morph weight tracks, sampled at each of the given times
+    fn sample_morph_weights(&self, times: &[f32]) -> Vec<(String, Vec<f32>)> {
+
+        let mut morph_weights: Vec<(String, Vec<f32>)> = self.morph_weights.iter()
+            .map(|(name, _)| (name.clone(), Vec::with_capacity(times.len())))
+            .collect();
+
+        for &time in times.iter() {
+            for (track, weight) in morph_weights.iter_mut().zip(self.morph_weights_at_time(time)) {
+                track.1.push(weight);
+            }
+        }
+
+        morph_weights
+    }
+
+    /// The indices of the samples either side of the given time, which wraps around at the
+    /// duration of the clip, and the blend factor between them. The clip must have samples.
+    fn get_bracketing_samples(&self, elapsed_time: f32) -> (usize, usize, f32) {
+
+        if let Some(ref sample_times) = self.sample_times {
+            let duration = self.get_duration();
+            let time = if duration > 0.0 { elapsed_time.rem_euclid(duration) } else { 0.0 };
+            return Self::get_irregular_bracketing_samples(sample_times, time);
+        }
+
         let interpolated_index = elapsed_time * self.samples_per_second;
 
         let index_1 = interpolated_index.floor() as usize;
@@ -96,19 +335,506 @@ impl<T: Transform> AnimationClip<T> {
 
         let blend_factor = interpolated_index - index_1 as f32;
 
-        let index_1 = index_1 % self.samples.len();
-        let index_2 = index_2 % self.samples.len();
+        (index_1 % self.samples.len(), index_2 % self.samples.len(), blend_factor)
+    }
 
-        let sample_1 = &self.samples[index_1];
-        let sample_2 = &self.samples[index_2];
+    /// Like `get_pose_at_time`, but for non-looping playback: times before the first sample
+    /// or after the last are clamped to those samples, rather than wrapping around to
+    /// interpolate back towards the start of the clip.
+    pub fn get_pose_at_time_clamped(&self, elapsed_time: f32, blended_poses: &mut [T]) {
+
+        if self.samples.is_empty() {
+            return;
+        }
+
+        let time = elapsed_time.max(0.0).min(self.get_last_sample_time());
+
+        if let Some(ref sample_times) = self.sample_times {
+            return self.get_pose_at_irregular_sample_time(sample_times, time, blended_poses);
+        }
+
+        let last_index = self.samples.len() - 1;
+        let interpolated_index = time * self.samples_per_second;
+
+        let index_1 = (interpolated_index.floor() as usize).min(last_index);
+        let index_2 = (interpolated_index.ceil() as usize).min(last_index);
+
+        let blend_factor = if index_1 == index_2 { 0.0 } else { interpolated_index - index_1 as f32 };
+
+        self.blend_samples(&self.samples[index_1], &self.samples[index_2], blend_factor, blended_poses);
+    }
+
+    /// Iterate over the clip's interpolated local poses at the given rate, from the first
+    /// sample to the last, e.g. for baking, exporting or analysis. Joints whose channels
+    /// aren't driven by the clip are left at identity. A clip with no samples, or a rate
+    /// of zero or less, yields no poses.
+    pub fn iter_poses(&self, samples_per_second: f32) -> impl Iterator<Item = Vec<T>> + '_ {
+
+        let frame_count = if self.samples.is_empty() || samples_per_second <= 0.0 {
+            0
+        } else {
+            (self.get_last_sample_time() * samples_per_second).round() as usize + 1
+        };
+
+        let joint_count = self.joint_count();
+
+        (0 .. frame_count).map(move |frame| {
+            let mut poses = vec![T::identity(); joint_count];
+            self.get_pose_at_time_clamped(frame as f32 / samples_per_second, &mut poses[..]);
+            poses
+        })
+    }
+
+    /// Blend between two samples into the output poses, holding the first sample's pose for
+    /// stepped joints, and leaving any channels that aren't driven by the clip untouched
+    fn blend_samples(&self, sample_1: &AnimationSample<T>, sample_2: &AnimationSample<T>, blend_factor: f32, blended_poses: &mut [T]) {
+
+        // Output slices may be longer than the clip's joint count (e.g. fixed-size buffers)
+        debug_assert!(
+            blended_poses.len() >= sample_1.local_poses.len(),
+            "Output poses ({}) are fewer than the clip's joints ({})",
+            blended_poses.len(), sample_1.local_poses.len()
+        );
+
+        if self.channel_masks.is_none() && self.joint_interpolations.is_none() {
+            return blend_poses(&sample_1.local_poses[..], &sample_2.local_poses[..], blend_factor, blended_poses);
+        }
+
+        for (joint_index, ((pose_1, pose_2), blended_pose)) in sample_1.local_poses.iter()
+            .zip(sample_2.local_poses.iter())
+            .zip(blended_poses.iter_mut())
+            .enumerate()
+        {
+            *blended_pose = self.blend_joint(joint_index, *pose_1, *pose_2, blend_factor, *blended_pose);
+        }
+    }
+
+    /// Blend a single joint's poses from two samples, respecting its interpolation, and
+    /// keeping the channels the clip doesn't drive from `current_pose`
+    fn blend_joint(&self, joint_index: usize, pose_1: T, pose_2: T, blend_factor: f32, current_pose: T) -> T {
+
+        let mask = self.channel_masks.as_ref()
+            .and_then(|channel_masks| channel_masks.get(joint_index).cloned())
+            .unwrap_or_default();
+
+        let interpolation = self.joint_interpolations.as_ref()
+            .and_then(|joint_interpolations| joint_interpolations.get(joint_index).cloned())
+            .unwrap_or_default();
+
+        let mut pose = match interpolation {
+            Interpolation::Linear => pose_1.lerp(pose_2, blend_factor),
+            Interpolation::Step if blend_factor >= 1.0 => pose_2,
+            Interpolation::Step => pose_1,
+        };
+        if !mask.translation {
+            pose.set_translation(current_pose.get_translation());
+        }
+        if !mask.rotation {
+            pose.set_rotation(current_pose.get_rotation());
+        }
+        if !mask.scale {
+            pose.set_scale(current_pose.get_scale());
+        }
+        pose
+    }
+
+    /// Sample a clip with irregular sample times at a time within the range of its samples
+    fn get_pose_at_irregular_sample_time(&self, sample_times: &[f32], time: f32, blended_poses: &mut [T]) {
+        let (index_1, index_2, blend_factor) = Self::get_irregular_bracketing_samples(sample_times, time);
+        self.blend_samples(&self.samples[index_1], &self.samples[index_2], blend_factor, blended_poses);
+    }
+
+    /// Search for the irregularly timed samples either side of a time within the range of
+    /// the samples, returning their indices and the blend factor between them
+    fn get_irregular_bracketing_samples(sample_times: &[f32], time: f32) -> (usize, usize, f32) {
+
+        // Index of the first sample after the time
+        let index_2 = match sample_times.binary_search_by(|sample_time| sample_time.partial_cmp(&time).unwrap_or(Ordering::Less)) {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        };
+
+        let index_2 = index_2.min(sample_times.len() - 1);
+        let index_1 = index_2.saturating_sub(1);
+
+        let interval = sample_times[index_2] - sample_times[index_1];
+        let blend_factor = if interval > 0.0 {
+            ((time - sample_times[index_1]) / interval).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        (index_1, index_2, blend_factor)
+    }
+
+    /// Extracts the portion of the clip between `start_time` and `end_time` (in seconds)
+    /// as a new clip.
+    ///
+    /// Samples are taken at the clip's original rate, starting with an interpolated pose at
+    /// `start_time`, and `samples_per_second` is adjusted so that the duration of the returned
+    /// clip is exactly `end_time - start_time`. The window is clamped to the duration of the
+    /// clip; an empty or inverted window yields a single-sample clip holding the pose at
+    /// `start_time`.
+    pub fn sub_clip(&self, start_time: f32, end_time: f32) -> Self {
+
+        if self.samples.is_empty() {
+            return Self {
+                samples: Vec::new(),
+                samples_per_second: self.samples_per_second,
+                sample_times: None,
+                channel_masks: self.channel_masks.clone(),
+                joint_interpolations: self.joint_interpolations.clone(),
+                events: Vec::new(),
+                morph_weights: self.morph_weights.iter().map(|(name, _)| (name.clone(), Vec::new())).collect(),
+            };
+        }
+
+        let duration = self.get_duration();
+        let start_time = start_time.max(0.0).min(duration);
+        let end_time = end_time.max(start_time).min(duration);
+        let window = end_time - start_time;
+
+        let sample_count = ((window * self.samples_per_second).round() as usize).max(1);
+        let samples_per_second = if window > 0.0 {
+            sample_count as f32 / window
+        } else {
+            self.samples_per_second
+        };
+
+        let joint_count = self.samples[0].local_poses.len();
+
+        let times: Vec<f32> = (0 .. sample_count).map(|sample_index| start_time + sample_index as f32 / samples_per_second).collect();
+
+        let samples = times.iter().map(|&time| {
+            let mut local_poses = vec![T::identity(); joint_count];
+            self.get_pose_at_time(time, &mut local_poses[..]);
+            AnimationSample { local_poses }
+        }).collect();
+
+        let events = self.events.iter()
+            .filter(|&&(time, _)| time >= start_time && (time < end_time || (window <= 0.0 && time == start_time)))
+            .map(|&(time, ref name)| (time - start_time, name.clone()))
+            .collect();
+
+        Self {
+            samples_per_second,
+            samples,
+            sample_times: None,
+            channel_masks: self.channel_masks.clone(),
+            joint_interpolations: self.joint_interpolations.clone(),
+            events,
+            morph_weights: self.sample_morph_weights(&times),
+        }
+    }
+
+    /// Appends the samples of `other` to the end of this clip.
+    ///
+    /// If the sample rates differ, `other` is resampled to this clip's sample rate first,
+    /// unless this clip has irregular `sample_times`, in which case the samples of `other`
+    /// are appended with their own timing, one average sample interval after the last.
+    /// Returns an error if the clips target a different number of joints.
+    pub fn append(&mut self, other: &Self) -> Result<(), &'static str> {
+
+        if let (Some(sample), Some(other_sample)) = (self.samples.first(), other.samples.first()) {
+            if sample.local_poses.len() != other_sample.local_poses.len() {
+                return Err("Cannot append animation clips with different joint counts.");
+            }
+        }
+
+        if self.sample_times.is_some() && !other.samples.is_empty() {
+            // Shift the first sample of `other` to one interval after the last, with its
+            // later samples and events keeping their times relative to it
+            let time_offset = self.get_last_sample_time() + 1.0 / other.samples_per_second - other.get_sample_timestamp(0);
+            self.events.extend(other.events.iter().map(|&(time, ref name)| (time + time_offset, name.clone())));
+            self.append_morph_weights(&other.morph_weights, other.samples.len());
+
+            let other_times = (0 .. other.samples.len()).map(|sample_index| time_offset + other.get_sample_timestamp(sample_index));
+            if let Some(ref mut sample_times) = self.sample_times {
+                sample_times.extend(other_times);
+            }
+
+            self.samples.extend(other.samples.iter().cloned());
+            self.samples_per_second = self.samples.len() as f32 / self.get_duration();
+            return Ok(());
+        }
+
+        let time_offset = self.get_duration();
+        self.events.extend(other.events.iter().map(|&(time, ref name)| (time + time_offset, name.clone())));
+
+        if (other.samples_per_second == self.samples_per_second && other.sample_times.is_none()) || other.samples.is_empty() {
+            self.append_morph_weights(&other.morph_weights, other.samples.len());
+            self.samples.extend(other.samples.iter().cloned());
+        } else {
+            let resampled = other.resample(self.samples_per_second);
+            self.append_morph_weights(&resampled.morph_weights, resampled.samples.len());
+            self.samples.extend(resampled.samples);
+        }
+
+        Ok(())
+    }
+
+    /// Extend the clip's morph weight tracks with those of `sample_count` appended samples,
+    /// matching tracks by name. Targets missing from either clip are given weights of 0
+    /// for its samples. Must be called before the samples themselves are appended.
+    fn append_morph_weights(&mut self, other_morph_weights: &[(String, Vec<f32>)], sample_count: usize) {
+
+        let previous_count = self.samples.len();
+
+        for (name, _) in other_morph_weights.iter() {
+            if !self.morph_weights.iter().any(|track| track.0 == *name) {
+                self.morph_weights.push((name.clone(), Vec::new()));
+            }
+        }
+
+        for (name, weights) in self.morph_weights.iter_mut() {
+            weights.resize(previous_count, 0.0);
+            match other_morph_weights.iter().find(|track| track.0 == *name) {
+                Some((_, other_weights)) => {
+                    weights.extend((0 .. sample_count).map(|sample_index| other_weights.get(sample_index).cloned().unwrap_or(0.0)));
+                }
+                None => weights.resize(previous_count + sample_count, 0.0),
+            }
+        }
+    }
+
+    /// Creates a new clip sampled at uniform intervals of `samples_per_second` across the
+    /// full duration of this clip, e.g. to bring a library of clips to a common rate. As
+    /// for looping playback, samples are interpolated back towards the first sample after
+    /// the last, so the duration is kept (to the nearest sample interval at the new rate).
+    /// Clips with irregular `sample_times` become regularly sampled.
+    ///
+    /// A clip with no samples, or a rate of zero or less, is returned unchanged.
+    pub fn resample(&self, samples_per_second: f32) -> Self {
+
+        if self.samples.is_empty() || samples_per_second <= 0.0 {
+            return self.clone();
+        }
+
+        let joint_count = self.joint_count();
+        let sample_count = ((self.get_duration() * samples_per_second).round() as usize).max(1);
+
+        let times: Vec<f32> = (0 .. sample_count).map(|sample_index| sample_index as f32 / samples_per_second).collect();
+
+        let samples = times.iter().map(|&time| {
+            let mut local_poses = vec![T::identity(); joint_count];
+            self.get_pose_at_time(time, &mut local_poses[..]);
+            AnimationSample { local_poses }
+        }).collect();
+
+        Self {
+            samples,
+            samples_per_second,
+            sample_times: None,
+            channel_masks: self.channel_masks.clone(),
+            joint_interpolations: self.joint_interpolations.clone(),
+            events: self.events.clone(),
+            morph_weights: self.sample_morph_weights(&times),
+        }
+    }
+
+    /// Creates a new clip with its playback time remapped through a piecewise-linear curve,
+    /// e.g. to ease a linearly-timed clip in and out without re-exporting it. Each point of
+    /// `curve` maps a normalized phase of playback (0 to 1) to the phase of this clip that's
+    /// sampled there, with phases before the first point or after the last mapped to the
+    /// first or last point's value. Events are moved to when the new clip first reaches
+    /// them, and dropped if it never does. The new clip is regularly sampled at this clip's
+    /// rate, over the same duration.
+    ///
+    /// Points are clamped to the range 0 to 1. Returns an error if there are fewer than two
+    /// points, if their input phases aren't increasing, or if their output phases decrease.
+    pub fn with_time_curve(&self, curve: &[(f32, f32)]) -> Result<Self, &'static str> {
+
+        if curve.len() < 2 {
+            return Err("A time curve needs at least two points.");
+        }
+
+        let curve: Vec<(f32, f32)> = curve.iter().map(|&(x, y)| (x.clamp(0.0, 1.0), y.clamp(0.0, 1.0))).collect();
+
+        if curve.windows(2).any(|points| points[1].0 <= points[0].0) {
+            return Err("Time curve input phases must be increasing.");
+        }
+        if curve.windows(2).any(|points| points[1].1 < points[0].1) {
+            return Err("Time curve output phases must not decrease.");
+        }
 
-        for i in 0 .. sample_1.local_poses.len() {
+        let duration = self.get_duration();
+        if self.samples.is_empty() || duration <= 0.0 {
+            return Ok(self.clone());
+        }
+
+        let joint_count = self.joint_count();
+        let sample_count = ((duration * self.samples_per_second).round() as usize).max(1);
+
+        let times: Vec<f32> = (0 .. sample_count).map(|sample_index| {
+            let phase = sample_index as f32 / self.samples_per_second / duration;
+            evaluate_time_curve(&curve, phase) * duration
+        }).collect();
+
+        let samples = times.iter().map(|&time| {
+            let mut local_poses = vec![T::identity(); joint_count];
+            self.get_pose_at_time(time, &mut local_poses[..]);
+            AnimationSample { local_poses }
+        }).collect();
+
+        let events = self.events.iter().filter_map(|&(time, ref name)| {
+            invert_time_curve(&curve, time / duration).map(|phase| (phase * duration, name.clone()))
+        }).collect();
+
+        Ok(Self {
+            samples,
+            samples_per_second: self.samples_per_second,
+            sample_times: None,
+            channel_masks: self.channel_masks.clone(),
+            joint_interpolations: self.joint_interpolations.clone(),
+            events,
+            morph_weights: self.sample_morph_weights(&times),
+        })
+    }
+
+    /// Creates a new clip by playing each of the given clips in sequence, at the
+    /// sample rate of the first clip.
+    ///
+    /// Returns an error if no clips are given, or if the clips target a different
+    /// number of joints.
+    pub fn concat(clips: &[Self]) -> Result<Self, &'static str> {
+
+        let (first, rest) = match clips.split_first() {
+            Some(split) => split,
+            None => return Err("Cannot concatenate an empty list of animation clips."),
+        };
+
+        let mut clip = first.clone();
+        for other in rest.iter() {
+            clip.append(other)?;
+        }
+
+        Ok(clip)
+    }
+
+    /// Return a conservative axis-aligned bounding box (min, max) for the skeleton over
+    /// every sample of the clip, with each joint inflated by `joint_radius`
+    pub fn get_bounds(&self, skeleton: &Skeleton, joint_radius: f32) -> (Vector3<f32>, Vector3<f32>) {
+
+        let mut global_poses = vec![mat4_id(); skeleton.joints.len()];
+        let mut bounds: Option<(Vector3<f32>, Vector3<f32>)> = None;
+
+        for sample in self.samples.iter() {
+            skeleton.calculate_global_poses(&sample.local_poses[..], &mut global_poses[..]);
+            let (sample_min, sample_max) = skeleton.get_bounds(&global_poses[..], joint_radius);
+
+            bounds = Some(match bounds {
+                Some((min, max)) => (
+                    [min[0].min(sample_min[0]), min[1].min(sample_min[1]), min[2].min(sample_min[2])],
+                    [max[0].max(sample_max[0]), max[1].max(sample_max[1]), max[2].max(sample_max[2])],
+                ),
+                None => (sample_min, sample_max),
+            });
+        }
+
+        bounds.unwrap_or(([0.0; 3], [0.0; 3]))
+    }
+
+    /// Creates a mirrored copy of the clip, reflected across the plane normal to `axis`.
+    ///
+    /// Each joint takes the reflected pose of its counterpart in `joint_map`, which maps joint
+    /// names to the names of their mirror-image joints (e.g. "LeftFoot" to "RightFoot"), and
+    /// may list each pair in either or both directions. Joints without a counterpart are
+    /// reflected in place.
+    pub fn mirror(&self, skeleton: &Skeleton, joint_map: &HashMap<String, String>, axis: MirrorAxis) -> Self {
+
+        let counterparts: Vec<usize> = skeleton.joints.iter().enumerate().map(|(joint_index, joint)| {
+            let counterpart_name = joint_map.get(&joint.name).or_else(|| {
+                joint_map.iter().find(|&(_, name)| *name == joint.name).map(|(counterpart_name, _)| counterpart_name)
+            });
+            counterpart_name.and_then(|name| skeleton.get_joint_index(name))
+                .map(|index| index as usize)
+                .unwrap_or(joint_index)
+        }).collect();
+
+        let axis_index = match axis {
+            MirrorAxis::X => 0,
+            MirrorAxis::Y => 1,
+            MirrorAxis::Z => 2,
+        };
 
-            let pose_1 = sample_1.local_poses[i];
-            let pose_2 = sample_2.local_poses[i];
+        let samples = self.samples.iter().map(|sample| {
+            let local_poses = (0 .. sample.local_poses.len()).map(|joint_index| {
+                let source_index = counterparts.get(joint_index).cloned().unwrap_or(joint_index);
+                let mut pose = sample.local_poses[source_index];
 
-            let blended_pose = &mut blended_poses[i];
-            *blended_pose = pose_1.lerp(pose_2, blend_factor);
+                // Conjugating by the reflection negates the translation along the axis, and
+                // the rotation components about the two axes lying in the mirror plane
+                let mut translation = pose.get_translation();
+                translation[axis_index] = -translation[axis_index];
+
+                let (w, v) = pose.get_rotation();
+                let mut v = vec3_neg(v);
+                v[axis_index] = -v[axis_index];
+
+                pose.set_rotation((w, v));
+                pose.set_translation(translation);
+                pose
+            }).collect();
+
+            AnimationSample { local_poses }
+        }).collect();
+
+        Self {
+            samples_per_second: self.samples_per_second,
+            samples,
+            sample_times: self.sample_times.clone(),
+            channel_masks: self.channel_masks.as_ref().map(|channel_masks| {
+                (0 .. channel_masks.len()).map(|joint_index| {
+                    let source_index = counterparts.get(joint_index).cloned().unwrap_or(joint_index);
+                    channel_masks.get(source_index).cloned().unwrap_or_default()
+                }).collect()
+            }),
+            joint_interpolations: self.joint_interpolations.as_ref().map(|joint_interpolations| {
+                (0 .. joint_interpolations.len()).map(|joint_index| {
+                    let source_index = counterparts.get(joint_index).cloned().unwrap_or(joint_index);
+                    joint_interpolations.get(source_index).cloned().unwrap_or_default()
+                }).collect()
+            }),
+            events: self.events.clone(),
+            morph_weights: self.morph_weights.clone(),
+        }
+    }
+
+    /// Create an additive clip, holding each sample's difference from a single reference pose,
+    /// for use as the additive input of an AdditiveNode.
+    ///
+    /// For each joint, the rotation is stored as `inverse(reference_rotation) * pose_rotation` and
+    /// the translation as `pose_translation - reference_translation`. The reference is
+    /// expected to be the clip's first sample (`&clip.samples[0].local_poses[..]`), so that
+    /// the additive clip starts from an identity delta; the skeleton bind pose (see
+    /// `Skeleton::get_local_bind_poses`) can be used instead for clips authored relative to
+    /// it. Joints beyond the length of `reference` are left as-is.
+    pub fn to_additive(&self, reference: &[T]) -> Self {
+
+        let samples = self.samples.iter().map(|sample| {
+            let local_poses = sample.local_poses.iter().enumerate().map(|(joint_index, &pose)| {
+                match reference.get(joint_index) {
+                    Some(&reference_pose) => {
+                        let mut delta = T::identity();
+                        delta.set_rotation(quaternion_mul(quaternion_conj(reference_pose.get_rotation()), pose.get_rotation()));
+                        delta.set_translation(vec3_sub(pose.get_translation(), reference_pose.get_translation()));
+                        delta
+                    }
+                    None => pose,
+                }
+            }).collect();
+
+            AnimationSample { local_poses }
+        }).collect();
+
+        Self {
+            samples_per_second: self.samples_per_second,
+            samples,
+            sample_times: self.sample_times.clone(),
+            channel_masks: self.channel_masks.clone(),
+            joint_interpolations: self.joint_interpolations.clone(),
+            events: self.events.clone(),
+            morph_weights: self.morph_weights.clone(),
         }
     }
 
@@ -117,10 +843,10 @@ impl<T: Transform> AnimationClip<T> {
 
         let samples = (0 .. source_clip.samples.len()).map(|sample_index| {
 
-            let ref source_sample = source_clip.samples[sample_index];
+            let source_sample = &source_clip.samples[sample_index];
 
             // Extrapolate reference clip by wrapping, if reference clip is shorter than source clip
-            let ref reference_sample = reference_clip.samples[sample_index % reference_clip.samples.len()];
+            let reference_sample = &reference_clip.samples[sample_index % reference_clip.samples.len()];
 
             let difference_poses = (0 .. source_sample.local_poses.len()).map(|joint_index| {
                 let source_pose = source_sample.local_poses[joint_index];
@@ -128,7 +854,7 @@ impl<T: Transform> AnimationClip<T> {
                 reference_pose.inverse().concat(source_pose)
             }).collect();
 
-            Self {
+            AnimationSample {
                 local_poses: difference_poses,
             }
 
@@ -137,6 +863,11 @@ impl<T: Transform> AnimationClip<T> {
         Self {
             samples_per_second: source_clip.samples_per_second,
             samples,
+            sample_times: source_clip.sample_times.clone(),
+            channel_masks: source_clip.channel_masks.clone(),
+            joint_interpolations: source_clip.joint_interpolations.clone(),
+            events: source_clip.events.clone(),
+            morph_weights: source_clip.morph_weights.clone(),
         }
     }
 
@@ -149,11 +880,25 @@ impl<T: Transform> AnimationClip<T> {
     ///                  `AnimationClip`, using the given `Skeleton`.
     /// * `transform` - An offset transform to apply to the root pose of each animation sample,
     ///                 useful for applying rotation, translation, or scaling when loading an
-    ///                 animation.
-    pub fn from_collada(skeleton: &Skeleton, animations: &Vec<collada::Animation>, transform: &Matrix4<f32>) -> Self {
+    ///                 animation. For skeletons with several roots, it's applied to each.
+    pub fn from_collada(skeleton: &Skeleton, animations: &[collada::Animation], transform: &Matrix4<f32>) -> Self {
+        Self::from_collada_with_handedness(skeleton, animations, transform, Handedness::Right)
+    }
+
+    /// Like `from_collada`, converting every joint's local poses to the given handedness,
+    /// e.g. for a left-handed engine. Translations and rotations are converted together,
+    /// through their matrices, so the clip mirrors consistently down the hierarchy; the
+    /// skeleton's bind poses (see `Skeleton::flip_handedness`) and any meshes skinned with
+    /// it need the same conversion.
+    pub fn from_collada_with_handedness(
+        skeleton: &Skeleton,
+        animations: &[collada::Animation],
+        transform: &Matrix4<f32>,
+        handedness: Handedness,
+    ) -> Self {
         use std::f32::consts::PI;
 
-        // Z-axis is 'up' in COLLADA, so need to rotate root pose about x-axis so y-axis is 'up'
+        // Z-axis is 'up' in COLLADA, so need to rotate each root pose about x-axis so y-axis is 'up'
         let rotate_on_x =
             [
                 [1.0, 0.0, 0.0, 0.0],
@@ -177,9 +922,20 @@ impl<T: Transform> AnimationClip<T> {
         // Assuming all ColladaAnims have the same duration..
         let duration = *animations[0].sample_times.last().unwrap();
 
-        // Assuming constant sample rate
         let samples_per_second = sample_count as f32 / duration;
 
+        // Keep the sample times if they're irregular, rather than assuming a constant rate
+        let sample_times = {
+            let times = &animations[0].sample_times;
+            let mean_interval = if sample_count > 1 { (duration - times[0]) / (sample_count - 1) as f32 } else { 0.0 };
+            let is_irregular = times.windows(2).any(|pair| ((pair[1] - pair[0]) - mean_interval).abs() > mean_interval * 0.01);
+            if is_irregular {
+                Some(times.iter().map(|time| time - times[0]).collect())
+            } else {
+                None
+            }
+        };
+
         let samples = (0 .. sample_count).map(|sample_index| {
 
             // Grab local poses for each joint from COLLADA animation if available,
@@ -192,25 +948,704 @@ impl<T: Transform> AnimationClip<T> {
                 }
             }).collect();
 
-            // Convert local poses to Transforms (for interpolation)
-            let local_poses: Vec<T> = local_poses.iter().map(|pose_matrix| {
-                T::from_matrix(*pose_matrix)
+            // Convert local poses to Transforms (for interpolation), in the given handedness
+            let local_poses: Vec<T> = local_poses.iter().map(|&pose_matrix| {
+                match handedness {
+                    Handedness::Right => T::from_matrix(pose_matrix),
+                    Handedness::Left => T::from_matrix(mat4_flip_handedness(pose_matrix)),
+                }
             }).collect();
 
             AnimationSample {
-                local_poses: local_poses,
+                local_poses,
             }
         }).collect();
 
-        Self { samples_per_second, samples }
+        let mut clip = Self {
+            samples_per_second,
+            samples,
+            sample_times,
+            channel_masks: None,
+            joint_interpolations: None,
+            events: Vec::new(),
+            morph_weights: Vec::new(),
+        };
+        clip.ensure_quaternion_continuity();
+        clip
     }
 
-}
+    /// Quantize the clip's poses to 16 bits per rotation and translation component, with
+    /// translations relative to the bind pose of `skeleton`, trading precision for memory.
+    /// Use `CompressedAnimationClip::decompress` to reconstruct a clip for sampling.
+    pub fn compress(&self, skeleton: &Skeleton) -> CompressedAnimationClip<T> {
+        CompressedAnimationClip::from_clip(self, skeleton)
+    }
+
+    /// Return the global (model-space) transform of the named joint when the clip is sampled
+    /// at the given time, or None if the skeleton has no joint with that name
+    pub fn joint_world_transform(&self, skeleton: &Skeleton, joint_name: &str, time: f32) -> Option<Matrix4<f32>> {
+
+        let joint_index = skeleton.get_joint_index(joint_name)? as usize;
+
+        let mut local_poses = vec![T::identity(); skeleton.joints.len()];
+        self.get_pose_at_time(time, &mut local_poses[..]);
+
+        let mut global_poses = vec![mat4_id(); skeleton.joints.len()];
+        skeleton.calculate_global_poses(&local_poses[..], &mut global_poses[..]);
+
+        Some(global_poses[joint_index])
+    }
+
+    /// Fill `out` with the global (model-space) pose of each joint of `skeleton` at the
+    /// given sampling time, as for `get_pose_at_time` followed by
+    /// `Skeleton::calculate_global_poses`, but interpolating and composing each joint in a
+    /// single pass, without a buffer of local poses. `out` is cleared first, so it can be
+    /// reused between calls. Joints, or channels, that the clip doesn't pose are identity.
+    pub fn global_poses_at_time(&self, skeleton: &Skeleton, elapsed_time: f32, out: &mut Vec<Matrix4<f32>>) {
+
+        out.clear();
+
+        let samples = if self.samples.is_empty() {
+            None
+        } else {
+            let (index_1, index_2, blend_factor) = self.get_bracketing_samples(elapsed_time);
+            Some((&self.samples[index_1], &self.samples[index_2], blend_factor))
+        };
+
+        for (joint_index, joint) in skeleton.joints.iter().enumerate() {
+
+            let local_pose = samples.and_then(|(sample_1, sample_2, blend_factor)| {
+                let pose_1 = *sample_1.local_poses.get(joint_index)?;
+                let pose_2 = *sample_2.local_poses.get(joint_index)?;
+                Some(self.blend_joint(joint_index, pose_1, pose_2, blend_factor, T::identity()))
+            }).unwrap_or_else(T::identity);
+
+            let parent_pose = if joint.is_root() {
+                mat4_id()
+            } else {
+                out[joint.parent_index as usize]
+            };
+
+            out.push(parent_pose.concat(Matrix4::from_transform(local_pose)));
+        }
+    }
+
+    /// Return the linear and angular velocity (in radians per second, about the axis of the
+    /// returned vector) of the joint at `joint_index` in model space, at the given time,
+    /// from the finite difference between the samples either side of that time. Clips with
+    /// fewer than two samples, or no time between them, have zero velocity.
+    pub fn joint_velocity(&self, skeleton: &Skeleton, joint_index: usize, time: f32) -> (Vector3<f32>, Vector3<f32>) {
+
+        if self.samples.len() < 2 {
+            return ([0.0; 3], [0.0; 3]);
+        }
+
+        let (index_1, _, _) = self.get_bracketing_samples(time);
+        let index_2 = (index_1 + 1) % self.samples.len();
+
+        let elapsed_time = if index_2 > index_1 {
+            self.get_sample_timestamp(index_2) - self.get_sample_timestamp(index_1)
+        } else {
+            // Wrapping around from the last sample to the first
+            self.get_duration() - self.get_sample_timestamp(index_1)
+        };
+
+        if elapsed_time <= 0.0 {
+            return ([0.0; 3], [0.0; 3]);
+        }
+
+        let global_pose = |sample_index: usize| {
+            let mut global_poses = vec![mat4_id(); skeleton.joints.len()];
+            skeleton.calculate_global_poses(&self.samples[sample_index].local_poses[..], &mut global_poses[..]);
+            global_poses[joint_index]
+        };
+
+        let pose_1 = global_pose(index_1);
+        let pose_2 = global_pose(index_2);
+
+        let translation_1 = [pose_1[0][3], pose_1[1][3], pose_1[2][3]];
+        let translation_2 = [pose_2[0][3], pose_2[1][3], pose_2[2][3]];
+        let linear_velocity = vec3_scale(vec3_sub(translation_2, translation_1), 1.0 / elapsed_time);
+
+        // Rotation from the first sample to the second, taking the shorter way around
+        let mut delta = quaternion::mul(matrix_to_quaternion(&pose_2), quaternion::conj(matrix_to_quaternion(&pose_1)));
+        if delta.0 < 0.0 {
+            delta = quaternion::scale(delta, -1.0);
+        }
+
+        let sin_half_angle = vec3_len(delta.1);
+        let angular_velocity = if sin_half_angle > 0.0 {
+            let angle = 2.0 * sin_half_angle.atan2(delta.0);
+            vec3_scale(delta.1, angle / (sin_half_angle * elapsed_time))
+        } else {
+            [0.0; 3]
+        };
+
+        (linear_velocity, angular_velocity)
+    }
+
+    /// Warp the root trajectory of the clip so the root pose (of the joint at index 0, as for
+    /// clips from `from_collada`) matches each target pose at that warp's time, e.g. to land
+    /// a vault on a ledge of varying height.
+    ///
+    /// Each warp gives the correction from the clip's root pose to the target at that time,
+    /// which is eased between consecutive warps, and held before the first warp and after
+    /// the last. Targets are matched exactly at warp times that fall on samples, and to
+    /// within interpolation between neighbouring corrections otherwise.
+    pub fn warp_root(&mut self, warps: &[(f32, Matrix4<f32>)]) {
+
+        if self.samples.is_empty() || warps.is_empty() {
+            return;
+        }
+
+        let mut corrections: Vec<(f32, T)> = warps.iter().filter_map(|&(time, target)| {
+            let root_pose = self.interpolated_local_pose(0, time)?;
+            Some((time, T::from_matrix(target).concat(root_pose.inverse())))
+        }).collect();
+
+        corrections.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+        if corrections.is_empty() {
+            return;
+        }
+
+        for sample_index in 0 .. self.samples.len() {
+
+            let time = self.get_sample_timestamp(sample_index);
+
+            let correction = match corrections.iter().position(|&(warp_time, _)| warp_time > time) {
+                Some(0) => corrections[0].1,
+                None => corrections[corrections.len() - 1].1,
+                Some(next) => {
+                    let (time_1, correction_1) = corrections[next - 1];
+                    let (time_2, correction_2) = corrections[next];
+                    let t = (time - time_1) / (time_2 - time_1);
+                    correction_1.lerp(correction_2, t * t * (3.0 - 2.0 * t))
+                }
+            };
+
+            let root_pose = &mut self.samples[sample_index].local_poses[0];
+            *root_pose = correction.concat(*root_pose);
+        }
+    }
+
+    /// Remove samples that can be reconstructed to within `tolerance` by interpolating
+    /// between the samples either side of them, converting the clip to use `sample_times`.
+    /// A sample is only removed if every joint's pose, with translation, rotation and scale
+    /// compared as the elements of its matrix, and every morph weight is within the tolerance.
+    ///
+    /// As for other clips with `sample_times`, the duration of the simplified clip is the
+    /// time of its last sample, so a clip that was evenly sampled becomes one sample
+    /// interval shorter when looped.
+    pub fn simplify(&mut self, tolerance: f32) {
+
+        let sample_count = self.samples.len();
+        if sample_count < 3 {
+            return;
+        }
+
+        let times: Vec<f32> = match self.sample_times {
+            Some(ref sample_times) => sample_times.clone(),
+            None => (0 .. sample_count).map(|sample_index| sample_index as f32 / self.samples_per_second).collect(),
+        };
+
+        // Extend each interval from the last kept sample for as long as the samples it
+        // skips can still be reconstructed
+        let mut kept = vec![0];
+        let mut start = 0;
+        while start < sample_count - 1 {
+            let mut end = start + 1;
+            while end + 1 < sample_count && self.can_interpolate(&times, start, end + 1, tolerance) {
+                end += 1;
+            }
+            kept.push(end);
+            start = end;
+        }
+
+        let mut samples: Vec<Option<AnimationSample<T>>> = self.samples.drain(..).map(Some).collect();
+        self.samples = kept.iter().map(|&sample_index| samples[sample_index].take().unwrap()).collect();
+
+        for (_, weights) in self.morph_weights.iter_mut() {
+            *weights = kept.iter().map(|&sample_index| weights.get(sample_index).cloned().unwrap_or(0.0)).collect();
+        }
+
+        let sample_times: Vec<f32> = kept.iter().map(|&sample_index| times[sample_index]).collect();
+        let duration = sample_times.last().cloned().unwrap_or(0.0);
+        if duration > 0.0 {
+            self.samples_per_second = sample_times.len() as f32 / duration;
+        }
+        self.sample_times = Some(sample_times);
+    }
+
+    /// Whether every sample between `start` and `end` is within `tolerance` of the pose
+    /// interpolated between those two samples
+    fn can_interpolate(&self, times: &[f32], start: usize, end: usize, tolerance: f32) -> bool {
+        let interval = times[end] - times[start];
+        let mut interpolated_poses = self.samples[start].local_poses.clone();
+
+        (start + 1 .. end).all(|sample_index| {
+            let blend_factor = if interval > 0.0 { (times[sample_index] - times[start]) / interval } else { 0.0 };
+            self.blend_samples(&self.samples[start], &self.samples[end], blend_factor, &mut interpolated_poses[..]);
+
+            let weight = |weights: &[f32], index: usize| weights.get(index).cloned().unwrap_or(0.0);
+            let weights_match = self.morph_weights.iter().all(|(_, weights)| {
+                let interpolated_weight = weight(weights, start) + (weight(weights, end) - weight(weights, start)) * blend_factor;
+                (weight(weights, sample_index) - interpolated_weight).abs() <= tolerance
+            });
+
+            weights_match && self.samples[sample_index].local_poses.iter().zip(interpolated_poses.iter()).all(|(pose, interpolated_pose)| {
+                let m1 = pose.to_matrix();
+                let m2 = interpolated_pose.to_matrix();
+                m1.iter().zip(m2.iter()).all(|(row_1, row_2)| {
+                    row_1.iter().zip(row_2.iter()).all(|(a, b)| (a - b).abs() <= tolerance)
+                })
+            })
+        })
+    }
+
+    /// Negate any joint rotation whose quaternion has a negative dot product with the same
+    /// joint's rotation in the previous sample, so that interpolating between samples never
+    /// takes the long way around. Transforms that can't store the sign of their rotation
+    /// (e.g. matrices, where q and -q give the same matrix) are left unchanged.
+    pub fn ensure_quaternion_continuity(&mut self) {
+
+        let joint_count = self.joint_count();
+
+        for joint_index in 0 .. joint_count {
+
+            let mut previous_rotation = self.samples[0].local_poses[joint_index].get_rotation();
+
+            for sample in self.samples.iter_mut().skip(1) {
+
+                let pose = match sample.local_poses.get_mut(joint_index) {
+                    Some(pose) => pose,
+                    None => continue,
+                };
+
+                let rotation = pose.get_rotation();
+                if quaternion::dot(previous_rotation, rotation) >= 0.0 {
+                    previous_rotation = rotation;
+                    continue;
+                }
+
+                let negated_rotation = quaternion::scale(rotation, -1.0);
+                let mut flipped_pose = *pose;
+                flipped_pose.set_rotation(negated_rotation);
+
+                if quaternion::dot(previous_rotation, flipped_pose.get_rotation()) > 0.0 {
+                    *pose = flipped_pose;
+                    previous_rotation = negated_rotation;
+                } else {
+                    previous_rotation = rotation;
+                }
+            }
+        }
+    }
+
+    /// A hash of the clip's content, e.g. to key caches of processed clips and skip
+    /// reprocessing unchanged ones. It covers the sample rate, sample times, each joint's
+    /// pose in every sample, the channel masks, joint interpolations, events and morph
+    /// weights, and is the same across runs, platforms and versions of Rust. Poses are
+    /// hashed as matrices, so equivalent rotations (q and -q) hash the same, and -0.0
+    /// hashes the same as 0.0.
+    pub fn content_hash(&self) -> u64 {
+
+        let mut hasher = ContentHasher::new();
+
+        hasher.write_f32(self.samples_per_second);
+
+        hasher.write_len(self.samples.len());
+        for sample in self.samples.iter() {
+            hasher.write_len(sample.local_poses.len());
+            for pose in sample.local_poses.iter() {
+                for row in pose.to_matrix().iter() {
+                    for &value in row.iter() {
+                        hasher.write_f32(value);
+                    }
+                }
+            }
+        }
+
+        // Optional fields hash a marker first, so that None differs from an empty list
+        match self.sample_times {
+            Some(ref sample_times) => {
+                hasher.write_len(sample_times.len());
+                for &time in sample_times.iter() {
+                    hasher.write_f32(time);
+                }
+            }
+            None => hasher.write_none(),
+        }
+
+        match self.channel_masks {
+            Some(ref channel_masks) => {
+                hasher.write_len(channel_masks.len());
+                for mask in channel_masks.iter() {
+                    hasher.write_bytes(&[mask.translation as u8, mask.rotation as u8, mask.scale as u8]);
+                }
+            }
+            None => hasher.write_none(),
+        }
+
+        match self.joint_interpolations {
+            Some(ref joint_interpolations) => {
+                hasher.write_len(joint_interpolations.len());
+                for interpolation in joint_interpolations.iter() {
+                    hasher.write_bytes(&[match *interpolation {
+                        Interpolation::Linear => 0,
+                        Interpolation::Step => 1,
+                    }]);
+                }
+            }
+            None => hasher.write_none(),
+        }
+
+        hasher.write_len(self.events.len());
+        for &(time, ref name) in self.events.iter() {
+            hasher.write_f32(time);
+            hasher.write_len(name.len());
+            hasher.write_bytes(name.as_bytes());
+        }
+
+        // Morph weights are only hashed when present, so clips without them keep their hashes
+        if !self.morph_weights.is_empty() {
+            hasher.write_len(self.morph_weights.len());
+        }
+        for (name, weights) in self.morph_weights.iter() {
+            hasher.write_len(name.len());
+            hasher.write_bytes(name.as_bytes());
+            hasher.write_len(weights.len());
+            for &weight in weights.iter() {
+                hasher.write_f32(weight);
+            }
+        }
+
+        hasher.finish()
+    }
+
+}
+
+/// 64-bit FNV-1a, for hashes that must be reproducible, unlike those of `DefaultHasher`
+struct ContentHasher(u64);
+
+impl ContentHasher {
+
+    fn new() -> Self {
+        ContentHasher(0xcbf29ce484222325)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes.iter() {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_len(&mut self, len: usize) {
+        self.write_bytes(&[1]);
+        self.write_bytes(&(len as u64).to_le_bytes());
+    }
+
+    fn write_none(&mut self) {
+        self.write_bytes(&[0]);
+    }
+
+    /// Write a float by its bits, with -0.0 written as 0.0 and every NaN written the same
+    fn write_f32(&mut self, value: f32) {
+        let value = if value == 0.0 {
+            0.0
+        } else if value.is_nan() {
+            f32::NAN
+        } else {
+            value
+        };
+        self.write_bytes(&value.to_bits().to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Sample rate of clips built by an AnimationClipBuilder, unless overridden
+const DEFAULT_SAMPLES_PER_SECOND: f32 = 30.0;
+
+/// Builds an AnimationClip from poses generated in code, rather than loaded from COLLADA
+#[derive(Debug, Clone)]
+pub struct AnimationClipBuilder<T: Transform> {
+    samples: Vec<AnimationSample<T>>,
+    samples_per_second: f32,
+}
+
+impl<T: Transform> Default for AnimationClipBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Transform> AnimationClipBuilder<T> {
+
+    pub fn new() -> Self {
+        Self {
+            samples: Vec::new(),
+            samples_per_second: DEFAULT_SAMPLES_PER_SECOND,
+        }
+    }
+
+    /// Set the sample rate of the clip (30 samples per second by default)
+    pub fn with_samples_per_second(mut self, samples_per_second: f32) -> Self {
+        self.samples_per_second = samples_per_second;
+        self
+    }
+
+    /// Append a sample, holding the local pose of each joint
+    pub fn push_sample(&mut self, local_poses: Vec<T>) -> &mut Self {
+        self.samples.push(AnimationSample { local_poses });
+        self
+    }
+
+    /// Build the clip, returning an error if there are no samples, the samples have
+    /// differing joint counts, or the sample rate isn't positive
+    pub fn build(self) -> Result<AnimationClip<T>, &'static str> {
+
+        let joint_count = match self.samples.first() {
+            Some(sample) => sample.local_poses.len(),
+            None => return Err("Clip has no samples."),
+        };
+
+        if self.samples.iter().any(|sample| sample.local_poses.len() != joint_count) {
+            return Err("Clip samples have differing joint counts.");
+        }
+
+        if self.samples_per_second.is_nan() || self.samples_per_second <= 0.0 {
+            return Err("Clip sample rate must be positive.");
+        }
+
+        Ok(AnimationClip {
+            samples: self.samples,
+            samples_per_second: self.samples_per_second,
+            sample_times: None,
+            channel_masks: None,
+            joint_interpolations: None,
+            events: Vec::new(),
+            morph_weights: Vec::new(),
+        })
+    }
+}
+
+/// Find the interpolation of each joint's animation sampler in a COLLADA document, by joint
+/// index. A joint is stepped if every key of its sampler uses STEP interpolation; anything
+/// else, including BEZIER, is treated as linear. Returns None if no joints are stepped.
+fn get_joint_interpolations(collada_document: &ColladaDocument, skeleton: &Skeleton) -> Option<Vec<Interpolation>> {
+
+    let root_element = &collada_document.root_element;
+    let ns = root_element.ns.as_ref().map(|ns| &ns[..]);
+    let library_animations = root_element.get_child("library_animations", ns)?;
+
+    let mut stepped_joints = HashSet::new();
+
+    for animation in library_animations.get_children("animation", ns) {
+
+        let target = animation.get_child("channel", ns).and_then(|channel| channel.get_attribute("target", None));
+        let interpolation_source = animation.get_child("sampler", ns).and_then(|sampler| {
+            sampler.get_children("input", ns).find(|input| input.get_attribute("semantic", None) == Some("INTERPOLATION"))
+        }).and_then(|input| input.get_attribute("source", None));
+
+        let (target, interpolation_source) = match (target, interpolation_source) {
+            (Some(target), Some(interpolation_source)) => (target, interpolation_source.trim_start_matches('#')),
+            _ => continue,
+        };
+
+        let interpolations = animation.get_children("source", ns)
+            .find(|source| source.get_attribute("id", None) == Some(interpolation_source))
+            .and_then(|source| source.get_child("Name_array", ns))
+            .map(|name_array| name_array.content_str());
+
+        if let Some(interpolations) = interpolations {
+            let mut keys = interpolations.split_whitespace().peekable();
+            if keys.peek().is_some() && keys.all(|key| key == "STEP") {
+                stepped_joints.insert(target.split('/').next().unwrap().to_string());
+            }
+        }
+    }
+
+    if stepped_joints.is_empty() {
+        return None;
+    }
+
+    Some(skeleton.joints.iter().map(|joint| {
+        if stepped_joints.contains(&joint.name) { Interpolation::Step } else { Interpolation::Linear }
+    }).collect())
+}
+
+/// The output of a piecewise-linear curve of increasing inputs at `x`, holding the first or
+/// last point's output outside the curve
+fn evaluate_time_curve(curve: &[(f32, f32)], x: f32) -> f32 {
+    match curve.iter().position(|&(point_x, _)| point_x >= x) {
+        Some(0) => curve[0].1,
+        Some(index) => {
+            let (x_1, y_1) = curve[index - 1];
+            let (x_2, y_2) = curve[index];
+            y_1 + (y_2 - y_1) * (x - x_1) / (x_2 - x_1)
+        }
+        None => curve[curve.len() - 1].1,
+    }
+}
+
+/// The first input at which a piecewise-linear curve of non-decreasing outputs reaches `y`,
+/// or None if it never does
+fn invert_time_curve(curve: &[(f32, f32)], y: f32) -> Option<f32> {
+    // Inputs before the first point hold its output, so it's reached from the start
+    if y <= curve[0].1 {
+        return if y == curve[0].1 { Some(0.0) } else { None };
+    }
+    curve.windows(2).find(|points| points[1].1 >= y).map(|points| {
+        let (x_1, y_1) = points[0];
+        let (x_2, y_2) = points[1];
+        x_1 + (x_2 - x_1) * (y - y_1) / (y_2 - y_1)
+    })
+}
+
+/// Linearly blend each joint of pose `a` towards pose `b` by the factor `t`, writing the
+/// results to `out`. Joints beyond the length of the shortest slice are left unchanged.
+pub fn blend_poses<T: Transform>(a: &[T], b: &[T], t: f32, out: &mut [T]) {
+    for ((pose_a, pose_b), blended_pose) in a.iter().zip(b.iter()).zip(out.iter_mut()) {
+        *blended_pose = pose_a.lerp(*pose_b, t);
+    }
+}
+
+/// A measure of the difference between two poses: the sum over joints of the distance
+/// between their translations plus the angle (in radians) between their rotations.
+/// Joints beyond the length of the shorter pose are ignored.
+pub fn pose_distance<T: Transform>(a: &[T], b: &[T]) -> f32 {
+    a.iter().zip(b.iter()).map(|(pose_a, pose_b)| {
+        let translation_distance = vec3_len(vec3_sub(pose_a.get_translation(), pose_b.get_translation()));
+
+        let (w_a, v_a) = pose_a.get_rotation();
+        let (w_b, v_b) = pose_b.get_rotation();
+        let len_a = (w_a * w_a + vec3_square_len(v_a)).sqrt();
+        let len_b = (w_b * w_b + vec3_square_len(v_b)).sqrt();
+        let dot = (w_a * w_b + vec3_dot(v_a, v_b)) / (len_a * len_b);
+        let angle = 2.0 * dot.abs().min(1.0).acos();
+
+        translation_distance + angle
+    }).sum()
+}
+
+/// A measure of the difference between two clips: the sum of `pose_distance` between
+/// their poses at each sample time of a common timeline, at the higher of the two sample
+/// rates and over the longer of the two durations. Past its end, the shorter clip holds
+/// its final pose.
+pub fn clip_distance<T: Transform>(a: &AnimationClip<T>, b: &AnimationClip<T>) -> f32 {
+
+    let joint_count = a.joint_count().min(b.joint_count());
+
+    if joint_count == 0 {
+        return 0.0;
+    }
+
+    let last_sample_time = |clip: &AnimationClip<T>| clip.get_last_sample_time();
+
+    let samples_per_second = a.samples_per_second.max(b.samples_per_second);
+    let duration = last_sample_time(a).max(last_sample_time(b));
+    let sample_count = (duration * samples_per_second).round() as usize + 1;
+
+    let mut poses_a = vec![T::identity(); joint_count];
+    let mut poses_b = vec![T::identity(); joint_count];
+
+    (0 .. sample_count).map(|sample_index| {
+        let time = sample_index as f32 / samples_per_second;
+        a.get_pose_at_time_clamped(time, &mut poses_a[..]);
+        b.get_pose_at_time_clamped(time, &mut poses_b[..]);
+        pose_distance(&poses_a[..], &poses_b[..])
+    }).sum()
+}
+
+/// Layers clips over a base pose imperatively, for simple cases driven from gameplay code
+/// rather than a blend tree. Clips added with `add_clip` are blended by their normalized
+/// weights, with the base pose filling in while their total weight is below 1.0, and
+/// clips added with `add_additive` are then applied on top, as for an AdditiveNode.
+#[derive(Debug, Clone)]
+pub struct PoseAccumulator<T: Transform> {
+    base_pose: Vec<T>,
+    blended_pose: Vec<T>,
+    total_weight: f32,
+    additive_pose: Vec<T>,
+    sampled_pose: Vec<T>,
+}
+
+impl<T: Transform> PoseAccumulator<T> {
+
+    /// Start accumulating from the given local poses
+    pub fn new(base_pose: &[T]) -> Self {
+        Self {
+            base_pose: base_pose.to_vec(),
+            blended_pose: base_pose.to_vec(),
+            total_weight: 0.0,
+            additive_pose: vec![T::identity(); base_pose.len()],
+            sampled_pose: base_pose.to_vec(),
+        }
+    }
+
+    /// Blend in the pose of `clip` at `time`, with the given weight. Weights of zero or
+    /// less are ignored.
+    pub fn add_clip(&mut self, clip: &AnimationClip<T>, time: f32, weight: f32) -> &mut Self {
+        if weight > 0.0 {
+            self.sampled_pose.copy_from_slice(&self.base_pose[..]);
+            clip.get_pose_at_time(time, &mut self.sampled_pose[..]);
+
+            // Running blend, so each clip ends up weighted by its share of the total
+            self.total_weight += weight;
+            let blend_factor = weight / self.total_weight;
+            for (blended_pose, pose) in self.blended_pose.iter_mut().zip(self.sampled_pose.iter()) {
+                *blended_pose = blended_pose.lerp(*pose, blend_factor);
+            }
+        }
+        self
+    }
+
+    /// Apply the pose of the additive `clip` (see `AnimationClip::to_additive`) at `time`
+    /// on top of the blended clips, scaled by the given weight
+    pub fn add_additive(&mut self, clip: &AnimationClip<T>, time: f32, weight: f32) -> &mut Self {
+        for pose in self.sampled_pose.iter_mut() {
+            *pose = T::identity();
+        }
+        clip.get_pose_at_time(time, &mut self.sampled_pose[..]);
+
+        for (additive_pose, pose) in self.additive_pose.iter_mut().zip(self.sampled_pose.iter()) {
+            *additive_pose = additive_pose.concat(T::identity().lerp(*pose, weight));
+        }
+        self
+    }
+
+    /// Return the accumulated local poses
+    pub fn finish(self) -> Vec<T> {
+        let blend_factor = self.total_weight.min(1.0);
+        self.base_pose.iter().zip(self.blended_pose.iter()).zip(self.additive_pose.iter())
+            .map(|((base_pose, blended_pose), additive_pose)| base_pose.lerp(*blended_pose, blend_factor).concat(*additive_pose))
+            .collect()
+    }
+}
+
+/// How a ClipInstance maps local times beyond the end of its clip
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoopMode {
+    /// Hold the final pose once the end of the clip is reached
+    Clamp,
+
+    /// Wrap around to the start of the clip
+    #[default]
+    Loop,
+
+    /// Alternate between playing forwards and backwards
+    PingPong,
+}
 
 /// An instance of an AnimationClip which tracks playback parameters
 pub struct ClipInstance<T: Transform> {
     /// Shared clip reference
-    pub clip: Rc<AnimationClip<T>>,
+    pub clip: Shared<AnimationClip<T>>,
 
     /// Controller clock time at animation start
     pub start_time: f32,
@@ -220,19 +1655,35 @@ pub struct ClipInstance<T: Transform> {
 
     /// Used to account for changes in playback rate
     pub time_offset: f32,
+
+    /// Behavior when playback passes the end of the clip
+    pub loop_mode: LoopMode,
 }
 
 impl<T: Transform> ClipInstance<T> {
 
-    pub fn new(clip: Rc<AnimationClip<T>>) -> Self {
+    pub fn new(clip: Shared<AnimationClip<T>>) -> Self {
         Self {
             clip,
             start_time: 0.0,
             playback_rate: 1.0,
             time_offset: 0.0,
+            loop_mode: LoopMode::Loop,
         }
     }
 
+    pub fn set_loop_mode(&mut self, loop_mode: LoopMode) {
+        self.loop_mode = loop_mode;
+    }
+
+    /// Rewind the instance to play from the start of the clip at a global time of 0.0, at
+    /// its original speed
+    pub fn reset(&mut self) {
+        self.start_time = 0.0;
+        self.playback_rate = 1.0;
+        self.time_offset = 0.0;
+    }
+
     /// Adjust the playback rate of the clip without affecting the
     /// value of get_local_time for a given global time.
     pub fn set_playback_rate(&mut self, global_time: f32, new_rate: f32) {
@@ -244,14 +1695,1001 @@ impl<T: Transform> ClipInstance<T> {
     }
 
     pub fn get_pose_at_time(&self, global_time: f32, blended_poses: &mut [T]) {
-        self.clip.get_pose_at_time(self.get_local_time(global_time), blended_poses);
+        let sample_time = self.get_sample_time(global_time);
+        match self.loop_mode {
+            LoopMode::Loop => self.clip.get_pose_at_time(sample_time, blended_poses),
+
+            // Sample times are already within the clip, but may land exactly on the last sample
+            LoopMode::Clamp | LoopMode::PingPong => self.clip.get_pose_at_time_clamped(sample_time, blended_poses),
+        }
+    }
+
+    /// Sample the clip at a normalized phase of its duration, where 0.0 is the start of the
+    /// clip and 1.0 is the end, ignoring the playback rate and loop mode.
+    pub fn get_pose_at_phase(&self, phase: f32, blended_poses: &mut [T]) {
+        self.clip.get_pose_at_time(phase.rem_euclid(1.0) * self.get_duration(), blended_poses);
     }
 
     pub fn get_duration(&self) -> f32 {
         self.clip.get_duration()
     }
 
+    /// Collect the names of the clip's events that are passed when playback advances from
+    /// `prev_global_time` (exclusive) to `curr_global_time` (inclusive). On looping and
+    /// ping-pong clips, an event is reported once for each time it's passed.
+    pub fn collect_events<'a>(&'a self, prev_global_time: f32, curr_global_time: f32, events: &mut Vec<&'a str>) {
+
+        let prev_time = self.get_local_time(prev_global_time);
+        let curr_time = self.get_local_time(curr_global_time);
+
+        if curr_time <= prev_time || self.clip.events.is_empty() {
+            return;
+        }
+
+        let duration = self.get_duration();
+        let last_sample_time = self.clip.get_last_sample_time();
+
+        for &(time, ref name) in self.clip.events.iter() {
+
+            // Positions of the event in each repetition of the playback cycle
+            let (period, positions) = match self.loop_mode {
+                LoopMode::Clamp => {
+                    let time = time.min(last_sample_time);
+                    if prev_time < time && time <= curr_time {
+                        events.push(&name[..]);
+                    }
+                    continue;
+                }
+                LoopMode::Loop => (duration, [time, time]),
+                LoopMode::PingPong => (2.0 * last_sample_time, [time, 2.0 * last_sample_time - time]),
+            };
+
+            if period <= 0.0 {
+                continue;
+            }
+
+            let mut unique_positions = &positions[..];
+            if positions[0] == positions[1] || (positions[1] - positions[0]).abs() == period {
+                unique_positions = &positions[.. 1];
+            }
+
+            for &position in unique_positions.iter() {
+                let mut cycle_time = position + ((prev_time - position) / period).floor() * period;
+                while cycle_time <= prev_time {
+                    cycle_time += period;
+                }
+                while cycle_time <= curr_time {
+                    events.push(&name[..]);
+                    cycle_time += period;
+                }
+            }
+        }
+    }
+
+    /// Playback position at the given global time as a fraction of the clip duration, in [0, 1)
+    pub fn get_phase(&self, global_time: f32) -> f32 {
+        let duration = self.get_duration();
+        if duration > 0.0 {
+            (self.get_sample_time(global_time) / duration).rem_euclid(1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Whether a non-looping clip has played through by the given global time, i.e. its
+    /// local time has reached the clip's duration. Looping and ping-pong clips never finish.
+    pub fn is_finished(&self, global_time: f32) -> bool {
+        match self.loop_mode {
+            LoopMode::Clamp => self.get_local_time(global_time) >= self.get_duration(),
+            LoopMode::Loop | LoopMode::PingPong => false,
+        }
+    }
+
+    /// Whether playback wraps back to the start of the clip's cycle when advancing from
+    /// `prev_global_time` to `curr_global_time`, in either direction and however many
+    /// cycles are passed. A ping-pong cycle is once forwards and back; non-looping clips
+    /// never wrap.
+    pub fn has_wrapped(&self, prev_global_time: f32, curr_global_time: f32) -> bool {
+        let period = match self.loop_mode {
+            LoopMode::Clamp => return false,
+            LoopMode::Loop => self.get_duration(),
+            LoopMode::PingPong => 2.0 * self.clip.get_last_sample_time(),
+        };
+
+        if period <= 0.0 {
+            return false;
+        }
+
+        let prev_cycle = (self.get_local_time(prev_global_time) / period).floor();
+        let curr_cycle = (self.get_local_time(curr_global_time) / period).floor();
+        prev_cycle != curr_cycle
+    }
+
     fn get_local_time(&self, global_time: f32) -> f32 {
         (global_time - self.start_time) * self.playback_rate + self.time_offset
     }
+
+    /// The time to sample the clip at for the given global time, according to the loop mode
+    fn get_sample_time(&self, global_time: f32) -> f32 {
+        let local_time = self.get_local_time(global_time);
+
+        // Time of the final sample; beyond it, sampling would blend back towards the first
+        let last_sample_time = self.clip.get_last_sample_time();
+
+        match self.loop_mode {
+            LoopMode::Loop => local_time,
+            LoopMode::Clamp => local_time.max(0.0).min(last_sample_time),
+            LoopMode::PingPong => {
+                if last_sample_time <= 0.0 {
+                    return 0.0;
+                }
+                let cycle_time = local_time.abs() % (2.0 * last_sample_time);
+                if cycle_time > last_sample_time { 2.0 * last_sample_time - cycle_time } else { cycle_time }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    use std::collections::HashMap;
+
+    use collada;
+    use collada::document::ColladaDocument;
+
+    use Shared;
+    use math::*;
+    use skeleton::{Joint, Skeleton};
+
+    use super::{AnimationClip, AnimationClipBuilder, AnimationSample, ChannelMask, ClipInstance, Handedness, Interpolation, LoopMode, MirrorAxis,
+                PoseAccumulator, get_joint_interpolations, pose_distance, clip_distance};
+    use transform::{Transform, QVTransform};
+
+    static EPSILON: f32 = 0.000001;
+
+    fn make_clip(sample_count: usize, samples_per_second: f32) -> AnimationClip<QVTransform> {
+        AnimationClip {
+            samples: (0 .. sample_count).map(|i| {
+                let mut pose = QVTransform::identity();
+                pose.set_translation([i as f32, 0.0, 0.0]);
+                AnimationSample { local_poses: vec![pose] }
+            }).collect(),
+            samples_per_second,
+            sample_times: None,
+            channel_masks: None,
+            joint_interpolations: None,
+            events: Vec::new(),
+            morph_weights: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_content_hash() {
+
+        let clip = make_clip(4, 10.0);
+        assert_eq!(clip.content_hash(), make_clip(4, 10.0).content_hash());
+
+        // The hash is fixed, so caches stay valid between runs and builds
+        assert_eq!(make_clip(2, 10.0).content_hash(), 45926646764705589);
+
+        // Negative zero is the same content
+        let mut negative_zero_clip = make_clip(4, 10.0);
+        negative_zero_clip.samples[2].local_poses[0].translation[1] = -0.0;
+        assert_eq!(clip.content_hash(), negative_zero_clip.content_hash());
+
+        // Any change to the poses, sample rate or other fields changes the hash
+        let mut changed_clip = make_clip(4, 10.0);
+        changed_clip.samples[2].local_poses[0].translation[1] = 0.001;
+        assert!(clip.content_hash() != changed_clip.content_hash());
+        assert!(clip.content_hash() != make_clip(4, 20.0).content_hash());
+        assert!(clip.content_hash() != make_clip(5, 10.0).content_hash());
+
+        let mut step_clip = make_clip(4, 10.0);
+        step_clip.joint_interpolations = Some(vec![Interpolation::Step]);
+        assert!(clip.content_hash() != step_clip.content_hash());
+
+        let mut event_clip = make_clip(4, 10.0);
+        event_clip.events.push((0.1, "footstep".to_string()));
+        assert!(clip.content_hash() != event_clip.content_hash());
+
+        let mut empty_times_clip = make_clip(0, 10.0);
+        empty_times_clip.sample_times = Some(Vec::new());
+        assert!(make_clip(0, 10.0).content_hash() != empty_times_clip.content_hash());
+    }
+
+    #[test]
+    fn test_joint_count() {
+        assert_eq!(make_clip(3, 10.0).joint_count(), 1);
+        assert_eq!(make_clip(0, 10.0).joint_count(), 0);
+    }
+
+    #[test]
+    fn test_sub_clip() {
+
+        let clip = make_clip(10, 10.0);
+
+        let sub_clip = clip.sub_clip(0.25, 0.55);
+        assert_eq!(sub_clip.samples.len(), 3);
+        assert!((sub_clip.get_duration() - 0.3).abs() < EPSILON);
+        assert!((sub_clip.samples[0].local_poses[0].translation[0] - 2.5).abs() < EPSILON);
+        assert!((sub_clip.samples[2].local_poses[0].translation[0] - 4.5).abs() < EPSILON);
+
+        // Window is clamped to the clip's duration
+        let sub_clip = clip.sub_clip(-1.0, 0.5);
+        assert_eq!(sub_clip.samples.len(), 5);
+        assert!((sub_clip.get_duration() - 0.5).abs() < EPSILON);
+
+        // Inverted window yields a single pose
+        let sub_clip = clip.sub_clip(0.5, 0.2);
+        assert_eq!(sub_clip.samples.len(), 1);
+        assert!((sub_clip.samples[0].local_poses[0].translation[0] - 5.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_from_collada_with_handedness() {
+
+        let skeleton = Skeleton::new(vec![
+            Joint::new("hip", None, mat4_id()),
+            Joint::new("knee", Some(0), mat4_id()),
+        ]);
+
+        let mut hip_pose = quaternion_to_matrix(quaternion::axis_angle(vec3_normalized([1.0, 1.0, 0.0]), 0.6));
+        hip_pose[0][3] = 0.5;
+        hip_pose[1][3] = 1.0;
+        hip_pose[2][3] = 2.0;
+        let mut knee_pose = quaternion_to_matrix(quaternion::axis_angle([0.0, 0.0, 1.0], 0.9));
+        knee_pose[1][3] = 1.5;
+
+        let animations = [
+            collada::Animation { target: "hip/transform".to_string(), sample_times: vec![0.0, 0.5], sample_poses: vec![hip_pose, hip_pose] },
+            collada::Animation { target: "knee/transform".to_string(), sample_times: vec![0.0, 0.5], sample_poses: vec![knee_pose, knee_pose] },
+        ];
+
+        let right_clip = AnimationClip::<QVTransform>::from_collada(&skeleton, &animations, &mat4_id());
+        let left_clip = AnimationClip::<QVTransform>::from_collada_with_handedness(&skeleton, &animations, &mat4_id(), Handedness::Left);
+
+        let global_poses = |clip: &AnimationClip<QVTransform>| {
+            let mut global_poses = [mat4_id(); 2];
+            skeleton.calculate_global_poses(&clip.samples[0].local_poses[..], &mut global_poses);
+            global_poses
+        };
+        let right_poses = global_poses(&right_clip);
+        let left_poses = global_poses(&left_clip);
+
+        // Every point in the left-handed clip is the mirror image of the right-handed one
+        let mirror = |v: Vector3<f32>| [v[0], v[1], -v[2]];
+        for &v in [[0.0, 0.0, 0.0], [0.3, -0.5, 0.8]].iter() {
+            for joint_index in 0 .. 2 {
+                let right = right_poses[joint_index].transform_vector(v);
+                let left = left_poses[joint_index].transform_vector(mirror(v));
+                assert!(vec3_len(vec3_sub(left, mirror(right))) < 0.0001);
+            }
+        }
+    }
+
+    #[test]
+    fn test_global_poses_at_time() {
+
+        let skeleton = Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+            Joint::new("child", Some(0), mat4_id()),
+            Joint::new("unposed", Some(1), mat4_id()),
+        ]);
+
+        // The root moves along x and the child is offset along y, with no pose for the
+        // third joint
+        let mut clip = make_clip(4, 10.0);
+        for sample in clip.samples.iter_mut() {
+            let mut child_pose = QVTransform::identity();
+            child_pose.set_translation([0.0, 2.0, 0.0]);
+            sample.local_poses.push(child_pose);
+        }
+
+        let mut global_poses = vec![mat4_id(); 5];
+        clip.global_poses_at_time(&skeleton, 0.15, &mut global_poses);
+        assert_eq!(global_poses.len(), 3);
+
+        let mut expected_local_poses = [QVTransform::identity(); 3];
+        clip.get_pose_at_time(0.15, &mut expected_local_poses);
+        let mut expected_global_poses = [mat4_id(); 3];
+        skeleton.calculate_global_poses(&expected_local_poses, &mut expected_global_poses);
+        assert_eq!(&global_poses[..], &expected_global_poses[..]);
+
+        assert!(vec3_len(vec3_sub(global_poses[2].transform_vector([0.0, 0.0, 0.0]), [1.5, 2.0, 0.0])) < 0.0001);
+    }
+
+    #[test]
+    fn test_with_time_curve() {
+
+        // Ten samples over a second, with x = i
+        let mut clip = make_clip(10, 10.0);
+        clip.events = vec![(0.25, "halfway".to_string()), (0.9, "end".to_string())];
+
+        // Half speed through the first half, then one and a half times speed
+        let eased = clip.with_time_curve(&[(0.0, 0.0), (0.5, 0.25), (1.0, 1.0)]).unwrap();
+        assert_eq!(eased.samples.len(), 10);
+        assert_eq!(eased.samples_per_second, 10.0);
+        for &(sample_index, x) in [(0, 0.0), (2, 1.0), (5, 2.5), (6, 4.0), (9, 8.5)].iter() {
+            assert!((eased.samples[sample_index].local_poses[0].get_translation()[0] - x).abs() < EPSILON);
+        }
+        assert_eq!(eased.events.len(), 2);
+        assert!((eased.events[0].0 - 0.5).abs() < EPSILON);
+        assert!((eased.events[1].0 - (0.5 + 0.65 / 1.5)).abs() < EPSILON);
+
+        // A curve that stops short of the end holds its last phase, and drops later events
+        let held = clip.with_time_curve(&[(0.0, 0.0), (0.5, 0.5)]).unwrap();
+        assert!((held.samples[8].local_poses[0].get_translation()[0] - 5.0).abs() < EPSILON);
+        assert_eq!(held.events, vec![(0.25, "halfway".to_string())]);
+
+        // Points are clamped to 0 to 1, and must be monotonic
+        let clamped = clip.with_time_curve(&[(-1.0, -1.0), (2.0, 2.0)]).unwrap();
+        assert!((clamped.samples[3].local_poses[0].get_translation()[0] - 3.0).abs() < EPSILON);
+        assert!(clip.with_time_curve(&[(0.0, 0.0)]).is_err());
+        assert!(clip.with_time_curve(&[(0.0, 0.0), (0.5, 0.5), (0.5, 1.0)]).is_err());
+        assert!(clip.with_time_curve(&[(0.0, 0.5), (1.0, 0.25)]).is_err());
+    }
+
+    #[test]
+    fn test_resample() {
+
+        // Ten samples at 10 per second, with x = i
+        let clip = make_clip(10, 10.0);
+
+        let resampled = clip.resample(30.0);
+        assert_eq!(resampled.samples.len(), 30);
+        assert!((resampled.get_duration() - clip.get_duration()).abs() < EPSILON);
+        assert!((resampled.samples[4].local_poses[0].translation[0] - 4.0 / 3.0).abs() < 0.0001);
+
+        // Two thirds of the way from the last sample (x = 9) back towards the first (x = 0)
+        assert!((resampled.samples[29].local_poses[0].translation[0] - 3.0).abs() < 0.0001);
+
+        let resampled = clip.resample(5.0);
+        assert_eq!(resampled.samples.len(), 5);
+        assert!((resampled.samples[2].local_poses[0].translation[0] - 4.0).abs() < EPSILON);
+
+        assert_eq!(clip.resample(0.0).samples.len(), 10);
+        assert!(make_clip(0, 10.0).resample(30.0).samples.is_empty());
+    }
+
+    #[test]
+    fn test_concat() {
+
+        let mut second = make_clip(4, 20.0);
+        second.events.push((0.1, "land".to_string()));
+
+        let clip = AnimationClip::concat(&[make_clip(4, 10.0), second]).unwrap();
+        assert_eq!(clip.samples.len(), 6);
+        assert_eq!(clip.events.len(), 1);
+        assert!((clip.events[0].0 - 0.5).abs() < EPSILON);
+        assert!((clip.get_duration() - 0.6).abs() < EPSILON);
+        assert!((clip.samples[5].local_poses[0].translation[0] - 2.0).abs() < EPSILON);
+
+        let mut mismatched = make_clip(2, 10.0);
+        mismatched.samples[0].local_poses.push(QVTransform::identity());
+        mismatched.samples[1].local_poses.push(QVTransform::identity());
+        assert!(AnimationClip::concat(&[make_clip(4, 10.0), mismatched]).is_err());
+        assert!(AnimationClip::<QVTransform>::concat(&[]).is_err());
+    }
+
+    #[test]
+    fn test_clip_instance_loop_modes() {
+
+        let mut instance = ClipInstance::new(Shared::new(make_clip(10, 10.0)));
+        let mut poses = [QVTransform::identity(); 1];
+
+        let sample_x = |instance: &ClipInstance<QVTransform>, time: f32, poses: &mut [QVTransform]| {
+            instance.get_pose_at_time(time, poses);
+            poses[0].get_translation()[0]
+        };
+
+        assert!((sample_x(&instance, 1.25, &mut poses) - 2.5).abs() < EPSILON);
+
+        instance.set_loop_mode(LoopMode::Clamp);
+        assert!((sample_x(&instance, 1.25, &mut poses) - 9.0).abs() < EPSILON);
+        assert!((sample_x(&instance, -1.0, &mut poses) - 0.0).abs() < EPSILON);
+
+        instance.set_loop_mode(LoopMode::PingPong);
+        assert!((sample_x(&instance, 0.5, &mut poses) - 5.0).abs() < 0.0001);
+        assert!((sample_x(&instance, 1.2, &mut poses) - 6.0).abs() < 0.0001);
+        assert!((sample_x(&instance, 1.9, &mut poses) - 1.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_clip_instance_events() {
+
+        let mut clip = make_clip(10, 10.0);
+        clip.events = vec![(0.3, "footstep".to_string()), (0.8, "hit".to_string())];
+
+        let mut instance = ClipInstance::new(Shared::new(clip));
+
+        let collect = |instance: &ClipInstance<QVTransform>, prev_time: f32, curr_time: f32| {
+            let mut events = Vec::new();
+            instance.collect_events(prev_time, curr_time, &mut events);
+            events.iter().map(|name| name.to_string()).collect::<Vec<String>>()
+        };
+
+        assert_eq!(collect(&instance, 0.2, 0.5), vec!["footstep"]);
+        assert_eq!(collect(&instance, 0.3, 0.5), Vec::<String>::new());
+        assert_eq!(collect(&instance, 0.9, 1.35), vec!["footstep"]);
+        assert_eq!(collect(&instance, 0.0, 2.0), vec!["footstep", "footstep", "hit", "hit"]);
+
+        instance.set_loop_mode(LoopMode::Clamp);
+        assert_eq!(collect(&instance, 0.0, 5.0), vec!["footstep", "hit"]);
+
+        // Passed once going forwards, and again going backwards
+        instance.set_loop_mode(LoopMode::PingPong);
+        assert_eq!(collect(&instance, 0.0, 1.8), vec!["footstep", "footstep", "hit", "hit"]);
+    }
+
+    #[test]
+    fn test_mirror() {
+
+        let skeleton = Skeleton::new(["left", "right", "spine"].iter().map(|name| {
+            Joint::new(name, None, mat4_id())
+        }).collect());
+
+        let rotation = quaternion::axis_angle([0.0, 1.0, 0.0], 0.5);
+
+        let mut left_pose = QVTransform::identity();
+        left_pose.set_translation([1.0, 2.0, 3.0]);
+        left_pose.set_rotation(rotation);
+
+        let mut spine_pose = QVTransform::identity();
+        spine_pose.set_translation([0.5, 1.0, 0.0]);
+
+        let mut builder = AnimationClipBuilder::new().with_samples_per_second(10.0);
+        builder.push_sample(vec![left_pose, QVTransform::identity(), spine_pose]);
+        let clip = builder.build().unwrap();
+
+        let mut joint_map = HashMap::new();
+        joint_map.insert("left".to_string(), "right".to_string());
+
+        let mirrored = clip.mirror(&skeleton, &joint_map, MirrorAxis::X);
+        let poses = &mirrored.samples[0].local_poses;
+
+        // The right joint takes the left joint's pose, with its yaw reversed
+        assert!(vec3_len(vec3_sub(poses[1].translation, [-1.0, 2.0, 3.0])) < EPSILON);
+        let expected = quaternion::axis_angle([0.0, 1.0, 0.0], -0.5);
+        let dot = poses[1].rotation.0 * expected.0 + vec3_dot(poses[1].rotation.1, expected.1);
+        assert!((dot - 1.0).abs() < EPSILON);
+
+        assert!(vec3_len(vec3_sub(poses[0].translation, [0.0, 0.0, 0.0])) < EPSILON);
+        assert!(vec3_len(vec3_sub(poses[2].translation, [-0.5, 1.0, 0.0])) < EPSILON);
+    }
+
+    #[test]
+    fn test_get_bounds() {
+
+        let skeleton = Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+            Joint::new("child", Some(0), mat4_id()),
+        ]);
+
+        let mut clip = make_clip(3, 10.0);
+        for sample in clip.samples.iter_mut() {
+            let mut child_pose = QVTransform::identity();
+            child_pose.set_translation([0.0, 0.0, -1.0]);
+            sample.local_poses.push(child_pose);
+        }
+
+        let (min, max) = clip.get_bounds(&skeleton, 0.5);
+        assert!(vec3_len(vec3_sub(min, [-0.5, -0.5, -1.5])) < EPSILON);
+        assert!(vec3_len(vec3_sub(max, [2.5, 0.5, 0.5])) < EPSILON);
+    }
+
+    #[test]
+    fn test_joint_world_transform() {
+
+        let skeleton = Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+            Joint::new("hand", Some(0), mat4_id()),
+        ]);
+
+        let mut clip = make_clip(3, 10.0);
+        for sample in clip.samples.iter_mut() {
+            let mut hand_pose = QVTransform::identity();
+            hand_pose.set_translation([0.0, 1.0, 0.0]);
+            sample.local_poses.push(hand_pose);
+        }
+
+        // Root is at x = 1.5 halfway between samples 1 and 2, with the hand above it
+        let hand_transform = clip.joint_world_transform(&skeleton, "hand", 0.15).unwrap();
+        assert!(vec3_len(vec3_sub(hand_transform.get_translation(), [1.5, 1.0, 0.0])) < 0.0001);
+
+        assert!(clip.joint_world_transform(&skeleton, "foot", 0.15).is_none());
+    }
+
+    #[test]
+    fn test_pose_and_clip_distance() {
+
+        let mut pose_a = QVTransform::identity();
+        pose_a.set_translation([1.0, 0.0, 0.0]);
+
+        let mut pose_b = QVTransform::identity();
+        pose_b.set_rotation(quaternion::axis_angle([0.0, 0.0, 1.0], 0.5));
+
+        assert!(pose_distance(&[pose_a], &[pose_a]).abs() < EPSILON);
+        assert!((pose_distance(&[pose_a], &[pose_b]) - 1.5).abs() < 0.0001);
+
+        let clip = make_clip(10, 10.0);
+        assert!(clip_distance(&clip, &clip).abs() < EPSILON);
+
+        // The shorter clip holds x = 2.0 past 0.2 seconds, differing by 1.0 and 2.0
+        // at the following samples
+        assert!((clip_distance(&make_clip(5, 10.0), &make_clip(3, 10.0)) - 3.0).abs() < 0.0001);
+
+        // Resampled to the higher rate, the same motion at 20 samples per second
+        let mut fast_clip = make_clip(9, 20.0);
+        for sample in fast_clip.samples.iter_mut() {
+            let x = sample.local_poses[0].translation[0];
+            sample.local_poses[0].translation[0] = x * 0.5;
+        }
+        assert!(clip_distance(&make_clip(5, 10.0), &fast_clip).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_to_additive() {
+
+        let mut clip = make_clip(3, 10.0);
+        let rotation = quaternion::axis_angle([0.0, 1.0, 0.0], 0.5);
+        for sample in clip.samples.iter_mut() {
+            sample.local_poses[0].set_rotation(rotation);
+        }
+
+        let reference = clip.samples[0].local_poses.clone();
+        let additive = clip.to_additive(&reference[..]);
+
+        for (i, sample) in additive.samples.iter().enumerate() {
+            let delta = sample.local_poses[0];
+            assert!(vec3_len(vec3_sub(delta.get_translation(), [i as f32, 0.0, 0.0])) < EPSILON);
+            assert!((delta.get_rotation().0.abs() - 1.0).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_irregular_sample_times() {
+
+        let mut clip = make_clip(4, 4.0);
+        clip.sample_times = Some(vec![0.0, 0.1, 0.5, 1.0]);
+
+        let mut poses = [QVTransform::identity(); 1];
+        let mut sample_x = |time: f32| {
+            clip.get_pose_at_time(time, &mut poses);
+            poses[0].get_translation()[0]
+        };
+
+        assert!((sample_x(0.3) - 1.5).abs() < 0.0001);
+        assert!((sample_x(0.75) - 2.5).abs() < 0.0001);
+        assert!((sample_x(0.5) - 2.0).abs() < 0.0001);
+
+        // Wraps around at the time of the last sample
+        assert!((sample_x(1.25) - 1.375).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_sample_times_not_starting_at_zero() {
+
+        let mut clip = make_clip(3, 1.5);
+        clip.sample_times = Some(vec![0.5, 1.0, 2.0]);
+        clip.events.push((0.5, "start".to_string()));
+
+        // Sample times are used as they are, so the first sample is held until its time
+        assert_eq!(clip.get_sample_timestamp(0), 0.5);
+        assert_eq!(clip.get_duration(), 2.0);
+        let mut poses = [QVTransform::identity(); 1];
+        clip.get_pose_at_time_clamped(0.25, &mut poses);
+        assert!(poses[0].translation[0].abs() < EPSILON);
+        clip.get_pose_at_time(1.5, &mut poses);
+        assert!((poses[0].translation[0] - 1.5).abs() < 0.0001);
+
+        // Appended, the clip's first sample and the event at its time stay together
+        let mut irregular_clip = make_clip(2, 4.0);
+        irregular_clip.sample_times = Some(vec![0.0, 0.5]);
+        irregular_clip.append(&clip).unwrap();
+
+        let appended_start = irregular_clip.get_sample_timestamp(2);
+        assert!((appended_start - (0.5 + 1.0 / 1.5)).abs() < EPSILON);
+        assert!((irregular_clip.events[0].0 - appended_start).abs() < EPSILON);
+        assert!((irregular_clip.get_sample_timestamp(4) - (appended_start + 1.5)).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_joint_velocity() {
+
+        let skeleton = Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+        ]);
+
+        // Moving 1 along x and rotating 0.1 radians about z per sample, at 10 samples per second
+        let mut builder = AnimationClipBuilder::new().with_samples_per_second(10.0);
+        for i in 0 .. 10 {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([i as f32, 0.0, 0.0]);
+            pose.set_rotation(quaternion::axis_angle([0.0, 0.0, 1.0], 0.1 * i as f32));
+            builder.push_sample(vec![pose]);
+        }
+        let clip = builder.build().unwrap();
+
+        let (linear_velocity, angular_velocity) = clip.joint_velocity(&skeleton, 0, 0.25);
+        assert!(vec3_len(vec3_sub(linear_velocity, [10.0, 0.0, 0.0])) < 0.001);
+        assert!(vec3_len(vec3_sub(angular_velocity, [0.0, 0.0, 1.0])) < 0.001);
+    }
+
+    #[test]
+    fn test_warp_root() {
+
+        let target = |x: f32| {
+            let mut target = mat4_id();
+            target[0][3] = x;
+            target[1][3] = 1.0;
+            target
+        };
+
+        let mut clip = make_clip(10, 10.0);
+        clip.warp_root(&[(0.6, target(2.0)), (0.2, target(5.0))]);
+
+        let root_translation = |clip: &AnimationClip<QVTransform>, sample_index: usize| {
+            clip.samples[sample_index].local_poses[0].translation
+        };
+
+        // Matched at the warp times
+        assert!(vec3_len(vec3_sub(root_translation(&clip, 2), [5.0, 1.0, 0.0])) < EPSILON);
+        assert!(vec3_len(vec3_sub(root_translation(&clip, 6), [2.0, 1.0, 0.0])) < EPSILON);
+
+        // Corrections held outside the warps, and eased between them
+        assert!(vec3_len(vec3_sub(root_translation(&clip, 0), [3.0, 1.0, 0.0])) < EPSILON);
+        assert!(vec3_len(vec3_sub(root_translation(&clip, 9), [5.0, 1.0, 0.0])) < EPSILON);
+        assert!(vec3_len(vec3_sub(root_translation(&clip, 4), [4.0 - 0.5, 1.0, 0.0])) < 0.0001);
+    }
+
+    #[test]
+    fn test_pose_accumulator() {
+
+        let translation = |x: f32| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([x, 0.0, 0.0]);
+            pose
+        };
+
+        let clip_at = |x: f32| {
+            let mut builder = AnimationClipBuilder::new().with_samples_per_second(1.0);
+            builder.push_sample(vec![translation(x)]);
+            builder.build().unwrap()
+        };
+
+        let base_pose = [translation(0.0)];
+        let x = |poses: Vec<QVTransform>| poses[0].translation[0];
+
+        // Weights are normalized
+        let mut accumulator = PoseAccumulator::new(&base_pose[..]);
+        accumulator.add_clip(&clip_at(2.0), 0.0, 1.0).add_clip(&clip_at(8.0), 0.0, 3.0);
+        assert!((x(accumulator.finish()) - 6.5).abs() < EPSILON);
+
+        // The base pose fills in below a total weight of 1.0, with additive clips on top
+        let mut accumulator = PoseAccumulator::new(&base_pose[..]);
+        accumulator.add_clip(&clip_at(4.0), 0.0, 0.25).add_additive(&clip_at(2.0), 0.0, 0.5);
+        assert!((x(accumulator.finish()) - 2.0).abs() < EPSILON);
+
+        // Nothing added gives the base pose
+        assert_eq!(x(PoseAccumulator::new(&base_pose[..]).finish()), 0.0);
+    }
+
+    #[test]
+    fn test_iter_poses() {
+
+        // Samples at x = 0 to 9 over 0.9 seconds, resampled at twice the rate
+        let clip = make_clip(10, 10.0);
+        let poses: Vec<Vec<QVTransform>> = clip.iter_poses(20.0).collect();
+        assert_eq!(poses.len(), 19);
+        for (frame, pose) in poses.iter().enumerate() {
+            assert!((pose[0].translation[0] - 0.5 * frame as f32).abs() < 0.0001);
+        }
+
+        assert_eq!(make_clip(0, 10.0).iter_poses(20.0).count(), 0);
+        assert_eq!(clip.iter_poses(0.0).count(), 0);
+    }
+
+    #[test]
+    fn test_clips_are_send_and_sync() {
+
+        fn assert_send_sync<S: Send + Sync>() {}
+        assert_send_sync::<AnimationClip<QVTransform>>();
+        assert_send_sync::<AnimationClip<Matrix4<f32>>>();
+        assert_send_sync::<AnimationClip<DualQuaternion<f32>>>();
+
+        // Load on a worker thread and hand over to this one
+        let clip = ::std::thread::spawn(|| make_clip(10, 10.0)).join().unwrap();
+        assert_eq!(clip.samples.len(), 10);
+    }
+
+    #[test]
+    fn test_interpolated_local_pose() {
+
+        let mut clip = make_clip(10, 10.0);
+        for sample in clip.samples.iter_mut() {
+            let mut child_pose = QVTransform::identity();
+            child_pose.set_translation([0.0, 1.0, 0.0]);
+            sample.local_poses.push(child_pose);
+        }
+
+        let mut poses = [QVTransform::identity(); 2];
+        for &time in [0.0, 0.25, 0.95, 1.3].iter() {
+            clip.get_pose_at_time(time, &mut poses);
+            let pose = clip.interpolated_local_pose(0, time).unwrap();
+            assert!(vec3_len(vec3_sub(pose.translation, poses[0].translation)) < EPSILON);
+        }
+
+        assert!(clip.interpolated_local_pose(2, 0.5).is_none());
+        assert!(make_clip(0, 10.0).interpolated_local_pose(0, 0.5).is_none());
+
+        // Irregularly sampled
+        clip.simplify(0.001);
+        let pose = clip.interpolated_local_pose(0, 0.45).unwrap();
+        assert!((pose.translation[0] - 4.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_morph_weights() {
+
+        // The second track is short, with weights of 0 after its second sample
+        let mut clip = make_clip(4, 10.0);
+        clip.morph_weights = vec![
+            ("smile".to_string(), vec![0.0, 0.5, 1.0, 1.0]),
+            ("blink".to_string(), vec![1.0, 0.0]),
+        ];
+
+        let weights = clip.morph_weights_at_time(0.05);
+        assert!((weights[0] - 0.25).abs() < EPSILON && (weights[1] - 0.5).abs() < EPSILON);
+
+        // Looping back from the last sample towards the first
+        let weights = clip.morph_weights_at_time(0.35);
+        assert!((weights[0] - 0.5).abs() < EPSILON && (weights[1] - 0.5).abs() < EPSILON);
+        assert_eq!(make_clip(0, 10.0).morph_weights_at_time(0.5), Vec::<f32>::new());
+
+        // Weights are resampled along with the poses
+        let resampled = clip.resample(20.0);
+        assert_eq!(resampled.morph_weights[0].1.len(), 8);
+        assert!((resampled.morph_weights[0].1[1] - 0.25).abs() < EPSILON);
+
+        // Appending matches tracks by name, with weights of 0 where a clip lacks a target
+        let mut other = make_clip(2, 10.0);
+        other.morph_weights = vec![("frown".to_string(), vec![1.0, 1.0]), ("smile".to_string(), vec![0.5, 0.5])];
+        let mut appended = clip.clone();
+        appended.append(&other).unwrap();
+        assert_eq!(appended.morph_weights[0], ("smile".to_string(), vec![0.0, 0.5, 1.0, 1.0, 0.5, 0.5]));
+        assert_eq!(appended.morph_weights[1], ("blink".to_string(), vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0]));
+        assert_eq!(appended.morph_weights[2], ("frown".to_string(), vec![0.0, 0.0, 0.0, 0.0, 1.0, 1.0]));
+
+        // Simplifying keeps the samples the weights need, although the poses are linear
+        let mut simplified = clip.clone();
+        simplified.simplify(0.001);
+        assert_eq!(simplified.samples.len(), 4);
+        clip.morph_weights.truncate(1);
+        clip.simplify(0.001);
+        assert_eq!(clip.samples.len(), 3);
+        assert_eq!(clip.morph_weights[0].1, vec![0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_simplify() {
+
+        // Linear motion needs only its first and last samples
+        let mut clip = make_clip(10, 10.0);
+        clip.simplify(0.001);
+        assert_eq!(clip.samples.len(), 2);
+        assert_eq!(clip.sample_times, Some(vec![0.0, 0.9]));
+
+        let mut poses = [QVTransform::identity(); 1];
+        clip.get_pose_at_time(0.45, &mut poses);
+        assert!((poses[0].get_translation()[0] - 4.5).abs() < EPSILON);
+
+        // A change of direction is kept; a small wobble within the tolerance isn't
+        let mut clip = make_clip(10, 10.0);
+        for (sample_index, sample) in clip.samples.iter_mut().enumerate() {
+            let x = if sample_index <= 5 { sample_index as f32 } else { 10.0 - sample_index as f32 };
+            let wobble = if sample_index == 2 { 0.05 } else { 0.0 };
+            sample.local_poses[0].set_translation([x, wobble, 0.0]);
+        }
+        clip.simplify(0.1);
+        assert_eq!(clip.sample_times, Some(vec![0.0, 0.5, 0.9]));
+
+        clip.get_pose_at_time(0.7, &mut poses);
+        assert!((poses[0].get_translation()[0] - 3.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_ensure_quaternion_continuity() {
+
+        let rotation = quaternion::axis_angle([0.0, 0.0, 1.0], 0.5);
+        let negated_rotation = quaternion::scale(rotation, -1.0);
+
+        let mut clip = make_clip(4, 10.0);
+        for (i, sample) in clip.samples.iter_mut().enumerate() {
+            sample.local_poses[0].set_rotation(if i % 2 == 0 { rotation } else { negated_rotation });
+        }
+
+        clip.ensure_quaternion_continuity();
+        for (i, sample) in clip.samples.iter().enumerate() {
+            assert!((quaternion::dot(sample.local_poses[0].rotation, rotation) - 1.0).abs() < EPSILON);
+            assert!((sample.local_poses[0].translation[0] - i as f32).abs() < EPSILON);
+        }
+
+        // Matrices can't store the sign of their rotation, so are left unchanged
+        let mut scaled_matrix = quaternion_to_matrix(negated_rotation);
+        for row in scaled_matrix.iter_mut().take(3) {
+            for value in row.iter_mut().take(3) {
+                *value *= 2.0;
+            }
+        }
+        let mut matrix_clip = AnimationClipBuilder::new();
+        matrix_clip.push_sample(vec![quaternion_to_matrix(rotation)]);
+        matrix_clip.push_sample(vec![scaled_matrix]);
+        let mut matrix_clip = matrix_clip.build().unwrap();
+        let expected_matrix = matrix_clip.samples[1].local_poses[0];
+        matrix_clip.ensure_quaternion_continuity();
+        assert_eq!(matrix_clip.samples[1].local_poses[0], expected_matrix);
+    }
+
+    #[test]
+    fn test_clip_builder() {
+
+        let mut builder = AnimationClipBuilder::new().with_samples_per_second(10.0);
+        for i in 0 .. 5 {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([i as f32, 0.0, 0.0]);
+            builder.push_sample(vec![pose, QVTransform::identity()]);
+        }
+
+        let clip = builder.clone().build().unwrap();
+        assert_eq!(clip.samples.len(), 5);
+        assert!((clip.get_duration() - 0.5).abs() < EPSILON);
+
+        builder.push_sample(vec![QVTransform::identity()]);
+        assert!(builder.build().is_err());
+        assert!(AnimationClipBuilder::<QVTransform>::new().build().is_err());
+    }
+
+    #[test]
+    fn test_get_pose_at_time_clamped() {
+
+        let mut poses = [QVTransform::identity(); 1];
+
+        // At the duration, sampling stays on the final sample rather than wrapping to the first
+        let clip = make_clip(10, 10.0);
+        clip.get_pose_at_time_clamped(clip.get_duration(), &mut poses);
+        assert!((poses[0].translation[0] - 9.0).abs() < EPSILON);
+        clip.get_pose_at_time_clamped(0.85, &mut poses);
+        assert!((poses[0].translation[0] - 8.5).abs() < 0.0001);
+        clip.get_pose_at_time_clamped(-1.0, &mut poses);
+        assert!(poses[0].translation[0].abs() < EPSILON);
+
+        let single_sample_clip = make_clip(1, 10.0);
+        single_sample_clip.get_pose_at_time_clamped(single_sample_clip.get_duration(), &mut poses);
+        assert!(poses[0].translation[0].abs() < EPSILON);
+
+        // Empty clips leave the output untouched
+        let empty_clip = make_clip(0, 10.0);
+        poses[0].set_translation([2.0, 0.0, 0.0]);
+        empty_clip.get_pose_at_time(0.5, &mut poses);
+        empty_clip.get_pose_at_time_clamped(0.5, &mut poses);
+        assert!((poses[0].translation[0] - 2.0).abs() < EPSILON);
+
+        let mut irregular_clip = make_clip(4, 4.0);
+        irregular_clip.sample_times = Some(vec![0.0, 0.1, 0.5, 1.0]);
+        irregular_clip.get_pose_at_time_clamped(irregular_clip.get_duration(), &mut poses);
+        assert!((poses[0].translation[0] - 3.0).abs() < EPSILON);
+
+        let mut instance = ClipInstance::new(Shared::new(irregular_clip));
+        instance.set_loop_mode(LoopMode::Clamp);
+        instance.get_pose_at_time(5.0, &mut poses);
+        assert!((poses[0].translation[0] - 3.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_step_interpolation() {
+
+        let mut clip = make_clip(4, 10.0);
+        for sample in clip.samples.iter_mut() {
+            let pose = sample.local_poses[0];
+            sample.local_poses.push(pose);
+        }
+        clip.joint_interpolations = Some(vec![Interpolation::Linear, Interpolation::Step]);
+
+        let mut poses = [QVTransform::identity(); 2];
+        clip.get_pose_at_time(0.15, &mut poses);
+        assert!((poses[0].translation[0] - 1.5).abs() < 0.0001);
+        assert!((poses[1].translation[0] - 1.0).abs() < EPSILON);
+
+        // The held pose changes as soon as the next sample is reached
+        clip.get_pose_at_time_clamped(clip.get_last_sample_time(), &mut poses);
+        assert!((poses[1].translation[0] - 3.0).abs() < EPSILON);
+
+        // Irregularly-timed samples are held the same way
+        clip.sample_times = Some(vec![0.0, 0.1, 0.4, 0.5]);
+        assert!((clip.interpolated_local_pose(1, 0.35).unwrap().translation[0] - 1.0).abs() < EPSILON);
+        assert!((clip.interpolated_local_pose(0, 0.25).unwrap().translation[0] - 1.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_get_joint_interpolations() {
+
+        let path = env::temp_dir().join("skeletal_animation_test_interpolation.dae");
+        File::create(&path).unwrap().write_all(br##"<?xml version="1.0" encoding="utf-8"?>
+            <COLLADA xmlns="http://www.collada.org/2005/11/COLLADASchema" version="1.4.1">
+                <library_animations>
+                    <animation id="visibility">
+                        <source id="visibility-interpolation">
+                            <Name_array id="visibility-interpolation-array" count="2">STEP STEP</Name_array>
+                        </source>
+                        <sampler id="visibility-sampler">
+                            <input semantic="INTERPOLATION" source="#visibility-interpolation"/>
+                        </sampler>
+                        <channel source="#visibility-sampler" target="prop/transform"/>
+                    </animation>
+                    <animation id="arm">
+                        <source id="arm-interpolation">
+                            <Name_array id="arm-interpolation-array" count="2">STEP LINEAR</Name_array>
+                        </source>
+                        <sampler id="arm-sampler">
+                            <input semantic="INTERPOLATION" source="#arm-interpolation"/>
+                        </sampler>
+                        <channel source="#arm-sampler" target="arm/transform"/>
+                    </animation>
+                </library_animations>
+            </COLLADA>"##).unwrap();
+
+        let collada_document = ColladaDocument::from_path(&path).unwrap();
+        let skeleton = Skeleton::new(["arm", "prop", "spine"].iter().map(|name| {
+            Joint::new(name, None, mat4_id())
+        }).collect());
+
+        assert_eq!(
+            get_joint_interpolations(&collada_document, &skeleton),
+            Some(vec![Interpolation::Linear, Interpolation::Step, Interpolation::Linear])
+        );
+    }
+
+    #[test]
+    fn test_channel_masks() {
+
+        let mut clip = make_clip(10, 10.0);
+        let rotation = quaternion::axis_angle([0.0, 1.0, 0.0], 0.5);
+        for sample in clip.samples.iter_mut() {
+            sample.local_poses[0].set_rotation(rotation);
+        }
+        let mut unmasked_poses = [QVTransform::identity(); 1];
+        clip.get_pose_at_time(0.3, &mut unmasked_poses);
+
+        clip.channel_masks = Some(vec![ChannelMask { translation: false, rotation: true, scale: true }]);
+
+        let mut poses = [QVTransform::identity(); 1];
+        poses[0].set_translation([0.0, 5.0, 0.0]);
+        clip.get_pose_at_time(0.3, &mut poses);
+
+        // Translation is left as it was, while rotation is driven by the clip
+        assert!(vec3_len(vec3_sub(poses[0].get_translation(), [0.0, 5.0, 0.0])) < EPSILON);
+        assert!((poses[0].rotation.0 - unmasked_poses[0].rotation.0).abs() < EPSILON);
+        assert!(vec3_len(vec3_sub(poses[0].rotation.1, unmasked_poses[0].rotation.1)) < EPSILON);
+
+        // Masking scale keeps the output pose's scale instead of the clip's
+        for sample in clip.samples.iter_mut() {
+            sample.local_poses[0].set_scale(2.0);
+        }
+        clip.channel_masks = Some(vec![ChannelMask { translation: true, rotation: true, scale: false }]);
+        poses[0].set_scale(0.5);
+        clip.get_pose_at_time(0.3, &mut poses);
+        assert!((poses[0].scale - 0.5).abs() < EPSILON);
+
+        clip.channel_masks = Some(vec![ChannelMask::default()]);
+        clip.get_pose_at_time(0.3, &mut poses);
+        assert!((poses[0].scale - 2.0).abs() < EPSILON);
+
+        let mask: ChannelMask = serde_json::from_str(r#"{ "translation": false, "rotation": true }"#).unwrap();
+        assert_eq!(mask, ChannelMask { translation: false, rotation: true, scale: true });
+    }
 }
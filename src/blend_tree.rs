@@ -1,9 +1,13 @@
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::mem;
 
-use rustc_serialize::{Decodable, Decoder};
+use serde::{Deserialize, Serialize};
+use serde_json;
 
-use animation::{AnimationClip, ClipInstance};
+use Shared;
+use animation::{AnimationClip, AnimationSample, ClipInstance, LoopMode, blend_poses};
+use parameter::{ParamIndex, ParameterSet};
 use skeleton::{Skeleton, JointIndex};
 
 use transform::Transform;
@@ -15,100 +19,484 @@ pub type ClipId = String;
 /// Identifier for animation controller parameter, within a LerpNode
 pub type ParamId = String;
 
+/// The default limit on the depth of nested nodes in a blend tree, counting RefNodes and
+/// the definitions they reference, beyond which trees are rejected rather than risking a
+/// stack overflow
+pub const MAX_BLEND_TREE_DEPTH: usize = 128;
+
+/// Clip durations (in seconds) at or below which `synchronize` leaves playback rates at 1.0
+const SYNCHRONIZE_EPSILON: f32 = 0.00001;
+
+/// A parameter referenced by a runtime blend node, by its index in the ParameterSet the
+/// tree was built with, or None if it wasn't declared there
+type ParamSlot = Option<ParamIndex>;
+
 /// Definition of a blend tree, used by AnimationController to construct an AnimBlendTree
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum BlendTreeNodeDef {
-    LerpNode(Box<Self>, Box<Self>, ParamId),
-    AdditiveNode(Box<Self>, Box<Self>, ParamId),
-    IKNode(Box<Self>, String, ParamId, ParamId, ParamId, ParamId, ParamId, ParamId, ParamId),
-    ClipNode(ClipId),
+    LerpNode {
+        inputs: (Box<BlendTreeNodeDef>, Box<BlendTreeNodeDef>),
+        param: ParamId,
+
+        /// If given, clip inputs are sampled at this normalized phase (0 to 1) of their own
+        /// duration rather than at the controller time, keeping them in step across the blend
+        #[serde(skip_serializing_if = "Option::is_none")]
+        phase_param: Option<ParamId>,
+    },
+    AdditiveNode {
+        inputs: (Box<BlendTreeNodeDef>, Box<BlendTreeNodeDef>),
+        param: ParamId,
+    },
+    IKNode {
+        input: Box<BlendTreeNodeDef>,
+        effector: String,
+        blend_param: ParamId,
+        target_x_param: ParamId,
+        target_y_param: ParamId,
+        target_z_param: ParamId,
+        bend_x_param: ParamId,
+        bend_y_param: ParamId,
+        bend_z_param: ParamId,
+    },
+    SpeedNode {
+        input: Box<BlendTreeNodeDef>,
+
+        /// The parameter that the time is scaled by before evaluating the input
+        param: ParamId,
+    },
+    BlendSpace2D {
+        x_param: ParamId,
+        y_param: ParamId,
+        samples: Vec<BlendSpaceSampleDef>,
+    },
+    WeightedBlendNode {
+        inputs: Vec<WeightedInputDef>,
+    },
+    SelectNode {
+        inputs: Vec<BlendTreeNodeDef>,
+
+        /// The parameter whose value, rounded to the nearest index and clamped to the range
+        /// of inputs, selects the input
+        param: ParamId,
+    },
+    NoiseNode {
+        input: Box<BlendTreeNodeDef>,
+
+        /// The largest rotation added to each joint, in radians
+        amplitude: f32,
+
+        /// How quickly the noise varies, in cycles per second
+        frequency: f32,
+
+        /// Seed for the noise, so that each seed always gives the same variation
+        seed: u32,
+
+        /// If given, a scale for the amplitude per joint name. Joints that aren't
+        /// listed aren't perturbed, and names that aren't in the skeleton are ignored.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        joint_amplitudes: Option<HashMap<String, f32>>,
+    },
+    PoseNode {
+        /// The clip whose first sample is used as the pose, or the skeleton's bind pose if None
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pose_source: Option<ClipId>,
+    },
+    ClipNode {
+        clip_source: ClipId,
+
+        /// Playback behavior past the end of the clip, looping if unspecified
+        #[serde(rename = "loop", skip_serializing_if = "Option::is_none")]
+        loop_mode: Option<LoopMode>,
+
+        /// If given, the clip is sampled at this normalized phase (clamped to 0 to 1) of its
+        /// duration, ignoring the controller time, e.g. to tie it to a gameplay timer
+        #[serde(skip_serializing_if = "Option::is_none")]
+        phase_param: Option<ParamId>,
+    },
+    RefNode {
+        /// The name of the shared sub-tree definition this node stands in for
+        #[serde(rename = "ref")]
+        definition: DefinitionId,
+    },
+}
+
+/// Identifier for a named sub-tree definition, referenced by RefNodes
+pub type DefinitionId = String;
+
+/// An input to a BlendSpace2D node, placed at a point in the blend space
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlendSpaceSampleDef {
+    pub x: f32,
+    pub y: f32,
+    pub node: BlendTreeNodeDef,
+}
+
+/// An input to a WeightedBlendNode, weighted by the value of `param` relative to the
+/// other inputs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeightedInputDef {
+    pub node: BlendTreeNodeDef,
+    pub param: ParamId,
 }
 
-impl Decodable for BlendTreeNodeDef {
-    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, D::Error> {
-        decoder.read_struct("root", 0, |decoder| {
+/// A named blend tree definition, which can be deserialized from JSON
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlendTreeDef {
+    pub name: String,
+    pub blend_tree: BlendTreeNodeDef,
+}
 
-            let node_type = try!(decoder.read_struct_field("type", 0, |decoder| { Ok(try!(decoder.read_str())) }));
+/// A problem found when decoding, validating or building a BlendTreeNodeDef
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlendTreeError {
+    /// The JSON couldn't be parsed as a blend tree definition
+    Parse(String),
 
-            match &node_type[..] {
-                "LerpNode" => {
+    /// A ClipNode references a clip that isn't available
+    MissingClip(ClipId),
 
-                    let (input_1, input_2) = try!(decoder.read_struct_field("inputs", 0, |decoder| {
-                        decoder.read_seq(|decoder, _len| {
-                            Ok((
-                                try!(decoder.read_seq_elt(0, Decodable::decode)),
-                                try!(decoder.read_seq_elt(1, Decodable::decode))
-                            ))
-                        })
-                    }));
+    /// A node references a parameter that isn't declared
+    UnknownParam(ParamId),
 
-                    let blend_param_name = try!(decoder.read_struct_field("param", 0, |decoder| { Ok(try!(decoder.read_str())) }));
+    /// A RefNode references a sub-tree definition that isn't available
+    MissingDefinition(DefinitionId),
 
-                    Ok(BlendTreeNodeDef::LerpNode(Box::new(input_1), Box::new(input_2), blend_param_name))
+    /// A sub-tree definition references itself, directly or through other definitions
+    RecursiveDefinition(DefinitionId),
 
-                },
-                "AdditiveNode" => {
+    /// Nodes are nested deeper than the depth limit
+    TooDeep,
 
-                    let (input_1, input_2) = try!(decoder.read_struct_field("inputs", 0, |decoder| {
-                        decoder.read_seq(|decoder, _len| {
-                            Ok((
-                                try!(decoder.read_seq_elt(0, Decodable::decode)),
-                                try!(decoder.read_seq_elt(1, Decodable::decode))
-                            ))
-                        })
-                    }));
+    /// An IKNode's effector isn't a joint of the skeleton
+    MissingJoint { name: String },
+}
 
-                    let blend_param_name = try!(decoder.read_struct_field("param", 0, |decoder| { Ok(try!(decoder.read_str())) }));
+/// What a BlendTreeNodeDef is validated against, the definitions being followed, those
+/// already validated with the depth of each, the depth of the node being validated, and
+/// the deepest depth reached
+struct ValidationContext<'a, C: 'a> {
+    definitions: &'a HashMap<DefinitionId, BlendTreeNodeDef>,
+    available_clips: &'a HashMap<ClipId, C>,
+    available_params: &'a HashSet<ParamId>,
+    resolving: Vec<DefinitionId>,
+    validated: HashMap<DefinitionId, usize>,
+    depth: usize,
+    deepest: usize,
+}
 
-                    Ok(BlendTreeNodeDef::AdditiveNode(Box::new(input_1), Box::new(input_2), blend_param_name))
+impl BlendTreeNodeDef {
 
-                },
-                "IKNode" => {
+    /// Parse a blend tree definition from JSON, e.g. for content fetched over the network,
+    /// rejecting trees deeper than `MAX_BLEND_TREE_DEPTH`
+    pub fn from_json_str(json: &str) -> Result<Self, BlendTreeError> {
+        Self::from_json_str_with_max_depth(json, MAX_BLEND_TREE_DEPTH)
+    }
 
-                    let input = try!(decoder.read_struct_field("input", 0, Decodable::decode));
+    /// Like `from_json_str`, but with the given limit on the depth of nested nodes, e.g.
+    /// when loading untrusted content. RefNodes count as a single node, as their
+    /// definitions aren't available here. Returns `BlendTreeError::TooDeep` if the tree is
+    /// any deeper, or `BlendTreeError::Parse` if the JSON is invalid, including JSON nested
+    /// past serde_json's own recursion limit.
+    pub fn from_json_str_with_max_depth(json: &str, max_depth: usize) -> Result<Self, BlendTreeError> {
+        let def: Self = serde_json::from_str(json).map_err(|error| BlendTreeError::Parse(error.to_string()))?;
+        if def.is_deeper_than(0, max_depth) {
+            return Err(BlendTreeError::TooDeep);
+        }
+        Ok(def)
+    }
 
-                    let effector_name = try!(decoder.read_struct_field("effector", 0, |decoder| { Ok(try!(decoder.read_str())) }));
+    /// Whether this node, at `depth` nodes below the root, or any node under it lies
+    /// deeper than `max_depth`
+    fn is_deeper_than(&self, depth: usize, max_depth: usize) -> bool {
+        depth >= max_depth || self.inputs().iter().any(|input| input.is_deeper_than(depth + 1, max_depth))
+    }
 
-                    let blend_param_name = try!(decoder.read_struct_field("blend_param", 0, |decoder| { Ok(try!(decoder.read_str())) }));
+    /// The direct inputs of this node, not following RefNodes
+    fn inputs(&self) -> Vec<&BlendTreeNodeDef> {
+        match *self {
+            BlendTreeNodeDef::LerpNode { inputs: (ref input_1, ref input_2), .. }
+            | BlendTreeNodeDef::AdditiveNode { inputs: (ref input_1, ref input_2), .. } => vec![&**input_1, &**input_2],
+            BlendTreeNodeDef::IKNode { ref input, .. }
+            | BlendTreeNodeDef::SpeedNode { ref input, .. }
+            | BlendTreeNodeDef::NoiseNode { ref input, .. } => vec![&**input],
+            BlendTreeNodeDef::SelectNode { ref inputs, .. } => inputs.iter().collect(),
+            BlendTreeNodeDef::BlendSpace2D { ref samples, .. } => samples.iter().map(|sample| &sample.node).collect(),
+            BlendTreeNodeDef::WeightedBlendNode { ref inputs } => inputs.iter().map(|input| &input.node).collect(),
+            BlendTreeNodeDef::PoseNode { .. }
+            | BlendTreeNodeDef::ClipNode { .. }
+            | BlendTreeNodeDef::RefNode { .. } => Vec::new(),
+        }
+    }
 
-                    let target_x_name = try!(decoder.read_struct_field("target_x_param", 0, |decoder| { Ok(try!(decoder.read_str())) }));
-                    let target_y_name = try!(decoder.read_struct_field("target_y_param", 0, |decoder| { Ok(try!(decoder.read_str())) }));
-                    let target_z_name = try!(decoder.read_struct_field("target_z_param", 0, |decoder| { Ok(try!(decoder.read_str())) }));
+    /// Check that every clip and parameter referenced by this tree is available, and that
+    /// it's no deeper than `MAX_BLEND_TREE_DEPTH`, reporting all problems found rather
+    /// than stopping at the first
+    ///
+    /// # Arguments
+    ///
+    /// * `available_clips` - A mapping from ClipIds to the clips that can be referenced
+    /// * `available_params` - The set of ParamIds that can be referenced
+    pub fn validate<C>(
+        &self,
+        available_clips: &HashMap<ClipId, C>,
+        available_params: &HashSet<ParamId>,
+    ) -> Result<(), Vec<BlendTreeError>> {
+        self.validate_with_definitions(&HashMap::new(), available_clips, available_params)
+    }
 
-                    let bend_x_name = try!(decoder.read_struct_field("bend_x_param", 0, |decoder| { Ok(try!(decoder.read_str())) }));
-                    let bend_y_name = try!(decoder.read_struct_field("bend_y_param", 0, |decoder| { Ok(try!(decoder.read_str())) }));
-                    let bend_z_name = try!(decoder.read_struct_field("bend_z_param", 0, |decoder| { Ok(try!(decoder.read_str())) }));
+    /// Like `validate`, but also following RefNodes into the given sub-tree definitions,
+    /// and reporting any that are missing or recursive
+    pub fn validate_with_definitions<C>(
+        &self,
+        definitions: &HashMap<DefinitionId, BlendTreeNodeDef>,
+        available_clips: &HashMap<ClipId, C>,
+        available_params: &HashSet<ParamId>,
+    ) -> Result<(), Vec<BlendTreeError>> {
+        let mut errors = Vec::new();
+        let mut context = ValidationContext {
+            definitions,
+            available_clips,
+            available_params,
+            resolving: Vec::new(),
+            validated: HashMap::new(),
+            depth: 0,
+            deepest: 0,
+        };
+        self.collect_errors(&mut context, &mut errors);
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
 
+    fn collect_errors<C>(
+        &self,
+        context: &mut ValidationContext<C>,
+        errors: &mut Vec<BlendTreeError>,
+    ) {
+        if context.depth >= MAX_BLEND_TREE_DEPTH {
+            if !errors.contains(&BlendTreeError::TooDeep) {
+                errors.push(BlendTreeError::TooDeep);
+            }
+            return;
+        }
 
-                    Ok(BlendTreeNodeDef::IKNode(Box::new(input),
-                                                effector_name,
-                                                blend_param_name,
-                                                target_x_name,
-                                                target_y_name,
-                                                target_z_name,
-                                                bend_x_name,
-                                                bend_y_name,
-                                                bend_z_name))
+        context.depth += 1;
+        context.deepest = context.deepest.max(context.depth);
+        self.collect_node_errors(context, errors);
+        context.depth -= 1;
+    }
 
-                },
-                "ClipNode" => {
-                    let clip_source = try!(decoder.read_struct_field("clip_source", 0, |decoder| { Ok(try!(decoder.read_str())) }));
-                    Ok(BlendTreeNodeDef::ClipNode(clip_source))
+    fn collect_node_errors<C>(
+        &self,
+        context: &mut ValidationContext<C>,
+        errors: &mut Vec<BlendTreeError>,
+    ) {
+        let available_params = context.available_params;
+        let add_error = |error: BlendTreeError, errors: &mut Vec<BlendTreeError>| {
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+        };
+        let check_param = |param: &ParamId, errors: &mut Vec<BlendTreeError>| {
+            if !available_params.contains(param) {
+                add_error(BlendTreeError::UnknownParam(param.clone()), errors);
+            }
+        };
+        let available_clips = context.available_clips;
+        let check_clip = |clip_id: &ClipId, errors: &mut Vec<BlendTreeError>| {
+            if !available_clips.contains_key(clip_id) {
+                add_error(BlendTreeError::MissingClip(clip_id.clone()), errors);
+            }
+        };
+
+        match *self {
+            BlendTreeNodeDef::LerpNode { inputs: (ref input_1, ref input_2), ref param, ref phase_param } => {
+                input_1.collect_errors(context, errors);
+                input_2.collect_errors(context, errors);
+                check_param(param, errors);
+                if let Some(ref phase_param) = *phase_param {
+                    check_param(phase_param, errors);
                 }
-                _ => panic!("Unexpected blend node type")
             }
-        })
+            BlendTreeNodeDef::AdditiveNode { inputs: (ref input_1, ref input_2), ref param } => {
+                input_1.collect_errors(context, errors);
+                input_2.collect_errors(context, errors);
+                check_param(param, errors);
+            }
+            BlendTreeNodeDef::IKNode { ref input, ref blend_param, ref target_x_param, ref target_y_param, ref target_z_param, ref bend_x_param, ref bend_y_param, ref bend_z_param, .. } => {
+                input.collect_errors(context, errors);
+                for param in [blend_param, target_x_param, target_y_param, target_z_param, bend_x_param, bend_y_param, bend_z_param].iter() {
+                    check_param(param, errors);
+                }
+            }
+            BlendTreeNodeDef::SpeedNode { ref input, ref param } => {
+                input.collect_errors(context, errors);
+                check_param(param, errors);
+            }
+            BlendTreeNodeDef::SelectNode { ref inputs, ref param } => {
+                for input in inputs.iter() {
+                    input.collect_errors(context, errors);
+                }
+                check_param(param, errors);
+            }
+            BlendTreeNodeDef::NoiseNode { ref input, .. } => {
+                input.collect_errors(context, errors);
+            }
+            BlendTreeNodeDef::BlendSpace2D { ref x_param, ref y_param, ref samples } => {
+                for sample in samples.iter() {
+                    sample.node.collect_errors(context, errors);
+                }
+                check_param(x_param, errors);
+                check_param(y_param, errors);
+            }
+            BlendTreeNodeDef::WeightedBlendNode { ref inputs } => {
+                for input in inputs.iter() {
+                    input.node.collect_errors(context, errors);
+                    check_param(&input.param, errors);
+                }
+            }
+            BlendTreeNodeDef::PoseNode { pose_source: Some(ref clip_id) } => {
+                check_clip(clip_id, errors);
+            }
+            BlendTreeNodeDef::ClipNode { ref clip_source, ref phase_param, .. } => {
+                check_clip(clip_source, errors);
+                if let Some(ref phase_param) = *phase_param {
+                    check_param(phase_param, errors);
+                }
+            }
+            BlendTreeNodeDef::PoseNode { pose_source: None } => {}
+            BlendTreeNodeDef::RefNode { ref definition } => {
+                // Each definition is only walked once, with later references just checking
+                // its depth, so definitions referencing each other repeatedly stay cheap
+                if let Some(&height) = context.validated.get(definition) {
+                    if context.depth + height > MAX_BLEND_TREE_DEPTH {
+                        add_error(BlendTreeError::TooDeep, errors);
+                    }
+                    context.deepest = context.deepest.max(context.depth + height);
+                } else if context.resolving.contains(definition) {
+                    add_error(BlendTreeError::RecursiveDefinition(definition.clone()), errors);
+                } else if let Some(node) = context.definitions.get(definition) {
+                    context.resolving.push(definition.clone());
+                    let outer_deepest = mem::replace(&mut context.deepest, context.depth);
+                    node.collect_errors(context, errors);
+                    let height = context.deepest - context.depth;
+                    context.deepest = context.deepest.max(outer_deepest);
+                    context.resolving.pop();
+                    context.validated.insert(definition.clone(), height);
+                } else {
+                    add_error(BlendTreeError::MissingDefinition(definition.clone()), errors);
+                }
+            }
+        }
+    }
+
+    /// Return the ParamIds of every parameter referenced anywhere in this tree, down to
+    /// `MAX_BLEND_TREE_DEPTH`. RefNodes aren't followed into their definitions.
+    pub fn referenced_params(&self) -> HashSet<ParamId> {
+        let mut params = HashSet::new();
+        self.collect_references(0, &mut HashSet::new(), &mut params);
+        params
+    }
+
+    /// Return the ClipIds of every clip referenced anywhere in this tree, down to
+    /// `MAX_BLEND_TREE_DEPTH`. RefNodes aren't followed into their definitions.
+    pub fn referenced_clips(&self) -> HashSet<ClipId> {
+        let mut clips = HashSet::new();
+        self.collect_references(0, &mut clips, &mut HashSet::new());
+        clips
+    }
+
+    fn collect_references(&self, depth: usize, clips: &mut HashSet<ClipId>, params: &mut HashSet<ParamId>) {
+        if depth >= MAX_BLEND_TREE_DEPTH {
+            return;
+        }
+        match *self {
+            BlendTreeNodeDef::LerpNode { inputs: (ref input_1, ref input_2), ref param, ref phase_param } => {
+                input_1.collect_references(depth + 1, clips, params);
+                input_2.collect_references(depth + 1, clips, params);
+                params.insert(param.clone());
+                if let Some(ref phase_param) = *phase_param {
+                    params.insert(phase_param.clone());
+                }
+            }
+            BlendTreeNodeDef::AdditiveNode { inputs: (ref input_1, ref input_2), ref param } => {
+                input_1.collect_references(depth + 1, clips, params);
+                input_2.collect_references(depth + 1, clips, params);
+                params.insert(param.clone());
+            }
+            BlendTreeNodeDef::IKNode { ref input, ref blend_param, ref target_x_param, ref target_y_param, ref target_z_param, ref bend_x_param, ref bend_y_param, ref bend_z_param, .. } => {
+                input.collect_references(depth + 1, clips, params);
+                for param in [blend_param, target_x_param, target_y_param, target_z_param, bend_x_param, bend_y_param, bend_z_param].iter() {
+                    params.insert((*param).clone());
+                }
+            }
+            BlendTreeNodeDef::SpeedNode { ref input, ref param } => {
+                input.collect_references(depth + 1, clips, params);
+                params.insert(param.clone());
+            }
+            BlendTreeNodeDef::SelectNode { ref inputs, ref param } => {
+                for input in inputs.iter() {
+                    input.collect_references(depth + 1, clips, params);
+                }
+                params.insert(param.clone());
+            }
+            BlendTreeNodeDef::NoiseNode { ref input, .. } => {
+                input.collect_references(depth + 1, clips, params);
+            }
+            BlendTreeNodeDef::BlendSpace2D { ref x_param, ref y_param, ref samples } => {
+                for sample in samples.iter() {
+                    sample.node.collect_references(depth + 1, clips, params);
+                }
+                params.insert(x_param.clone());
+                params.insert(y_param.clone());
+            }
+            BlendTreeNodeDef::WeightedBlendNode { ref inputs } => {
+                for input in inputs.iter() {
+                    input.node.collect_references(depth + 1, clips, params);
+                    params.insert(input.param.clone());
+                }
+            }
+            BlendTreeNodeDef::PoseNode { pose_source: Some(ref clip_id) } => {
+                clips.insert(clip_id.clone());
+            }
+            BlendTreeNodeDef::ClipNode { ref clip_source, ref phase_param, .. } => {
+                clips.insert(clip_source.clone());
+                if let Some(ref phase_param) = *phase_param {
+                    params.insert(phase_param.clone());
+                }
+            }
+            BlendTreeNodeDef::PoseNode { pose_source: None }
+            | BlendTreeNodeDef::RefNode { .. } => {}
+        }
     }
 }
 
+/// The sub-tree definitions available to RefNodes while building an AnimBlendTree, the
+/// nodes already built for them with the depth of each built sub-tree, the depth of the
+/// node being built, and the deepest depth reached
+struct SharedDefinitions<'a> {
+    definitions: &'a HashMap<DefinitionId, BlendTreeNodeDef>,
+    handles: HashMap<DefinitionId, (AnimNodeHandle, usize)>,
+    resolving: Vec<DefinitionId>,
+    depth: usize,
+    deepest: usize,
+    max_depth: usize,
+}
+
 /// A tree of AnimNodes
 pub struct AnimBlendTree<T: Transform> {
     root_node: AnimNodeHandle,
     lerp_nodes: Vec<LerpAnimNode>,
     additive_nodes: Vec<AdditiveAnimNode>,
     ik_nodes: Vec<IKNode>,
+    speed_nodes: Vec<SpeedAnimNode>,
     clip_nodes: Vec<ClipAnimNode<T>>,
-    skeleton: Rc<Skeleton>,
+    pose_nodes: Vec<PoseAnimNode<T>>,
+    blend_space_nodes: Vec<BlendSpace2DAnimNode>,
+    weighted_blend_nodes: Vec<WeightedBlendAnimNode>,
+    noise_nodes: Vec<NoiseAnimNode>,
+    select_nodes: Vec<SelectAnimNode>,
+    skeleton: Shared<Skeleton>,
+    missing_param_value: f32,
 }
 
 impl<T: Transform> AnimBlendTree<T> {
@@ -120,36 +508,156 @@ impl<T: Transform> AnimBlendTree<T> {
     ///
     /// * `def` - The root BlendTreeNodeDef
     /// * `animations` - A mapping from ClipIds to shared AnimationClip instances
+    /// * `params` - The ParameterSet the tree will be evaluated with. Parameters referenced
+    ///              by the tree are resolved to their indices here; any that aren't declared
+    ///              are treated as missing.
+    ///
+    /// Returns the error from `from_def_with_max_depth` if the tree can't be built.
     pub fn from_def(
         def: BlendTreeNodeDef,
-        animations: &HashMap<ClipId, Rc<AnimationClip<T>>>,
-        skeleton: Rc<Skeleton>,
-    ) -> Self {
+        animations: &HashMap<ClipId, Shared<AnimationClip<T>>>,
+        skeleton: Shared<Skeleton>,
+        params: &ParameterSet,
+    ) -> Result<Self, BlendTreeError> {
+        Self::from_def_with_definitions(def, &HashMap::new(), animations, skeleton, params)
+    }
+
+    /// Like `from_def`, but resolving RefNodes to the named sub-tree definitions. Each
+    /// definition is built once, and every RefNode to it shares the resulting nodes.
+    ///
+    /// Returns the error from `from_def_with_max_depth` if a clip is missing, if a RefNode
+    /// references a definition that's missing, or that references itself, or if the tree is
+    /// deeper than `MAX_BLEND_TREE_DEPTH`; see `BlendTreeNodeDef::validate_with_definitions`.
+    pub fn from_def_with_definitions(
+        def: BlendTreeNodeDef,
+        definitions: &HashMap<DefinitionId, BlendTreeNodeDef>,
+        animations: &HashMap<ClipId, Shared<AnimationClip<T>>>,
+        skeleton: Shared<Skeleton>,
+        params: &ParameterSet,
+    ) -> Result<Self, BlendTreeError> {
+        Self::from_def_with_max_depth(def, definitions, animations, skeleton, params, MAX_BLEND_TREE_DEPTH)
+    }
+
+    /// Like `from_def_with_definitions`, but with the given limit on the depth of nested
+    /// nodes, counting RefNodes and the definitions they reference, e.g. when loading
+    /// untrusted content. Returns `BlendTreeError::TooDeep` if the tree is any deeper, and
+    /// `MissingClip`, `MissingDefinition`, `RecursiveDefinition` or `MissingJoint` for the
+    /// first missing clip, bad definition or missing IK effector found.
+    pub fn from_def_with_max_depth(
+        def: BlendTreeNodeDef,
+        definitions: &HashMap<DefinitionId, BlendTreeNodeDef>,
+        animations: &HashMap<ClipId, Shared<AnimationClip<T>>>,
+        skeleton: Shared<Skeleton>,
+        params: &ParameterSet,
+        max_depth: usize,
+    ) -> Result<Self, BlendTreeError> {
 
         let mut tree = Self {
             root_node: AnimNodeHandle::None,
             lerp_nodes: Vec::new(),
             additive_nodes: Vec::new(),
             ik_nodes: Vec::new(),
+            speed_nodes: Vec::new(),
             clip_nodes: Vec::new(),
-            skeleton: skeleton.clone()
+            pose_nodes: Vec::new(),
+            blend_space_nodes: Vec::new(),
+            weighted_blend_nodes: Vec::new(),
+            noise_nodes: Vec::new(),
+            select_nodes: Vec::new(),
+            skeleton: skeleton.clone(),
+            missing_param_value: 0.0,
         };
 
-        tree.root_node = tree.add_node(def, animations, &skeleton);
-        tree
+        let mut shared = SharedDefinitions { definitions, handles: HashMap::new(), resolving: Vec::new(), depth: 0, deepest: 0, max_depth };
+        tree.root_node = tree.add_node(def, animations, &skeleton, params, &mut shared)?;
+        Ok(tree)
+    }
+
+    /// Set the value used for any parameter referenced by the tree that wasn't declared
+    /// in the ParameterSet it was built with. Defaults to 0.0.
+    pub fn set_missing_param_value(&mut self, value: f32) {
+        self.missing_param_value = value;
     }
 
-    /// Get the output skeletal pose from the blend tree for the given time and parameters
+    /// Return the value of the given parameter, or the missing parameter value if it isn't set
+    fn get_param(&self, params: &ParameterSet, param: ParamSlot) -> f32 {
+        param.and_then(|index| params.get_by_index(index)).unwrap_or(self.missing_param_value)
+    }
+
+    /// Get the output skeletal pose from the blend tree for the given time and parameters,
+    /// as for `get_output_pose_with_buffers`, with scratch poses allocated for this call
     ///
     /// # Arguments
     ///
     /// * `time` - The time to sample from any AnimationClips
-    /// * `params` - A mapping from ParamIds to their current parameter values
+    /// * `params` - The current parameter values
     /// * `output_poses` - The output array slice of joint transforms that will be populated
     ///                    according to the defined output for this BlendTreeNode
-    pub fn get_output_pose(&self, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
-        if let Some(ref node) = self.get_node(self.root_node.clone()) {
-            node.get_output_pose(self, time, params, output_poses);
+    pub fn get_output_pose(&self, time: f32, params: &ParameterSet, output_poses: &mut [T]) {
+        self.get_output_pose_with_buffers(time, params, &mut PoseBuffers::new(), output_poses);
+    }
+
+    /// Get the output skeletal pose from the blend tree for the given time and parameters,
+    /// borrowing the scratch poses each node needs from `buffers`. Reusing the same buffers
+    /// for every evaluation avoids allocating once they've grown to the depth of the tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - The time to sample from any AnimationClips
+    /// * `params` - The current parameter values
+    /// * `buffers` - The pool of scratch poses to borrow from
+    /// * `output_poses` - The output array slice of joint transforms that will be populated
+    ///                    according to the defined output for this BlendTreeNode
+    pub fn get_output_pose_with_buffers(&self, time: f32, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+
+        // Output slices may be longer than the skeleton (e.g. fixed-size buffers), but not shorter
+        debug_assert!(
+            output_poses.len() >= self.skeleton.joints.len(),
+            "Output poses ({}) are fewer than the skeleton's joints ({})",
+            output_poses.len(), self.skeleton.joints.len()
+        );
+
+        if let Some(node) = self.get_node(self.root_node.clone()) {
+            node.get_output_pose(self, time, params, buffers, output_poses);
+        }
+    }
+
+    /// Evaluate the tree at fixed intervals with fixed parameter values, storing the output
+    /// poses as a plain AnimationClip that is much cheaper to sample
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - The parameter values to bake with
+    /// * `samples_per_second` - The rate at which to sample the tree
+    /// * `duration` - The length of time to sample, in seconds, starting from time 0.0
+    pub fn bake(&self, params: &ParameterSet, samples_per_second: f32, duration: f32) -> AnimationClip<T> {
+
+        let joint_count = self.skeleton.joints.len();
+        let sample_count = ((duration * samples_per_second).round() as usize).max(1);
+
+        let mut buffers = PoseBuffers::new();
+        let samples = (0 .. sample_count).map(|sample_index| {
+            let mut local_poses = vec![T::identity(); joint_count];
+            self.get_output_pose_with_buffers(sample_index as f32 / samples_per_second, params, &mut buffers, &mut local_poses[..]);
+            AnimationSample { local_poses }
+        }).collect();
+
+        AnimationClip {
+            samples,
+            samples_per_second: if duration > 0.0 { sample_count as f32 / duration } else { samples_per_second },
+            sample_times: None,
+            channel_masks: None,
+            joint_interpolations: None,
+            events: Vec::new(),
+            morph_weights: Vec::new(),
+        }
+    }
+
+    /// Rewind every clip in the tree to play from its start at a global time of 0.0, at its
+    /// original speed, undoing any playback rate changes from `synchronize`
+    pub fn reset(&mut self) {
+        for clip_node in self.clip_nodes.iter_mut() {
+            clip_node.clip.reset();
         }
     }
 
@@ -158,33 +666,25 @@ impl<T: Transform> AnimBlendTree<T> {
     /// # Arguments
     ///
     /// * `global_time` - The current global clock time from the controller
-    /// * `params` - A mapping from ParamIds to their current parameter values
-    pub fn synchronize(&mut self, global_time: f32, params: &HashMap<String, f32>) {
-        for lerp_node in self.lerp_nodes.iter() {
+    /// * `params` - The current parameter values
+    pub fn synchronize(&mut self, global_time: f32, params: &ParameterSet) {
+        for lerp_node in self.lerp_nodes.iter().filter(|lerp_node| lerp_node.phase_param.is_none()) {
             if let (AnimNodeHandle::ClipAnimNodeHandle(clip_1), AnimNodeHandle::ClipAnimNodeHandle(clip_2)) = (lerp_node.input_1.clone(), lerp_node.input_2.clone()) {
-                let blend_parameter = params[&lerp_node.blend_param[..]];
-
-                let target_length = {
-                    let clip_1 = &self.clip_nodes[clip_1].clip;
-                    let clip_2 = &self.clip_nodes[clip_2].clip;
+                let blend_parameter = self.get_param(params, lerp_node.blend_param);
 
-                    let length_1 = clip_1.get_duration();
-                    let length_2 = clip_2.get_duration();
+                let (length_1, length_2) = (self.clip_nodes[clip_1].clip.get_duration(), self.clip_nodes[clip_2].clip.get_duration());
 
-                    (1.0 - blend_parameter) * length_1 + blend_parameter * length_2
+                // Clips without any duration can't be stretched to match, so play both at
+                // their original speed rather than at an infinite or zero rate
+                let (rate_1, rate_2) = if length_1 <= SYNCHRONIZE_EPSILON || length_2 <= SYNCHRONIZE_EPSILON {
+                    (1.0, 1.0)
+                } else {
+                    let target_length = (1.0 - blend_parameter) * length_1 + blend_parameter * length_2;
+                    (length_1 / target_length, length_2 / target_length)
                 };
 
-                {
-                    let clip_1 = &mut self.clip_nodes[clip_1].clip;
-                    let length = clip_1.get_duration();
-                    clip_1.set_playback_rate(global_time, length / target_length);
-                }
-
-                {
-                    let clip_2 = &mut self.clip_nodes[clip_2].clip;
-                    let length = clip_2.get_duration();
-                    clip_2.set_playback_rate(global_time, length / target_length);
-                }
+                self.clip_nodes[clip_1].clip.set_playback_rate(global_time, rate_1);
+                self.clip_nodes[clip_2].clip.set_playback_rate(global_time, rate_2);
             }
         }
     }
@@ -192,69 +692,466 @@ impl<T: Transform> AnimBlendTree<T> {
     fn add_node(
         &mut self,
         def: BlendTreeNodeDef,
-        animations: &HashMap<ClipId, Rc<AnimationClip<T>>>,
-        skeleton: &Skeleton
-    ) -> AnimNodeHandle {
-        match def {
-            BlendTreeNodeDef::LerpNode(input_1, input_2, param_id) => {
-                let input_1_handle = self.add_node(*input_1, animations, skeleton);
-                let input_2_handle = self.add_node(*input_2, animations, skeleton);
+        animations: &HashMap<ClipId, Shared<AnimationClip<T>>>,
+        skeleton: &Skeleton,
+        params: &ParameterSet,
+        shared: &mut SharedDefinitions,
+    ) -> Result<AnimNodeHandle, BlendTreeError> {
+        if shared.depth >= shared.max_depth {
+            return Err(BlendTreeError::TooDeep);
+        }
+
+        shared.depth += 1;
+        shared.deepest = shared.deepest.max(shared.depth);
+        let handle = self.build_node(def, animations, skeleton, params, shared);
+        shared.depth -= 1;
+        handle
+    }
+
+    fn build_node(
+        &mut self,
+        def: BlendTreeNodeDef,
+        animations: &HashMap<ClipId, Shared<AnimationClip<T>>>,
+        skeleton: &Skeleton,
+        params: &ParameterSet,
+        shared: &mut SharedDefinitions,
+    ) -> Result<AnimNodeHandle, BlendTreeError> {
+        let slot = |param: &str| params.index_of(param);
+        Ok(match def {
+            BlendTreeNodeDef::LerpNode { inputs: (input_1, input_2), param: param_id, phase_param } => {
+                let input_1_handle = self.add_node(*input_1, animations, skeleton, params, shared)?;
+                let input_2_handle = self.add_node(*input_2, animations, skeleton, params, shared)?;
                 self.lerp_nodes.push(LerpAnimNode {
                     input_1: input_1_handle,
                     input_2: input_2_handle,
-                    blend_param: param_id.clone()
+                    blend_param: slot(&param_id),
+                    phase_param: phase_param.map(|phase_param| slot(&phase_param)),
                 });
                 AnimNodeHandle::LerpAnimNodeHandle(self.lerp_nodes.len() - 1)
             }
-            BlendTreeNodeDef::AdditiveNode(input_1, input_2, param_id) => {
-                let input_1_handle = self.add_node(*input_1, animations, skeleton);
-                let input_2_handle = self.add_node(*input_2, animations, skeleton);
+            BlendTreeNodeDef::AdditiveNode { inputs: (input_1, input_2), param: param_id } => {
+                let input_1_handle = self.add_node(*input_1, animations, skeleton, params, shared)?;
+                let input_2_handle = self.add_node(*input_2, animations, skeleton, params, shared)?;
                 self.additive_nodes.push(AdditiveAnimNode {
                     base_input: input_1_handle,
                     additive_input: input_2_handle,
-                    blend_param: param_id.clone()
+                    blend_param: slot(&param_id),
                 });
                 AnimNodeHandle::AdditiveAnimNodeHandle(self.additive_nodes.len() - 1)
             }
-            BlendTreeNodeDef::IKNode(input, effector_name, blend_param, target_x_param, target_y_param, target_z_param, bend_x_param, bend_y_param, bend_z_param) => {
-                let input_handle = self.add_node(*input, animations, skeleton);
+            BlendTreeNodeDef::IKNode { input, effector: effector_name, blend_param, target_x_param, target_y_param, target_z_param, bend_x_param, bend_y_param, bend_z_param } => {
+                let input_handle = self.add_node(*input, animations, skeleton, params, shared)?;
                 self.ik_nodes.push(IKNode {
                     input: input_handle,
-                    blend_param: blend_param.clone(),
-                    target_x_param: target_x_param.clone(),
-                    target_y_param: target_y_param.clone(),
-                    target_z_param: target_z_param.clone(),
-                    bend_x_param: bend_x_param.clone(),
-                    bend_y_param: bend_y_param.clone(),
-                    bend_z_param: bend_z_param.clone(),
-                    effector_bone_index: skeleton.get_joint_index(&effector_name).unwrap(),
-
+                    blend_param: slot(&blend_param),
+                    target_x_param: slot(&target_x_param),
+                    target_y_param: slot(&target_y_param),
+                    target_z_param: slot(&target_z_param),
+                    bend_x_param: slot(&bend_x_param),
+                    bend_y_param: slot(&bend_y_param),
+                    bend_z_param: slot(&bend_z_param),
+                    effector_bone_index: skeleton.get_joint_index(&effector_name)
+                        .ok_or_else(|| BlendTreeError::MissingJoint { name: effector_name.clone() })?,
                 });
                 AnimNodeHandle::IKAnimNodeHandle(self.ik_nodes.len() - 1)
             }
-            BlendTreeNodeDef::ClipNode(clip_id) => {
-                let clip = animations.get(&clip_id[..]).expect(&format!("Missing animation clip: {}", clip_id)[..]);
+            BlendTreeNodeDef::SpeedNode { input, param } => {
+                let input_handle = self.add_node(*input, animations, skeleton, params, shared)?;
+                self.speed_nodes.push(SpeedAnimNode {
+                    input: input_handle,
+                    speed_param: slot(&param),
+                });
+                AnimNodeHandle::SpeedAnimNodeHandle(self.speed_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::SelectNode { inputs, param } => {
+                let inputs = inputs.into_iter().map(|input| self.add_node(input, animations, skeleton, params, shared)).collect::<Result<_, _>>()?;
+                self.select_nodes.push(SelectAnimNode {
+                    inputs,
+                    select_param: slot(&param),
+                });
+                AnimNodeHandle::SelectAnimNodeHandle(self.select_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::NoiseNode { input, amplitude, frequency, seed, joint_amplitudes } => {
+                let input_handle = self.add_node(*input, animations, skeleton, params, shared)?;
+                let joint_amplitudes = match joint_amplitudes {
+                    Some(joint_amplitudes) => {
+                        let mut amplitudes = vec![0.0; skeleton.joints.len()];
+                        for (joint_name, joint_amplitude) in joint_amplitudes {
+                            if let Some(joint_index) = skeleton.get_joint_index(&joint_name) {
+                                amplitudes[joint_index as usize] = amplitude * joint_amplitude;
+                            }
+                        }
+                        amplitudes
+                    }
+                    None => vec![amplitude; skeleton.joints.len()],
+                };
+                self.noise_nodes.push(NoiseAnimNode {
+                    input: input_handle,
+                    joint_amplitudes,
+                    frequency,
+                    seed,
+                });
+                AnimNodeHandle::NoiseAnimNodeHandle(self.noise_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::BlendSpace2D { x_param, y_param, samples } => {
+                let mut points = Vec::new();
+                let mut inputs = Vec::new();
+                for sample in samples {
+                    points.push([sample.x, sample.y]);
+                    inputs.push(self.add_node(sample.node, animations, skeleton, params, shared)?);
+                }
+                self.blend_space_nodes.push(BlendSpace2DAnimNode {
+                    x_param: slot(&x_param),
+                    y_param: slot(&y_param),
+                    triangulation: BlendSpaceTriangulation::new(points),
+                    inputs,
+                });
+                AnimNodeHandle::BlendSpace2DAnimNodeHandle(self.blend_space_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::WeightedBlendNode { inputs } => {
+                let inputs = inputs.into_iter().map(|input| {
+                    Ok((self.add_node(input.node, animations, skeleton, params, shared)?, slot(&input.param)))
+                }).collect::<Result<_, _>>()?;
+                self.weighted_blend_nodes.push(WeightedBlendAnimNode {
+                    inputs,
+                });
+                AnimNodeHandle::WeightedBlendAnimNodeHandle(self.weighted_blend_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::PoseNode { pose_source } => {
+                let poses = match pose_source {
+                    Some(clip_id) => {
+                        let clip = animations.get(&clip_id[..]).ok_or_else(|| BlendTreeError::MissingClip(clip_id.clone()))?;
+                        clip.samples.first().map(|sample| sample.local_poses.clone()).unwrap_or_default()
+                    }
+                    None => skeleton.get_local_bind_poses(),
+                };
+                self.pose_nodes.push(PoseAnimNode {
+                    poses
+                });
+                AnimNodeHandle::PoseAnimNodeHandle(self.pose_nodes.len() - 1)
+            }
+            BlendTreeNodeDef::ClipNode { clip_source: clip_id, loop_mode, phase_param } => {
+                let clip = animations.get(&clip_id[..]).ok_or_else(|| BlendTreeError::MissingClip(clip_id.clone()))?;
+                let mut clip = ClipInstance::new(clip.clone());
+                clip.set_loop_mode(loop_mode.unwrap_or_default());
                 self.clip_nodes.push(ClipAnimNode {
-                    clip: ClipInstance::new(clip.clone())
+                    clip,
+                    phase_param: phase_param.map(|phase_param| slot(&phase_param)),
                 });
                 AnimNodeHandle::ClipAnimNodeHandle(self.clip_nodes.len() - 1)
             }
+            BlendTreeNodeDef::RefNode { definition } => {
+                // Shared nodes are evaluated from every RefNode to them, so each reference
+                // is charged the full depth of the definition
+                if let Some(&(ref handle, height)) = shared.handles.get(&definition) {
+                    if shared.depth + height > shared.max_depth {
+                        return Err(BlendTreeError::TooDeep);
+                    }
+                    shared.deepest = shared.deepest.max(shared.depth + height);
+                    return Ok(handle.clone());
+                }
+                if shared.resolving.contains(&definition) {
+                    return Err(BlendTreeError::RecursiveDefinition(definition));
+                }
+                let node = match shared.definitions.get(&definition) {
+                    Some(node) => node.clone(),
+                    None => return Err(BlendTreeError::MissingDefinition(definition)),
+                };
+                shared.resolving.push(definition.clone());
+                let outer_deepest = mem::replace(&mut shared.deepest, shared.depth);
+                let handle = self.add_node(node, animations, skeleton, params, shared)?;
+                let height = shared.deepest - shared.depth;
+                shared.deepest = shared.deepest.max(outer_deepest);
+                shared.resolving.pop();
+                shared.handles.insert(definition, (handle.clone(), height));
+                handle
+            }
+        })
+    }
+
+    /// Collect the names of events passed by every clip in the tree when advancing from
+    /// `prev_time` (exclusive) to `curr_time` (inclusive), with the times scaled by any
+    /// SpeedNodes above each clip, as for its poses. Clips sampled at a phase parameter
+    /// rather than the time (ClipNodes with a `phase_param`, and the clip inputs of LerpNodes
+    /// with one) have no interval of playback to collect events from, so are skipped.
+    pub fn collect_events<'a>(&'a self, prev_time: f32, curr_time: f32, params: &ParameterSet, events: &mut Vec<&'a str>) {
+        self.collect_node_events(self.root_node.clone(), prev_time, curr_time, params, &mut Vec::new(), events);
+    }
+
+    /// Collect the events under `handle`, as for `collect_events`, skipping clip nodes that
+    /// were already collected over the same interval through another RefNode to them
+    fn collect_node_events<'a>(
+        &'a self,
+        handle: AnimNodeHandle,
+        prev_time: f32,
+        curr_time: f32,
+        params: &ParameterSet,
+        collected: &mut Vec<(usize, f32, f32)>,
+        events: &mut Vec<&'a str>,
+    ) {
+        let inputs: Vec<AnimNodeHandle> = match handle {
+            AnimNodeHandle::LerpAnimNodeHandle(i) => {
+                let lerp_node = &self.lerp_nodes[i];
+                [lerp_node.input_1.clone(), lerp_node.input_2.clone()].iter()
+                    .filter(|input| lerp_node.phase_param.is_none() || !matches!(**input, AnimNodeHandle::ClipAnimNodeHandle(_)))
+                    .cloned()
+                    .collect()
+            }
+            AnimNodeHandle::AdditiveAnimNodeHandle(i) => {
+                let additive_node = &self.additive_nodes[i];
+                vec![additive_node.base_input.clone(), additive_node.additive_input.clone()]
+            }
+            AnimNodeHandle::BlendSpace2DAnimNodeHandle(i) => self.blend_space_nodes[i].inputs.clone(),
+            AnimNodeHandle::WeightedBlendAnimNodeHandle(i) => self.weighted_blend_nodes[i].inputs.iter().map(|input| input.0.clone()).collect(),
+            AnimNodeHandle::SelectAnimNodeHandle(i) => self.select_nodes[i].inputs.clone(),
+            AnimNodeHandle::IKAnimNodeHandle(i) => vec![self.ik_nodes[i].input.clone()],
+            AnimNodeHandle::NoiseAnimNodeHandle(i) => vec![self.noise_nodes[i].input.clone()],
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => {
+                let speed_node = &self.speed_nodes[i];
+                let speed = self.get_param(params, speed_node.speed_param);
+                return self.collect_node_events(speed_node.input.clone(), prev_time * speed, curr_time * speed, params, collected, events);
+            }
+            AnimNodeHandle::ClipAnimNodeHandle(i) => {
+                let clip_node = &self.clip_nodes[i];
+                if clip_node.phase_param.is_none() && !collected.contains(&(i, prev_time, curr_time)) {
+                    collected.push((i, prev_time, curr_time));
+                    clip_node.clip.collect_events(prev_time, curr_time, events);
+                }
+                return;
+            }
+            AnimNodeHandle::PoseAnimNodeHandle(_) | AnimNodeHandle::None => return,
+        };
+
+        for input in inputs {
+            self.collect_node_events(input, prev_time, curr_time, params, collected, events);
+        }
+    }
+
+    /// The normalized playback phase of the dominant clip in the tree at the given time,
+    /// following the more heavily weighted input of each blend node, or None if the tree
+    /// contains no clips
+    pub fn get_phase(&self, time: f32, params: &ParameterSet) -> Option<f32> {
+        self.get_node_phase(self.root_node.clone(), time, params)
+    }
+
+    fn get_node_phase(&self, handle: AnimNodeHandle, time: f32, params: &ParameterSet) -> Option<f32> {
+        match handle {
+            AnimNodeHandle::LerpAnimNodeHandle(i) => {
+                let lerp_node = &self.lerp_nodes[i];
+                if let Some(phase_param) = lerp_node.phase_param {
+                    return Some(self.get_param(params, phase_param).rem_euclid(1.0));
+                }
+                let dominant_input = if self.get_param(params, lerp_node.blend_param) < 0.5 {
+                    lerp_node.input_1.clone()
+                } else {
+                    lerp_node.input_2.clone()
+                };
+                self.get_node_phase(dominant_input, time, params)
+            }
+            AnimNodeHandle::AdditiveAnimNodeHandle(i) => self.get_node_phase(self.additive_nodes[i].base_input.clone(), time, params),
+            AnimNodeHandle::BlendSpace2DAnimNodeHandle(i) => {
+                let blend_space_node = &self.blend_space_nodes[i];
+                let weights = blend_space_node.get_weights(self, params);
+                let dominant_input = weights.iter()
+                    .fold(None, |dominant: Option<(usize, f32)>, &(index, weight)| match dominant {
+                        Some((_, dominant_weight)) if dominant_weight >= weight => dominant,
+                        _ => Some((index, weight)),
+                    });
+                dominant_input.and_then(|(index, _)| self.get_node_phase(blend_space_node.inputs[index].clone(), time, params))
+            }
+            AnimNodeHandle::WeightedBlendAnimNodeHandle(i) => {
+                let weighted_blend_node = &self.weighted_blend_nodes[i];
+                let dominant_input = weighted_blend_node.get_weights(self, params).into_iter()
+                    .zip(weighted_blend_node.inputs.iter())
+                    .fold(None, |dominant: Option<(f32, &AnimNodeHandle)>, (weight, input)| match dominant {
+                        Some((dominant_weight, _)) if dominant_weight >= weight => dominant,
+                        _ => Some((weight, &input.0)),
+                    });
+                dominant_input.and_then(|(_, input)| self.get_node_phase(input.clone(), time, params))
+            }
+            AnimNodeHandle::IKAnimNodeHandle(i) => self.get_node_phase(self.ik_nodes[i].input.clone(), time, params),
+            AnimNodeHandle::NoiseAnimNodeHandle(i) => self.get_node_phase(self.noise_nodes[i].input.clone(), time, params),
+            AnimNodeHandle::SelectAnimNodeHandle(i) => {
+                let selected_input = self.select_nodes[i].get_selected_input(self, params);
+                selected_input.and_then(|input| self.get_node_phase(input.clone(), time, params))
+            }
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => {
+                let speed_node = &self.speed_nodes[i];
+                self.get_node_phase(speed_node.input.clone(), time * self.get_param(params, speed_node.speed_param), params)
+            }
+            AnimNodeHandle::ClipAnimNodeHandle(i) => {
+                let clip_node = &self.clip_nodes[i];
+                Some(clip_node.get_driven_phase(self, params).unwrap_or_else(|| clip_node.clip.get_phase(time)))
+            }
+            AnimNodeHandle::PoseAnimNodeHandle(_) | AnimNodeHandle::None => None,
+        }
+    }
+
+    /// The sample rate of the dominant clip in the tree, following the more heavily weighted
+    /// input of each blend node, or None if the tree contains no clips
+    pub fn get_samples_per_second(&self, params: &ParameterSet) -> Option<f32> {
+        self.get_dominant_clip_node(self.root_node.clone(), 0.0, params)
+            .map(|(i, _)| self.clip_nodes[i].clip.clip.samples_per_second)
+    }
+
+    /// Whether the dominant clip in the tree (as for `get_phase`) is non-looping and has
+    /// played through by the given time. Trees without clips are never finished.
+    pub fn is_finished(&self, time: f32, params: &ParameterSet) -> bool {
+        self.get_dominant_clip_node(self.root_node.clone(), time, params)
+            .is_some_and(|(i, time)| {
+                let clip_node = &self.clip_nodes[i];
+                match clip_node.get_driven_phase(self, params) {
+                    Some(phase) => clip_node.clip.loop_mode == LoopMode::Clamp && phase >= 1.0,
+                    None => clip_node.clip.is_finished(time),
+                }
+            })
+    }
+
+    /// Whether the dominant clip in the tree (as for `get_phase`) wraps back to the start of
+    /// its cycle between the tree times `prev_time` and `curr_time`, however many cycles
+    /// are passed. Trees without clips never wrap.
+    pub fn has_wrapped(&self, prev_time: f32, curr_time: f32, params: &ParameterSet) -> bool {
+        let prev_clip_node = self.get_dominant_clip_node(self.root_node.clone(), prev_time, params);
+        let curr_clip_node = self.get_dominant_clip_node(self.root_node.clone(), curr_time, params);
+        match (prev_clip_node, curr_clip_node) {
+            (Some((i, prev_clip_time)), Some((j, curr_clip_time))) if i == j && self.clip_nodes[i].phase_param.is_none() => {
+                self.clip_nodes[i].clip.has_wrapped(prev_clip_time, curr_clip_time)
+            }
+            _ => false,
+        }
+    }
+
+    /// The index of the dominant clip node under `handle`, and the time it's evaluated at
+    /// for the given tree time
+    fn get_dominant_clip_node(&self, handle: AnimNodeHandle, time: f32, params: &ParameterSet) -> Option<(usize, f32)> {
+        match handle {
+            AnimNodeHandle::LerpAnimNodeHandle(i) => {
+                let lerp_node = &self.lerp_nodes[i];
+                let dominant_input = if self.get_param(params, lerp_node.blend_param) < 0.5 {
+                    lerp_node.input_1.clone()
+                } else {
+                    lerp_node.input_2.clone()
+                };
+                self.get_dominant_clip_node(dominant_input, time, params)
+            }
+            AnimNodeHandle::AdditiveAnimNodeHandle(i) => self.get_dominant_clip_node(self.additive_nodes[i].base_input.clone(), time, params),
+            AnimNodeHandle::BlendSpace2DAnimNodeHandle(i) => {
+                let blend_space_node = &self.blend_space_nodes[i];
+                let dominant_input = blend_space_node.get_weights(self, params).iter()
+                    .fold(None, |dominant: Option<(usize, f32)>, &(index, weight)| match dominant {
+                        Some((_, dominant_weight)) if dominant_weight >= weight => dominant,
+                        _ => Some((index, weight)),
+                    });
+                dominant_input.and_then(|(index, _)| self.get_dominant_clip_node(blend_space_node.inputs[index].clone(), time, params))
+            }
+            AnimNodeHandle::WeightedBlendAnimNodeHandle(i) => {
+                let weighted_blend_node = &self.weighted_blend_nodes[i];
+                let dominant_input = weighted_blend_node.get_weights(self, params).into_iter()
+                    .zip(weighted_blend_node.inputs.iter())
+                    .fold(None, |dominant: Option<(f32, &AnimNodeHandle)>, (weight, input)| match dominant {
+                        Some((dominant_weight, _)) if dominant_weight >= weight => dominant,
+                        _ => Some((weight, &input.0)),
+                    });
+                dominant_input.and_then(|(_, input)| self.get_dominant_clip_node(input.clone(), time, params))
+            }
+            AnimNodeHandle::IKAnimNodeHandle(i) => self.get_dominant_clip_node(self.ik_nodes[i].input.clone(), time, params),
+            AnimNodeHandle::NoiseAnimNodeHandle(i) => self.get_dominant_clip_node(self.noise_nodes[i].input.clone(), time, params),
+            AnimNodeHandle::SelectAnimNodeHandle(i) => {
+                let selected_input = self.select_nodes[i].get_selected_input(self, params);
+                selected_input.and_then(|input| self.get_dominant_clip_node(input.clone(), time, params))
+            }
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => {
+                let speed_node = &self.speed_nodes[i];
+                self.get_dominant_clip_node(speed_node.input.clone(), time * self.get_param(params, speed_node.speed_param), params)
+            }
+            AnimNodeHandle::ClipAnimNodeHandle(i) => Some((i, time)),
+            AnimNodeHandle::PoseAnimNodeHandle(_) | AnimNodeHandle::None => None,
+        }
+    }
+
+    /// Evaluate the node for `handle`, sampling it at `phase` of its duration if it's a clip
+    /// node (without its own phase parameter) and a phase is given, or at `time` otherwise
+    fn get_phased_output_pose(&self, handle: AnimNodeHandle, time: f32, phase: Option<f32>, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+        match (handle, phase) {
+            (AnimNodeHandle::ClipAnimNodeHandle(i), Some(phase)) if self.clip_nodes[i].phase_param.is_none() => {
+                self.clip_nodes[i].clip.get_pose_at_phase(phase, output_poses);
+            }
+            (handle, _) => {
+                if let Some(node) = self.get_node(handle) {
+                    node.get_output_pose(self, time, params, buffers, output_poses);
+                }
+            }
         }
     }
 
-    fn get_node(&self, handle: AnimNodeHandle) -> Option<&AnimNode<T>> {
+    fn get_node(&self, handle: AnimNodeHandle) -> Option<&dyn AnimNode<T>> {
         match handle {
             AnimNodeHandle::LerpAnimNodeHandle(i) => Some(&self.lerp_nodes[i]),
             AnimNodeHandle::AdditiveAnimNodeHandle(i) => Some(&self.additive_nodes[i]),
             AnimNodeHandle::ClipAnimNodeHandle(i) => Some(&self.clip_nodes[i]),
             AnimNodeHandle::IKAnimNodeHandle(i) => Some(&self.ik_nodes[i]),
+            AnimNodeHandle::SpeedAnimNodeHandle(i) => Some(&self.speed_nodes[i]),
+            AnimNodeHandle::PoseAnimNodeHandle(i) => Some(&self.pose_nodes[i]),
+            AnimNodeHandle::BlendSpace2DAnimNodeHandle(i) => Some(&self.blend_space_nodes[i]),
+            AnimNodeHandle::WeightedBlendAnimNodeHandle(i) => Some(&self.weighted_blend_nodes[i]),
+            AnimNodeHandle::NoiseAnimNodeHandle(i) => Some(&self.noise_nodes[i]),
+            AnimNodeHandle::SelectAnimNodeHandle(i) => Some(&self.select_nodes[i]),
             AnimNodeHandle::None => None,
         }
     }
 }
 
 pub trait AnimNode<T: Transform> {
-    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]);
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]);
+}
+
+/// A pool of scratch pose buffers, which blend tree nodes borrow from while evaluating
+/// their inputs and return when done, so deep trees reuse the same allocations rather
+/// than each node holding fixed-size pose arrays on the stack. The pool grows to the
+/// depth of the tree, with buffers sized to the joint count.
+pub struct PoseBuffers<T: Transform> {
+    poses: Vec<Vec<T>>,
+    matrices: Vec<Vec<Matrix4<f32>>>,
+}
+
+impl<T: Transform> Default for PoseBuffers<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Transform> PoseBuffers<T> {
+
+    pub fn new() -> Self {
+        Self { poses: Vec::new(), matrices: Vec::new() }
+    }
+
+    /// Borrow a buffer of `len` identity poses, to be returned with `give`
+    pub fn take(&mut self, len: usize) -> Vec<T> {
+        let mut buffer = self.poses.pop().unwrap_or_default();
+        buffer.clear();
+        buffer.resize(len, T::identity());
+        buffer
+    }
+
+    /// Return a buffer borrowed with `take` to the pool
+    pub fn give(&mut self, buffer: Vec<T>) {
+        self.poses.push(buffer);
+    }
+
+    /// Borrow a buffer of `len` identity matrices, e.g. for global poses, to be returned
+    /// with `give_matrices`
+    pub fn take_matrices(&mut self, len: usize) -> Vec<Matrix4<f32>> {
+        let mut buffer = self.matrices.pop().unwrap_or_default();
+        buffer.clear();
+        buffer.resize(len, mat4_id());
+        buffer
+    }
+
+    /// Return a buffer borrowed with `take_matrices` to the pool
+    pub fn give_matrices(&mut self, buffer: Vec<Matrix4<f32>>) {
+        self.matrices.push(buffer);
+    }
 }
 
 #[derive(Clone)]
@@ -264,6 +1161,12 @@ pub enum AnimNodeHandle {
     AdditiveAnimNodeHandle(usize),
     ClipAnimNodeHandle(usize),
     IKAnimNodeHandle(usize),
+    SpeedAnimNodeHandle(usize),
+    PoseAnimNodeHandle(usize),
+    BlendSpace2DAnimNodeHandle(usize),
+    WeightedBlendAnimNodeHandle(usize),
+    NoiseAnimNodeHandle(usize),
+    SelectAnimNodeHandle(usize),
 }
 
 /// An AnimNode where pose output is linear blend between the output of the two input AnimNodes,
@@ -271,30 +1174,38 @@ pub enum AnimNodeHandle {
 pub struct LerpAnimNode {
     input_1: AnimNodeHandle,
     input_2: AnimNodeHandle,
-    blend_param: ParamId
+    blend_param: ParamSlot,
+    phase_param: Option<ParamSlot>,
 }
 
 impl<T: Transform> AnimNode<T> for LerpAnimNode {
-    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
-
-        let mut input_poses = [ T::identity(); 64 ];
-        let sample_count = output_poses.len();
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+
+        let blend_parameter = tree.get_param(params, self.blend_param);
+        let phase = self.phase_param.map(|phase_param| tree.get_param(params, phase_param));
+
+        // At either end of the blend only one input contributes, so evaluate it straight
+        // into the output, skipping the other input and the blend. The output is reset to
+        // the identity first, the same base pose the inputs are evaluated over otherwise,
+        // so that channels the input doesn't drive don't jump at the ends.
+        if blend_parameter == 0.0 || blend_parameter == 1.0 {
+            let input = if blend_parameter == 0.0 { &self.input_1 } else { &self.input_2 };
+            for output_pose in output_poses.iter_mut() {
+                *output_pose = T::identity();
+            }
+            return tree.get_phased_output_pose(input.clone(), time, phase, params, buffers, output_poses);
+        }
 
-        let blend_parameter = params[&self.blend_param[..]];
+        let mut input_poses_1 = buffers.take(output_poses.len());
+        let mut input_poses_2 = buffers.take(output_poses.len());
 
-        if let Some(ref node) = tree.get_node(self.input_1.clone()) {
-            node.get_output_pose(tree, time, params, &mut input_poses[0 .. sample_count]);
-        }
+        tree.get_phased_output_pose(self.input_1.clone(), time, phase, params, buffers, &mut input_poses_1[..]);
+        tree.get_phased_output_pose(self.input_2.clone(), time, phase, params, buffers, &mut input_poses_2[..]);
 
-        if let Some(ref node) = tree.get_node(self.input_2.clone()) {
-            node.get_output_pose(tree, time, params, output_poses);
-        }
+        blend_poses(&input_poses_1[..], &input_poses_2[..], blend_parameter, output_poses);
 
-        for i in 0 .. output_poses.len() {
-            let pose_1 = input_poses[i];
-            let pose_2 = &mut output_poses[i];
-            (*pose_2) = pose_1.lerp(pose_2.clone(), blend_parameter);
-        }
+        buffers.give(input_poses_1);
+        buffers.give(input_poses_2);
     }
 }
 
@@ -303,69 +1214,457 @@ impl<T: Transform> AnimNode<T> for LerpAnimNode {
 pub struct AdditiveAnimNode {
     base_input: AnimNodeHandle,
     additive_input: AnimNodeHandle,
-    blend_param: ParamId
+    blend_param: ParamSlot,
 }
 
 impl<T: Transform> AnimNode<T> for AdditiveAnimNode {
-    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+
+        let blend_parameter = tree.get_param(params, self.blend_param);
 
-        let mut input_poses = [ T::identity(); 64 ];
-        let sample_count = output_poses.len();
+        // With none of the additive input, the output is just the base input's, over the
+        // same identity base pose as otherwise
+        if blend_parameter == 0.0 {
+            for output_pose in output_poses.iter_mut() {
+                *output_pose = T::identity();
+            }
+            if let Some(node) = tree.get_node(self.base_input.clone()) {
+                node.get_output_pose(tree, time, params, buffers, output_poses);
+            }
+            return;
+        }
 
-        let blend_parameter = params[&self.blend_param[..]];
+        let mut input_poses = buffers.take(output_poses.len());
 
-        if let Some(ref node) = tree.get_node(self.base_input.clone()) {
-            node.get_output_pose(tree, time, params, &mut input_poses[0 .. sample_count]);
+        if let Some(node) = tree.get_node(self.base_input.clone()) {
+            node.get_output_pose(tree, time, params, buffers, &mut input_poses[..]);
         }
 
-        if let Some(ref node) = tree.get_node(self.additive_input.clone()) {
-            node.get_output_pose(tree, time, params, output_poses);
+        if let Some(node) = tree.get_node(self.additive_input.clone()) {
+            node.get_output_pose(tree, time, params, buffers, output_poses);
         }
 
-        for i in 0 .. output_poses.len() {
-            let pose_1 = input_poses[i];
-            let pose_2 = &mut output_poses[i];
-            let additive_pose = T::identity().lerp(pose_2.clone(), blend_parameter);
-            (*pose_2) = pose_1.concat(additive_pose);
+        for (pose_1, pose_2) in input_poses.iter().zip(output_poses.iter_mut()) {
+            let additive_pose = T::identity().lerp(*pose_2, blend_parameter);
+            *pose_2 = pose_1.concat(additive_pose);
         }
+
+        buffers.give(input_poses);
     }
 }
 
-/// An AnimNode where pose output is from an animation ClipInstance
+/// An AnimNode where pose output is from an animation ClipInstance, sampled at the tree
+/// time, or at the normalized phase given by phase_param if there is one
 pub struct ClipAnimNode<T: Transform> {
-    clip: ClipInstance<T>
+    clip: ClipInstance<T>,
+    phase_param: Option<ParamSlot>,
+}
+
+impl<T: Transform> ClipAnimNode<T> {
+    /// The phase the clip is driven at by its phase_param, if it has one
+    fn get_driven_phase(&self, tree: &AnimBlendTree<T>, params: &ParameterSet) -> Option<f32> {
+        self.phase_param.map(|phase_param| tree.get_param(params, phase_param).clamp(0.0, 1.0))
+    }
 }
 
 impl<T: Transform> AnimNode<T> for ClipAnimNode<T> {
-    fn get_output_pose(&self, _tree: &AnimBlendTree<T>, time: f32, _params: &HashMap<String, f32>, output_poses: &mut [T]) {
-        self.clip.get_pose_at_time(time, output_poses);
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &ParameterSet, _buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+        match self.get_driven_phase(tree, params) {
+            Some(phase) => {
+                // A phase of 1.0 holds the last sample, unless looping back to the first
+                let sample_time = phase * self.clip.get_duration();
+                match self.clip.loop_mode {
+                    LoopMode::Loop => self.clip.clip.get_pose_at_time(sample_time, output_poses),
+                    LoopMode::Clamp | LoopMode::PingPong => self.clip.clip.get_pose_at_time_clamped(sample_time, output_poses),
+                }
+            }
+            None => self.clip.get_pose_at_time(time, output_poses),
+        }
+    }
+}
+
+/// An AnimNode where pose output is from its input evaluated at a time scaled by the value
+/// of speed_param, uniformly speeding up or slowing down the whole subtree
+pub struct SpeedAnimNode {
+    input: AnimNodeHandle,
+    speed_param: ParamSlot,
+}
+
+impl<T: Transform> AnimNode<T> for SpeedAnimNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+        let speed = tree.get_param(params, self.speed_param);
+        if let Some(node) = tree.get_node(self.input.clone()) {
+            node.get_output_pose(tree, time * speed, params, buffers, output_poses);
+        }
+    }
+}
+
+/// An AnimNode where pose output is a blend of inputs placed at points in a 2D space,
+/// weighted according to the position of the point (x_param, y_param) among them
+pub struct BlendSpace2DAnimNode {
+    x_param: ParamSlot,
+    y_param: ParamSlot,
+    triangulation: BlendSpaceTriangulation,
+    inputs: Vec<AnimNodeHandle>,
+}
+
+impl BlendSpace2DAnimNode {
+    fn get_weights<T: Transform>(&self, tree: &AnimBlendTree<T>, params: &ParameterSet) -> BlendSpaceWeights {
+        let point = [tree.get_param(params, self.x_param), tree.get_param(params, self.y_param)];
+        let mut weights = [(0, 0.0); 3];
+        self.triangulation.weights(point, &mut weights);
+        weights
+    }
+}
+
+impl<T: Transform> AnimNode<T> for BlendSpace2DAnimNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+
+        let mut input_poses = buffers.take(output_poses.len());
+
+        let mut accumulated_weight = 0.0;
+
+        for (index, weight) in self.get_weights(tree, params).iter().cloned() {
+            if weight == 0.0 {
+                continue;
+            }
+
+            let node = match tree.get_node(self.inputs[index].clone()) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            if accumulated_weight == 0.0 {
+                node.get_output_pose(tree, time, params, buffers, output_poses);
+            } else {
+                node.get_output_pose(tree, time, params, buffers, &mut input_poses[..]);
+                let blend_parameter = weight / (accumulated_weight + weight);
+                for (output_pose, input_pose) in output_poses.iter_mut().zip(input_poses.iter()) {
+                    *output_pose = output_pose.lerp(*input_pose, blend_parameter);
+                }
+            }
+
+            accumulated_weight += weight;
+        }
+
+        buffers.give(input_poses);
+    }
+}
+
+/// An AnimNode where pose output is the weighted average of any number of inputs, each
+/// weighted by its own parameter. Weights are normalized to sum to 1, with negative weights
+/// treated as 0; if every weight is 0 the inputs are weighted equally.
+pub struct WeightedBlendAnimNode {
+    inputs: Vec<(AnimNodeHandle, ParamSlot)>,
+}
+
+impl WeightedBlendAnimNode {
+    fn get_weights<T: Transform>(&self, tree: &AnimBlendTree<T>, params: &ParameterSet) -> Vec<f32> {
+        let weights: Vec<f32> = self.inputs.iter()
+            .map(|&(_, param)| tree.get_param(params, param).max(0.0))
+            .collect();
+        let total: f32 = weights.iter().sum();
+        if total > 0.0 {
+            weights.iter().map(|weight| weight / total).collect()
+        } else {
+            vec![1.0 / weights.len() as f32; weights.len()]
+        }
+    }
+}
+
+impl<T: Transform> AnimNode<T> for WeightedBlendAnimNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+
+        let mut input_poses = buffers.take(output_poses.len());
+
+        // Sum each joint's weighted translation, scale and rotation over every input, with
+        // rotations sign-aligned to the first input's so they sum along the shortest path,
+        // then normalize the rotation once. Unlike a running lerp this doesn't depend on
+        // the order of the inputs.
+        let mut sums = vec![([0.0; 3], 0.0, (0.0, [0.0; 3])); output_poses.len()];
+        let mut total_weight = 0.0;
+
+        for (input, weight) in self.inputs.iter().zip(self.get_weights(tree, params)) {
+            if weight == 0.0 {
+                continue;
+            }
+
+            let node = match tree.get_node(input.0.clone()) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            node.get_output_pose(tree, time, params, buffers, &mut input_poses[..]);
+
+            for (sum, input_pose) in sums.iter_mut().zip(input_poses.iter()) {
+                let rotation = input_pose.get_rotation();
+                let rotation_weight = if quaternion::dot(sum.2, rotation) < 0.0 { -weight } else { weight };
+                sum.0 = vec3_add(sum.0, vec3_scale(input_pose.get_translation(), weight));
+                sum.1 += input_pose.get_scale() * weight;
+                sum.2 = quaternion::add(sum.2, quaternion::scale(rotation, rotation_weight));
+            }
+
+            total_weight += weight;
+        }
+
+        if total_weight > 0.0 {
+            for (output_pose, &(translation, scale, rotation)) in output_poses.iter_mut().zip(sums.iter()) {
+                let mut pose = T::identity();
+                pose.set_rotation(quaternion::scale(rotation, 1.0 / quaternion::len(rotation)));
+                pose.set_translation(vec3_scale(translation, 1.0 / total_weight));
+                pose.set_scale(scale / total_weight);
+                *output_pose = pose;
+            }
+        }
+
+        buffers.give(input_poses);
+    }
+}
+
+/// An AnimNode where pose output is the output of one of its inputs, selected by the
+/// select_param value rounded to the nearest input index
+pub struct SelectAnimNode {
+    inputs: Vec<AnimNodeHandle>,
+    select_param: ParamSlot,
+}
+
+impl SelectAnimNode {
+    fn get_selected_input<T: Transform>(&self, tree: &AnimBlendTree<T>, params: &ParameterSet) -> Option<&AnimNodeHandle> {
+        let last_index = self.inputs.len().checked_sub(1)?;
+        let value = tree.get_param(params, self.select_param).round();
+        let index = if value > 0.0 { (value as usize).min(last_index) } else { 0 };
+        self.inputs.get(index)
+    }
+}
+
+impl<T: Transform> AnimNode<T> for SelectAnimNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+        if let Some(node) = self.get_selected_input(tree, params).and_then(|input| tree.get_node(input.clone())) {
+            node.get_output_pose(tree, time, params, buffers, output_poses);
+        }
+    }
+}
+
+/// An AnimNode where pose output is the input pose with smooth, seeded noise added to each
+/// joint's rotation, varying over time
+pub struct NoiseAnimNode {
+    input: AnimNodeHandle,
+    joint_amplitudes: Vec<f32>,
+    frequency: f32,
+    seed: u32,
+}
+
+impl<T: Transform> AnimNode<T> for NoiseAnimNode {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+
+        if let Some(node) = tree.get_node(self.input.clone()) {
+            node.get_output_pose(tree, time, params, buffers, output_poses);
+        }
+
+        let x = time * self.frequency;
+
+        for (joint_index, (output_pose, &amplitude)) in output_poses.iter_mut().zip(self.joint_amplitudes.iter()).enumerate() {
+            if amplitude == 0.0 {
+                continue;
+            }
+
+            // Independent noise for each axis of each joint, as a rotation vector
+            let mut rotation_vector = [0.0; 3];
+            for (axis, r) in rotation_vector.iter_mut().enumerate() {
+                let stream = (joint_index * 3 + axis) as u32;
+                *r = amplitude * value_noise(self.seed.wrapping_add(stream.wrapping_mul(0x632be5ab)), x);
+            }
+
+            let angle = vec3_len(rotation_vector);
+            if angle > 0.0 {
+                let noise_rotation = quaternion::axis_angle(vec3_scale(rotation_vector, 1.0 / angle), angle);
+                let rotation = quaternion_mul(output_pose.get_rotation(), noise_rotation);
+                output_pose.set_rotation(rotation);
+            }
+        }
+    }
+}
+
+/// The samples of a 2D blend space triangulated once when the tree is built, so that
+/// weights can be found at runtime by locating the triangle containing the blend point.
+/// Edges are added shortest first, skipping any that would cross an edge already added.
+struct BlendSpaceTriangulation {
+    points: Vec<[f32; 2]>,
+    triangles: Vec<[usize; 3]>,
+    /// The edges of the convex hull, or between neighbouring samples if they're collinear
+    hull_edges: Vec<[usize; 2]>,
+}
+
+/// The weights of up to three samples of a blend space, as (sample index, weight) pairs,
+/// with unused entries weighted 0
+type BlendSpaceWeights = [(usize, f32); 3];
+
+impl BlendSpaceTriangulation {
+
+    const EPSILON: f32 = 0.00001;
+
+    fn new(points: Vec<[f32; 2]>) -> Self {
+
+        let cross = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| {
+            (b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1])
+        };
+
+        // Candidate edges, shortest first, skipping any that pass through another sample
+        let mut candidates = Vec::new();
+        for i in 0 .. points.len() {
+            for j in i + 1 .. points.len() {
+                let (a, b) = (points[i], points[j]);
+                let ab = vec2_sub(b, a);
+                let ab_length_squared = vec2_square_len(ab);
+                let passes_through_sample = (0 .. points.len()).any(|k| {
+                    if k == i || k == j {
+                        return false;
+                    }
+                    let t = vec2_dot(vec2_sub(points[k], a), ab) / ab_length_squared;
+                    cross(a, b, points[k]).abs() < Self::EPSILON && t > 0.0 && t < 1.0
+                });
+                if ab_length_squared > 0.0 && !passes_through_sample {
+                    candidates.push((ab_length_squared, [i, j]));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+        let mut edges: Vec<[usize; 2]> = Vec::new();
+        for (_, [i, j]) in candidates {
+            let (a, b) = (points[i], points[j]);
+            let crosses_edge = edges.iter().any(|&[k, l]| {
+                if k == i || k == j || l == i || l == j {
+                    return false;
+                }
+                let (c, d) = (points[k], points[l]);
+                cross(a, b, c) * cross(a, b, d) < 0.0 && cross(c, d, a) * cross(c, d, b) < 0.0
+            });
+            if !crosses_edge {
+                edges.push([i, j]);
+            }
+        }
+
+        let has_edge = |i: usize, j: usize| edges.iter().any(|&edge| edge == [i.min(j), i.max(j)]);
+
+        // With a full triangulation, every triple of connected samples without another
+        // sample inside it is a triangle
+        let mut triangles = Vec::new();
+        for i in 0 .. points.len() {
+            for j in i + 1 .. points.len() {
+                for k in j + 1 .. points.len() {
+                    if !has_edge(i, j) || !has_edge(j, k) || !has_edge(i, k) {
+                        continue;
+                    }
+                    let (a, b, c) = (points[i], points[j], points[k]);
+                    if cross(a, b, c).abs() < Self::EPSILON {
+                        continue;
+                    }
+                    let contains_sample = (0 .. points.len()).any(|l| {
+                        l != i && l != j && l != k
+                            && barycentric_weights(a, b, c, points[l]).iter().all(|&weight| weight > Self::EPSILON)
+                    });
+                    if !contains_sample {
+                        triangles.push([i, j, k]);
+                    }
+                }
+            }
+        }
+
+        // Edges of only one triangle are on the hull
+        let hull_edges = if triangles.is_empty() {
+            edges
+        } else {
+            edges.into_iter().filter(|&[i, j]| {
+                triangles.iter().filter(|triangle| triangle.contains(&i) && triangle.contains(&j)).count() == 1
+            }).collect()
+        };
+
+        Self { points, triangles, hull_edges }
+    }
+
+    /// Write the blend weights for the point `p` into `weights`. Uses barycentric weights
+    /// for the triangle containing `p`, or if `p` is outside all of them, linear weights
+    /// for the nearest point on the hull.
+    fn weights(&self, p: [f32; 2], weights: &mut BlendSpaceWeights) {
+
+        *weights = [(0, 0.0); 3];
+
+        for &[i, j, k] in self.triangles.iter() {
+            let [w_a, w_b, w_c] = barycentric_weights(self.points[i], self.points[j], self.points[k], p);
+            if w_a >= -Self::EPSILON && w_b >= -Self::EPSILON && w_c >= -Self::EPSILON {
+                *weights = [(i, w_a.max(0.0)), (j, w_b.max(0.0)), (k, w_c.max(0.0))];
+                return;
+            }
+        }
+
+        if self.points.len() == 1 {
+            weights[0] = (0, 1.0);
+            return;
+        }
+
+        let mut nearest_distance_squared = f32::INFINITY;
+
+        for &[i, j] in self.hull_edges.iter() {
+            let (a, b) = (self.points[i], self.points[j]);
+            let ab = vec2_sub(b, a);
+            let t = (vec2_dot(vec2_sub(p, a), ab) / vec2_square_len(ab)).clamp(0.0, 1.0);
+
+            let distance_squared = vec2_square_len(vec2_sub(p, vec2_add(a, vec2_scale(ab, t))));
+            if distance_squared < nearest_distance_squared {
+                nearest_distance_squared = distance_squared;
+                *weights = [(i, 1.0 - t), (j, t), (0, 0.0)];
+            }
+        }
+    }
+}
+
+/// The barycentric weights of `p` relative to the triangle `a`, `b`, `c`, which mustn't
+/// be degenerate
+fn barycentric_weights(a: [f32; 2], b: [f32; 2], c: [f32; 2], p: [f32; 2]) -> [f32; 3] {
+    let area = (b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1]);
+    let w_b = ((p[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (p[1] - a[1])) / area;
+    let w_c = ((b[0] - a[0]) * (p[1] - a[1]) - (p[0] - a[0]) * (b[1] - a[1])) / area;
+    [1.0 - w_b - w_c, w_b, w_c]
+}
+
+/// An AnimNode where pose output is a fixed pose, e.g. the skeleton's bind pose
+pub struct PoseAnimNode<T: Transform> {
+    poses: Vec<T>
+}
+
+impl<T: Transform> AnimNode<T> for PoseAnimNode<T> {
+    fn get_output_pose(&self, _tree: &AnimBlendTree<T>, _time: f32, _params: &ParameterSet, _buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
+        for (output_pose, pose) in output_poses.iter_mut().zip(self.poses.iter()) {
+            *output_pose = *pose;
+        }
     }
 }
 
 pub struct IKNode {
     input: AnimNodeHandle,
-    blend_param: ParamId,
-    target_x_param: ParamId,
-    target_y_param: ParamId,
-    target_z_param: ParamId,
-    bend_x_param: ParamId,
-    bend_y_param: ParamId,
-    bend_z_param: ParamId,
+    blend_param: ParamSlot,
+    target_x_param: ParamSlot,
+    target_y_param: ParamSlot,
+    target_z_param: ParamSlot,
+    bend_x_param: ParamSlot,
+    bend_y_param: ParamSlot,
+    bend_z_param: ParamSlot,
     effector_bone_index: JointIndex,
 }
 
 impl<T: Transform> AnimNode<T> for IKNode {
-    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &HashMap<String, f32>, output_poses: &mut [T]) {
+    fn get_output_pose(&self, tree: &AnimBlendTree<T>, time: f32, params: &ParameterSet, buffers: &mut PoseBuffers<T>, output_poses: &mut [T]) {
 
         // Get input pose
-        if let Some(ref node) = tree.get_node(self.input.clone()) {
-            node.get_output_pose(tree, time, params, output_poses);
+        if let Some(node) = tree.get_node(self.input.clone()) {
+            node.get_output_pose(tree, time, params, buffers, output_poses);
         }
 
         // Target position should be in model-space
-        let effector_target_position = [params[&self.target_x_param[..]],
-                                        params[&self.target_y_param[..]],
-                                        params[&self.target_z_param[..]]];
+        let effector_target_position = [tree.get_param(params, self.target_x_param),
+                                        tree.get_param(params, self.target_y_param),
+                                        tree.get_param(params, self.target_z_param)];
 
 
         let effector_bone_index = self.effector_bone_index;
@@ -374,8 +1673,8 @@ impl<T: Transform> AnimNode<T> for IKNode {
         let root_bone_parent_index = tree.skeleton.joints[root_bone_index as usize].parent_index;
 
         // Get bone positions in model-space by calculating global poses
-        let mut global_poses = [ Matrix4::<f32>::identity(); 64 ];
-        tree.skeleton.calculate_global_poses(output_poses, &mut global_poses);
+        let mut global_poses = buffers.take_matrices(tree.skeleton.joints.len());
+        tree.skeleton.calculate_global_poses(output_poses, &mut global_poses[..]);
 
         let root_bone_position = global_poses[root_bone_index as usize].transform_vector([0.0, 0.0, 0.0]);
         let middle_bone_position = global_poses[middle_bone_index as usize].transform_vector([0.0, 0.0, 0.0]);
@@ -392,9 +1691,9 @@ impl<T: Transform> AnimNode<T> for IKNode {
 
         // z axis of bend plane
         let plane_normal = {
-            let bend_direction = [params[&self.bend_x_param[..]],
-                                  params[&self.bend_y_param[..]],
-                                  params[&self.bend_z_param[..]]];
+            let bend_direction = [tree.get_param(params, self.bend_x_param),
+                                  tree.get_param(params, self.bend_y_param),
+                                  tree.get_param(params, self.bend_z_param)];
             if vec3_len(bend_direction) == 0.0 {
                 // Choose a somewhat arbitary bend normal:
                 vec3_normalized(vec3_cross(vec3_sub(middle_bone_position, root_bone_position),
@@ -424,10 +1723,8 @@ impl<T: Transform> AnimNode<T> for IKNode {
         if let Some(elbow_target) = solve_ik_2d(length_1, length_2, [plane_target[0], plane_target[1]]) {
 
             // Copy input poses into IK target poses
-            let mut target_poses = [ T::identity(); 64 ];
-            for i in 0 .. 64 {
-                target_poses[i] = output_poses[i];
-            }
+            let mut target_poses = buffers.take(output_poses.len());
+            target_poses.copy_from_slice(output_poses);
 
             let middle_bone_plane = [elbow_target[0], elbow_target[1], 0.0];
 
@@ -464,14 +1761,881 @@ impl<T: Transform> AnimNode<T> for IKNode {
                 target_poses[middle_bone_index as usize] = T::from_matrix(local_pose);
             }
 
+            // Keep within any limits on the bones
+            tree.skeleton.joint_limits.clamp_pose(root_bone_index, &mut target_poses[root_bone_index as usize]);
+            tree.skeleton.joint_limits.clamp_pose(middle_bone_index, &mut target_poses[middle_bone_index as usize]);
+
             // Blend between input and IK target poses
 
-            let blend_parameter = params[&self.blend_param[..]];
-            for i in 0 .. output_poses.len() {
-                let ik_pose = target_poses[i];
-                let output_pose = &mut output_poses[i];
-                (*output_pose) = output_pose.lerp(ik_pose.clone(), blend_parameter);
+            let blend_parameter = tree.get_param(params, self.blend_param);
+            for (ik_pose, output_pose) in target_poses.iter().zip(output_poses.iter_mut()) {
+                *output_pose = output_pose.lerp(*ik_pose, blend_parameter);
             }
+
+            buffers.give(target_poses);
         }
+
+        buffers.give_matrices(global_poses);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::{HashMap, HashSet};
+
+    use serde_json;
+
+    use Shared;
+    use animation::{AnimationClip, AnimationClipBuilder, ChannelMask};
+    use math::*;
+    use parameter::ParameterSet;
+    use skeleton::{Joint, Skeleton};
+    use transform::{Transform, QVTransform};
+
+    use super::{AnimBlendTree, BlendTreeNodeDef, BlendTreeError, BlendSpaceSampleDef, ClipId, DefinitionId, ParamId, PoseBuffers, WeightedInputDef, MAX_BLEND_TREE_DEPTH, BlendSpaceTriangulation};
+
+    static EPSILON: f32 = 0.0001;
+
+    /// A single-joint skeleton, and a clip per (name, sample count, x translation per sample)
+    fn make_animations(clips: &[(&str, usize, f32)]) -> (Shared<Skeleton>, HashMap<String, Shared<AnimationClip<QVTransform>>>) {
+
+        let skeleton = Shared::new(Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+        ]));
+
+        let animations = clips.iter().map(|&(name, sample_count, step)| {
+            let mut builder = AnimationClipBuilder::new().with_samples_per_second(10.0);
+            for i in 0 .. sample_count {
+                let mut pose = QVTransform::identity();
+                pose.set_translation([i as f32 * step, 0.0, 0.0]);
+                builder.push_sample(vec![pose]);
+            }
+            let clip = builder.build().unwrap();
+            (name.to_string(), Shared::new(clip))
+        }).collect();
+
+        (skeleton, animations)
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+
+        let source = r#"{
+            "type": "LerpNode",
+            "param": "forward-to-strafe",
+            "inputs": [{
+                "type": "LerpNode",
+                "param": "walk-to-run",
+                "inputs": [{
+                    "type": "ClipNode",
+                    "clip_source": "walk-forward"
+                }, {
+                    "type": "ClipNode",
+                    "clip_source": "run-forward"
+                }]
+            }, {
+                "type": "ClipNode",
+                "clip_source": "walk-left",
+                "loop": "pingpong"
+            }]
+        }"#;
+
+        let def: BlendTreeNodeDef = serde_json::from_str(source).unwrap();
+        let encoded = serde_json::to_string(&def).unwrap();
+
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&encoded[..]).unwrap(),
+                   serde_json::from_str::<serde_json::Value>(source).unwrap());
+        assert_eq!(serde_json::from_str::<BlendTreeNodeDef>(&encoded[..]).unwrap(), def);
+    }
+
+    #[test]
+    fn test_validate() {
+
+        let clip = |name: &str| Box::new(BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None });
+
+        let def = BlendTreeNodeDef::LerpNode {
+            inputs: (clip("walk"), Box::new(BlendTreeNodeDef::LerpNode {
+                inputs: (clip("rnu"), clip("rnu")),
+                param: "speed".to_string(),
+                phase_param: None,
+            })),
+            param: "walk-to-run".to_string(),
+            phase_param: None,
+        };
+
+        let mut clips = HashMap::new();
+        clips.insert("walk".to_string(), ());
+        clips.insert("run".to_string(), ());
+
+        let mut params = HashSet::new();
+        params.insert("walk-to-run".to_string());
+
+        assert_eq!(def.validate(&clips, &params), Err(vec![
+            BlendTreeError::MissingClip("rnu".to_string()),
+            BlendTreeError::UnknownParam("speed".to_string()),
+        ]));
+
+        params.insert("speed".to_string());
+        clips.insert("rnu".to_string(), ());
+        assert_eq!(def.validate(&clips, &params), Ok(()));
+    }
+
+    #[test]
+    fn test_referenced_params_and_clips() {
+
+        let clip = |name: &str| BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None };
+
+        let def = BlendTreeNodeDef::SpeedNode {
+            input: Box::new(BlendTreeNodeDef::LerpNode {
+                inputs: (Box::new(clip("walk")), Box::new(BlendTreeNodeDef::BlendSpace2D {
+                    x_param: "x".to_string(),
+                    y_param: "y".to_string(),
+                    samples: vec![
+                        BlendSpaceSampleDef { x: 0.0, y: 0.0, node: clip("walk") },
+                        BlendSpaceSampleDef { x: 1.0, y: 0.0, node: BlendTreeNodeDef::PoseNode { pose_source: Some("idle".to_string()) } },
+                    ],
+                })),
+                param: "blend".to_string(),
+                phase_param: Some("phase".to_string()),
+            }),
+            param: "speed".to_string(),
+        };
+
+        let expected_params: HashSet<ParamId> = ["x", "y", "blend", "phase", "speed"].iter().map(|s| s.to_string()).collect();
+        let expected_clips: HashSet<ClipId> = ["walk", "idle"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(def.referenced_params(), expected_params);
+        assert_eq!(def.referenced_clips(), expected_clips);
+    }
+
+    #[test]
+    fn test_lerp_node_phase() {
+
+        let (skeleton, animations) = make_animations(&[("walk", 10, 1.0), ("run", 5, 2.0)]);
+
+        let clip = |name: &str| Box::new(BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None });
+        let def = BlendTreeNodeDef::LerpNode {
+            inputs: (clip("walk"), clip("run")),
+            param: "walk-to-run".to_string(),
+            phase_param: Some("phase".to_string()),
+        };
+
+        let mut params = ParameterSet::new();
+        params.declare("walk-to-run");
+        params.declare("phase");
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+        let mut poses = [QVTransform::identity(); 1];
+
+        // Both clips are halfway through at phase 0.5, whatever the controller time
+        for &blend in [0.0, 0.5, 1.0].iter() {
+            params.set("walk-to-run", blend);
+            params.set("phase", 0.5);
+            tree.get_output_pose(0.1, &params, &mut poses);
+            assert!((poses[0].get_translation()[0] - 5.0).abs() < EPSILON);
+        }
+
+        params.set("phase", 0.2);
+        tree.get_output_pose(0.1, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 2.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_clip_node_phase() {
+
+        let (skeleton, animations) = make_animations(&[("reload", 10, 1.0)]);
+
+        let def: BlendTreeNodeDef = serde_json::from_str(r#"{
+            "type": "ClipNode",
+            "clip_source": "reload",
+            "loop": "clamp",
+            "phase_param": "reload-phase"
+        }"#).unwrap();
+        assert_eq!(def.referenced_params().into_iter().collect::<Vec<_>>(), vec!["reload-phase".to_string()]);
+
+        let mut params = ParameterSet::new();
+        params.declare("reload-phase");
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+        let mut poses = [QVTransform::identity(); 1];
+
+        // Halfway through the clip at phase 0.5, whatever the controller time
+        params.set("reload-phase", 0.5);
+        for &time in [0.1, 3.7].iter() {
+            tree.get_output_pose(time, &params, &mut poses);
+            assert!((poses[0].get_translation()[0] - 5.0).abs() < EPSILON);
+        }
+        assert_eq!(tree.get_phase(3.7, &params), Some(0.5));
+        assert!(!tree.is_finished(3.7, &params));
+
+        // Phases are clamped, holding the last sample at the end
+        params.set("reload-phase", 1.5);
+        tree.get_output_pose(0.1, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 9.0).abs() < EPSILON);
+        assert!(tree.is_finished(0.1, &params));
+
+        params.set("reload-phase", -1.0);
+        tree.get_output_pose(0.1, &params, &mut poses);
+        assert!(poses[0].get_translation()[0].abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_pose_node() {
+
+        let (skeleton, animations) = make_animations(&[("walk", 10, 1.0)]);
+
+        let def = BlendTreeNodeDef::LerpNode {
+            inputs: (
+                Box::new(BlendTreeNodeDef::PoseNode { pose_source: None }),
+                Box::new(BlendTreeNodeDef::ClipNode { clip_source: "walk".to_string(), loop_mode: None, phase_param: None }),
+            ),
+            param: "blend".to_string(),
+            phase_param: None,
+        };
+
+        let mut params = ParameterSet::new();
+        params.declare("blend");
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+        params.set("blend", 0.5);
+
+        // Halfway between the bind pose at the origin and the clip pose at x = 4.0
+        let mut poses = [QVTransform::identity(); 1];
+        tree.get_output_pose(0.4, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 2.0).abs() < EPSILON);
+
+        let def = BlendTreeNodeDef::PoseNode { pose_source: Some("walk".to_string()) };
+        let (skeleton, _) = make_animations(&[]);
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+        tree.get_output_pose(0.4, &params, &mut poses);
+        assert!(poses[0].get_translation()[0].abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_blend_space_2d_weights() {
+
+        let points = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]];
+        let weight_of = |weights: &[(usize, f32)], index: usize| -> f32 {
+            weights.iter().filter(|&&(i, _)| i == index).map(|&(_, weight)| weight).sum()
+        };
+
+        // The square is split into two triangles along one diagonal, with four hull edges
+        let triangulation = BlendSpaceTriangulation::new(points.to_vec());
+        assert_eq!(triangulation.triangles, vec![[0, 1, 3], [0, 2, 3]]);
+        assert_eq!(triangulation.hull_edges.len(), 4);
+
+        // The weights buffer is overwritten on each call
+        let mut weights = [(0, 0.0); 3];
+
+        // Inside: barycentric weights summing to one
+        triangulation.weights([0.25, 0.5], &mut weights);
+        assert!((weights.iter().map(|&(_, weight)| weight).sum::<f32>() - 1.0).abs() < EPSILON);
+        assert!((weight_of(&weights, 0) - 0.5).abs() < EPSILON);
+        assert!((weight_of(&weights, 2) - 0.25).abs() < EPSILON);
+        assert!((weight_of(&weights, 3) - 0.25).abs() < EPSILON);
+
+        // On a sample
+        triangulation.weights([1.0, 1.0], &mut weights);
+        assert!((weight_of(&weights, 3) - 1.0).abs() < EPSILON);
+
+        // Outside: clamped to the nearest edge
+        triangulation.weights([0.5, -2.0], &mut weights);
+        assert!((weight_of(&weights, 0) - 0.5).abs() < EPSILON);
+        assert!((weight_of(&weights, 1) - 0.5).abs() < EPSILON);
+
+        // Collinear samples have no triangles, so blend along the line between neighbours
+        let triangulation = BlendSpaceTriangulation::new(vec![[0.0, 0.0], [2.0, 0.0], [1.0, 0.0]]);
+        assert!(triangulation.triangles.is_empty());
+        assert_eq!(triangulation.hull_edges, vec![[0, 2], [1, 2]]);
+        triangulation.weights([1.5, 1.0], &mut weights);
+        assert!((weight_of(&weights, 1) - 0.5).abs() < EPSILON);
+        assert!((weight_of(&weights, 2) - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_blend_space_2d_node() {
+
+        let (skeleton, animations) = make_animations(&[("forward", 10, 1.0), ("left", 10, 2.0), ("right", 10, 4.0)]);
+
+        let sample = |x: f32, y: f32, name: &str| BlendSpaceSampleDef {
+            x, y, node: BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None },
+        };
+
+        let def = BlendTreeNodeDef::BlendSpace2D {
+            x_param: "strafe".to_string(),
+            y_param: "speed".to_string(),
+            samples: vec![sample(0.0, 1.0, "forward"), sample(-1.0, 0.0, "left"), sample(1.0, 0.0, "right")],
+        };
+
+        let mut params = ParameterSet::new();
+        params.declare("strafe");
+        params.declare("speed");
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+        let mut poses = [QVTransform::identity(); 1];
+
+        // At time 0.5 the clips are at x = 5.0, 10.0 and 20.0
+        params.set("strafe", 0.0);
+        params.set("speed", 0.0);
+        tree.get_output_pose(0.5, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 15.0).abs() < EPSILON);
+
+        params.set("speed", 1.0);
+        tree.get_output_pose(0.5, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 5.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_synchronize_zero_duration_clip() {
+
+        let (skeleton, mut animations) = make_animations(&[("walk", 10, 1.0)]);
+        let mut clip = AnimationClipBuilder::new().with_samples_per_second(10.0);
+        clip.push_sample(vec![QVTransform::identity()]);
+        let mut clip = clip.build().unwrap();
+        clip.sample_times = Some(vec![0.0]);
+        assert_eq!(clip.get_duration(), 0.0);
+        animations.insert("pose".to_string(), Shared::new(clip));
+
+        let def = BlendTreeNodeDef::LerpNode {
+            inputs: (
+                Box::new(BlendTreeNodeDef::ClipNode { clip_source: "walk".to_string(), loop_mode: None, phase_param: None }),
+                Box::new(BlendTreeNodeDef::ClipNode { clip_source: "pose".to_string(), loop_mode: None, phase_param: None }),
+            ),
+            param: "blend".to_string(),
+            phase_param: None,
+        };
+
+        let mut params = ParameterSet::new();
+        params.declare("blend");
+        let mut tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+
+        // Fully blended to the clip without any duration, which would otherwise play the
+        // other clip at an infinite rate
+        params.set("blend", 1.0);
+        tree.synchronize(0.5, &params);
+        for clip_node in tree.clip_nodes.iter() {
+            assert_eq!(clip_node.clip.playback_rate, 1.0);
+        }
+
+        let mut poses = [QVTransform::identity(); 1];
+        params.set("blend", 0.5);
+        tree.synchronize(0.5, &params);
+        tree.get_output_pose(0.5, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 2.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_lerp_node_ends_keep_masked_channels() {
+
+        let skeleton = Shared::new(Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+        ]));
+
+        // Both clips only drive the rotation, turned about z by 0.0 and 1.0 radians
+        let animations: HashMap<String, Shared<AnimationClip<QVTransform>>> = [("a", 0.0), ("b", 1.0)].iter().map(|&(name, angle)| {
+            let mut pose = QVTransform::identity();
+            pose.set_rotation(quaternion_from_axis_angle([0.0, 0.0, 1.0], angle));
+            pose.set_translation([5.0, 0.0, 0.0]);
+            let mut builder = AnimationClipBuilder::new();
+            builder.push_sample(vec![pose]).push_sample(vec![pose]);
+            let mut clip = builder.build().unwrap();
+            clip.channel_masks = Some(vec![ChannelMask { translation: false, rotation: true, scale: true }]);
+            (name.to_string(), Shared::new(clip))
+        }).collect();
+
+        let def = BlendTreeNodeDef::LerpNode {
+            inputs: (
+                Box::new(BlendTreeNodeDef::ClipNode { clip_source: "a".to_string(), loop_mode: None, phase_param: None }),
+                Box::new(BlendTreeNodeDef::ClipNode { clip_source: "b".to_string(), loop_mode: None, phase_param: None }),
+            ),
+            param: "blend".to_string(),
+            phase_param: None,
+        };
+
+        let mut params = ParameterSet::new();
+        params.declare("blend");
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+
+        let mut sample = |blend: f32| {
+            let mut poses = [QVTransform::identity(); 1];
+            poses[0].set_translation([1.0, 2.0, 3.0]);
+            params.set("blend", blend);
+            tree.get_output_pose(0.0, &params, &mut poses);
+            poses[0]
+        };
+
+        // The undriven translation is the same at the end of the blend as just inside it
+        let (end_pose, inside_pose) = (sample(0.0), sample(0.0001));
+        assert!(vec3_len(vec3_sub(end_pose.translation, inside_pose.translation)) < EPSILON);
+        assert!(quaternion::len(quaternion::add(end_pose.rotation, quaternion::scale(inside_pose.rotation, -1.0))) < 0.001);
+    }
+
+    #[test]
+    fn test_weighted_blend_node() {
+
+        let (skeleton, animations) = make_animations(&[("a", 10, 1.0), ("b", 10, 2.0), ("c", 10, 4.0)]);
+
+        let input = |name: &str, param: &str| WeightedInputDef {
+            node: BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None },
+            param: param.to_string(),
+        };
+
+        let def = BlendTreeNodeDef::WeightedBlendNode {
+            inputs: vec![input("a", "weight_a"), input("b", "weight_b"), input("c", "weight_c")],
+        };
+        assert_eq!(def.referenced_params().len(), 3);
+
+        let mut params = ParameterSet::new();
+        for name in ["weight_a", "weight_b", "weight_c"].iter() {
+            params.declare(name);
+        }
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+        let mut poses = [QVTransform::identity(); 1];
+
+        // At time 0.5 the clips are at x = 5.0, 10.0 and 20.0. Weights are normalized.
+        params.set("weight_a", 2.0);
+        params.set("weight_b", 1.0);
+        params.set("weight_c", 1.0);
+        tree.get_output_pose(0.5, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 10.0).abs() < EPSILON);
+
+        // Negative weights count as zero
+        params.set("weight_a", -1.0);
+        params.set("weight_b", 0.0);
+        tree.get_output_pose(0.5, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 20.0).abs() < EPSILON);
+
+        // With no weight at all, inputs are averaged equally
+        params.set("weight_c", 0.0);
+        tree.get_output_pose(0.5, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 35.0 / 3.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_weighted_blend_node_is_order_independent() {
+
+        let skeleton = Shared::new(Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+        ]));
+
+        // Constant poses, with "c" the same rotation as "b" but of the opposite sign
+        let rotation_b = quaternion_from_axis_angle([1.0, 0.0, 0.0], 0.8);
+        let poses = [
+            ("a", quaternion_from_axis_angle([0.0, 0.0, 1.0], 0.3), [1.0, 0.0, 0.0], 1.0),
+            ("b", rotation_b, [0.0, 2.0, 0.0], 2.0),
+            ("c", quaternion::scale(rotation_b, -1.0), [0.0, 0.0, 4.0], 0.5),
+        ];
+        let animations: HashMap<String, Shared<AnimationClip<QVTransform>>> = poses.iter().map(|&(name, rotation, translation, scale)| {
+            let pose = QVTransform { translation, scale, rotation };
+            let mut builder = AnimationClipBuilder::new();
+            builder.push_sample(vec![pose]).push_sample(vec![pose]);
+            let clip = builder.build().unwrap();
+            (name.to_string(), Shared::new(clip))
+        }).collect();
+
+        let mut params = ParameterSet::new();
+        params.declare("a");
+        params.declare("b");
+        params.declare("c");
+        params.set("a", 0.5);
+        params.set("b", 0.3);
+        params.set("c", 0.2);
+
+        let blend = |order: &[&str]| {
+            let def = BlendTreeNodeDef::WeightedBlendNode {
+                inputs: order.iter().map(|&name| WeightedInputDef {
+                    node: BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None },
+                    param: name.to_string(),
+                }).collect(),
+            };
+            let tree = AnimBlendTree::from_def(def, &animations, skeleton.clone(), &params).unwrap();
+            let mut output_poses = [QVTransform::identity(); 1];
+            tree.get_output_pose(0.0, &params, &mut output_poses);
+            output_poses[0]
+        };
+
+        let pose = blend(&["a", "b", "c"]);
+
+        // Translation and scale are weighted sums
+        assert!(vec3_len(vec3_sub(pose.translation, [0.5, 0.6, 0.8])) < EPSILON);
+        assert!((pose.scale - 1.2).abs() < EPSILON);
+
+        for order in [["c", "a", "b"], ["b", "c", "a"], ["c", "b", "a"]].iter() {
+            let reordered = blend(&order[..]);
+            assert!(vec3_len(vec3_sub(reordered.translation, pose.translation)) < EPSILON);
+            assert!((reordered.scale - pose.scale).abs() < EPSILON);
+            assert!(quaternion::dot(reordered.rotation, pose.rotation).abs() > 1.0 - EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_ref_node() {
+
+        let (skeleton, animations) = make_animations(&[("walk", 10, 1.0), ("run", 10, 2.0)]);
+
+        let definitions: HashMap<DefinitionId, BlendTreeNodeDef> = serde_json::from_str(r#"{
+            "locomotion": {
+                "type": "LerpNode",
+                "param": "speed",
+                "inputs": [
+                    { "type": "ClipNode", "clip_source": "walk" },
+                    { "type": "ClipNode", "clip_source": "run" }
+                ]
+            }
+        }"#).unwrap();
+
+        // Both inputs reference the same definition, so share its nodes
+        let def: BlendTreeNodeDef = serde_json::from_str(r#"{
+            "type": "AdditiveNode",
+            "param": "lean",
+            "inputs": [
+                { "type": "RefNode", "ref": "locomotion" },
+                { "type": "RefNode", "ref": "locomotion" }
+            ]
+        }"#).unwrap();
+
+        let mut params = ParameterSet::new();
+        params.declare("speed");
+        params.declare("lean");
+        params.set("speed", 0.5);
+
+        assert_eq!(def.validate_with_definitions(&definitions, &animations, &params.iter().map(|(name, _)| name.to_string()).collect()), Ok(()));
+
+        let tree = AnimBlendTree::from_def_with_definitions(def, &definitions, &animations, skeleton.clone(), &params).unwrap();
+        assert_eq!(tree.lerp_nodes.len(), 1);
+        assert_eq!(tree.clip_nodes.len(), 2);
+
+        let inlined = AnimBlendTree::from_def(definitions["locomotion"].clone(), &animations, skeleton, &params).unwrap();
+        let mut poses = [QVTransform::identity(); 1];
+        let mut inlined_poses = [QVTransform::identity(); 1];
+        tree.get_output_pose(0.5, &params, &mut poses);
+        inlined.get_output_pose(0.5, &params, &mut inlined_poses);
+        assert_eq!(poses[0].get_translation(), inlined_poses[0].get_translation());
+    }
+
+    #[test]
+    fn test_max_depth() {
+
+        let (skeleton, animations) = make_animations(&[("walk", 10, 1.0)]);
+
+        // A clip under `depth - 1` nested speed nodes
+        let nested = |depth: usize| (1 .. depth).fold(
+            BlendTreeNodeDef::ClipNode { clip_source: "walk".to_string(), loop_mode: None, phase_param: None },
+            |input, _| BlendTreeNodeDef::SpeedNode { input: Box::new(input), param: "speed".to_string() },
+        );
+
+        let params = ParameterSet::new();
+        let definitions = HashMap::new();
+        assert!(AnimBlendTree::from_def_with_max_depth(nested(10), &definitions, &animations, skeleton.clone(), &params, 10).is_ok());
+        assert_eq!(AnimBlendTree::from_def_with_max_depth(nested(11), &definitions, &animations, skeleton.clone(), &params, 10).err(), Some(BlendTreeError::TooDeep));
+
+        // References count towards the depth
+        let mut definitions = HashMap::new();
+        definitions.insert("walk".to_string(), nested(9));
+        let def = BlendTreeNodeDef::RefNode { definition: "walk".to_string() };
+        assert!(AnimBlendTree::from_def_with_max_depth(def.clone(), &definitions, &animations, skeleton.clone(), &params, 10).is_ok());
+        assert!(AnimBlendTree::from_def_with_max_depth(def, &definitions, &animations, skeleton.clone(), &params, 9).is_err());
+
+        // A definition built shallow is still charged its depth when referenced again deeper
+        let mut definitions = HashMap::new();
+        definitions.insert("walk".to_string(), nested(5));
+        let reference = || Box::new(BlendTreeNodeDef::RefNode { definition: "walk".to_string() });
+        let deep_reference = (0 .. 3).fold(*reference(), |input, _| BlendTreeNodeDef::SpeedNode { input: Box::new(input), param: "speed".to_string() });
+        let def = BlendTreeNodeDef::LerpNode { inputs: (reference(), Box::new(deep_reference)), param: "blend".to_string(), phase_param: None };
+        assert!(AnimBlendTree::from_def_with_max_depth(def.clone(), &definitions, &animations, skeleton.clone(), &params, 10).is_ok());
+        assert_eq!(AnimBlendTree::from_def_with_max_depth(def.clone(), &definitions, &animations, skeleton.clone(), &params, 9).err(), Some(BlendTreeError::TooDeep));
+
+        // Validation agrees with building about the same shared definition
+        definitions.insert("walk".to_string(), nested(MAX_BLEND_TREE_DEPTH - 4));
+        let mut available_params = HashSet::new();
+        available_params.insert("speed".to_string());
+        available_params.insert("blend".to_string());
+        assert_eq!(def.validate_with_definitions(&definitions, &animations, &available_params), Err(vec![BlendTreeError::TooDeep]));
+        assert_eq!(AnimBlendTree::from_def_with_max_depth(def, &definitions, &animations, skeleton, &params, MAX_BLEND_TREE_DEPTH).err(), Some(BlendTreeError::TooDeep));
+
+        // Decoding with a limit
+        let json = r#"{ "type": "SpeedNode", "param": "speed", "input": { "type": "ClipNode", "clip_source": "walk" } }"#;
+        assert!(BlendTreeNodeDef::from_json_str_with_max_depth(json, 2).is_ok());
+        assert_eq!(BlendTreeNodeDef::from_json_str_with_max_depth(json, 1), Err(BlendTreeError::TooDeep));
+        assert!(matches!(BlendTreeNodeDef::from_json_str("{"), Err(BlendTreeError::Parse(_))));
+
+        let too_deep = nested(MAX_BLEND_TREE_DEPTH + 1);
+        let mut available_params = HashSet::new();
+        available_params.insert("speed".to_string());
+        assert_eq!(too_deep.validate(&animations, &available_params), Err(vec![BlendTreeError::TooDeep]));
+        assert_eq!(too_deep.referenced_params().len(), 1);
+        assert!(nested(MAX_BLEND_TREE_DEPTH).validate(&animations, &available_params).is_ok());
+    }
+
+    #[test]
+    fn test_validate_definitions() {
+
+        let reference = |name: &str| BlendTreeNodeDef::RefNode { definition: name.to_string() };
+
+        let mut definitions = HashMap::new();
+        definitions.insert("a".to_string(), BlendTreeNodeDef::SelectNode {
+            inputs: vec![reference("b"), reference("missing")],
+            param: "select".to_string(),
+        });
+        definitions.insert("b".to_string(), reference("a"));
+
+        let mut params = HashSet::new();
+        params.insert("select".to_string());
+        let clips: HashMap<ClipId, ()> = HashMap::new();
+
+        assert_eq!(reference("a").validate_with_definitions(&definitions, &clips, &params), Err(vec![
+            BlendTreeError::RecursiveDefinition("a".to_string()),
+            BlendTreeError::MissingDefinition("missing".to_string()),
+        ]));
+        assert_eq!(reference("a").validate(&clips, &params), Err(vec![
+            BlendTreeError::MissingDefinition("a".to_string()),
+        ]));
+
+        // Building reports the same problems rather than panicking
+        let (skeleton, animations) = make_animations(&[("walk", 10, 1.0)]);
+        let parameters = ParameterSet::new();
+        let build = |def: BlendTreeNodeDef, definitions: &HashMap<DefinitionId, BlendTreeNodeDef>| {
+            AnimBlendTree::from_def_with_max_depth(def, definitions, &animations, skeleton.clone(), &parameters, MAX_BLEND_TREE_DEPTH).err()
+        };
+        assert_eq!(build(reference("a"), &definitions), Some(BlendTreeError::RecursiveDefinition("a".to_string())));
+        assert_eq!(build(reference("a"), &HashMap::new()), Some(BlendTreeError::MissingDefinition("a".to_string())));
+        let missing_clip = BlendTreeNodeDef::ClipNode { clip_source: "run".to_string(), loop_mode: None, phase_param: None };
+        assert_eq!(build(missing_clip, &HashMap::new()), Some(BlendTreeError::MissingClip("run".to_string())));
+        let param = || "ik".to_string();
+        let missing_effector = BlendTreeNodeDef::IKNode {
+            input: Box::new(BlendTreeNodeDef::PoseNode { pose_source: None }),
+            effector: "hand".to_string(),
+            blend_param: param(),
+            target_x_param: param(),
+            target_y_param: param(),
+            target_z_param: param(),
+            bend_x_param: param(),
+            bend_y_param: param(),
+            bend_z_param: param(),
+        };
+        assert_eq!(build(missing_effector, &HashMap::new()), Some(BlendTreeError::MissingJoint { name: "hand".to_string() }));
+
+        // Each definition blends two references to the one before, doubling the size of the
+        // expanded tree at every level, but is only validated once
+        let mut definitions = HashMap::new();
+        definitions.insert("level-0".to_string(), BlendTreeNodeDef::ClipNode { clip_source: "walk".to_string(), loop_mode: None, phase_param: None });
+        for level in 1 .. 60 {
+            let previous = || Box::new(reference(&format!("level-{}", level - 1)));
+            definitions.insert(format!("level-{}", level), BlendTreeNodeDef::LerpNode {
+                inputs: (previous(), previous()),
+                param: "blend".to_string(),
+                phase_param: None,
+            });
+        }
+        params.insert("blend".to_string());
+        assert!(reference("level-59").validate_with_definitions(&definitions, &animations, &params).is_ok());
+        assert!(build(reference("level-59"), &definitions).is_none());
+    }
+
+    #[test]
+    fn test_select_node() {
+
+        let (skeleton, animations) = make_animations(&[("rifle", 10, 1.0), ("pistol", 10, 2.0), ("unarmed", 10, 4.0)]);
+
+        let clip = |name: &str| BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None };
+        let def = BlendTreeNodeDef::SelectNode {
+            inputs: vec![clip("rifle"), clip("pistol"), clip("unarmed")],
+            param: "weapon".to_string(),
+        };
+
+        let mut params = ParameterSet::new();
+        params.declare("weapon");
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+        let mut poses = [QVTransform::identity(); 1];
+
+        // At time 0.5 the clips are at x = 5.0, 10.0 and 20.0. Values are rounded and clamped.
+        for &(weapon, x) in [(0.0, 5.0), (1.4, 10.0), (1.6, 20.0), (7.0, 20.0), (-3.0, 5.0)].iter() {
+            params.set("weapon", weapon);
+            tree.get_output_pose(0.5, &params, &mut poses);
+            assert!((poses[0].get_translation()[0] - x).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_noise_node() {
+
+        let (skeleton, animations) = make_animations(&[("idle", 10, 1.0)]);
+
+        let def = |seed: u32, joint_amplitudes: Option<HashMap<String, f32>>| BlendTreeNodeDef::NoiseNode {
+            input: Box::new(BlendTreeNodeDef::ClipNode { clip_source: "idle".to_string(), loop_mode: None, phase_param: None }),
+            amplitude: 0.1,
+            frequency: 2.0,
+            seed,
+            joint_amplitudes,
+        };
+
+        let params = ParameterSet::new();
+        let sample = |def: BlendTreeNodeDef, time: f32| {
+            let tree = AnimBlendTree::from_def(def, &animations, skeleton.clone(), &params).unwrap();
+            let mut poses = [QVTransform::identity(); 1];
+            tree.get_output_pose(time, &params, &mut poses);
+            poses[0]
+        };
+
+        // Noise only affects rotation, and stays within the amplitude
+        let pose = sample(def(7, None), 0.3);
+        assert!((pose.get_translation()[0] - 3.0).abs() < EPSILON);
+        let angle = 2.0 * pose.rotation.0.min(1.0).acos();
+        assert!(angle > 0.0 && angle <= 0.1 * 3.0f32.sqrt() + EPSILON);
+
+        // The same seed gives the same noise, and different seeds differ
+        assert_eq!(pose.rotation, sample(def(7, None), 0.3).rotation);
+        assert!(pose.rotation != sample(def(8, None), 0.3).rotation);
+
+        // Joints that aren't listed in the joint amplitudes aren't perturbed
+        let pose = sample(def(7, Some(HashMap::new())), 0.3);
+        let clip_pose = sample(BlendTreeNodeDef::ClipNode { clip_source: "idle".to_string(), loop_mode: None, phase_param: None }, 0.3);
+        assert_eq!(pose.rotation, clip_pose.rotation);
+
+        // Names missing from the skeleton are ignored
+        let mut joint_amplitudes = HashMap::new();
+        joint_amplitudes.insert("tail".to_string(), 1.0);
+        assert_eq!(sample(def(7, Some(joint_amplitudes)), 0.3).rotation, clip_pose.rotation);
+    }
+
+    #[test]
+    fn test_bake() {
+
+        let (skeleton, animations) = make_animations(&[("walk", 10, 1.0), ("run", 10, 3.0)]);
+
+        let clip = |name: &str| Box::new(BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None });
+        let def = BlendTreeNodeDef::LerpNode {
+            inputs: (clip("walk"), clip("run")),
+            param: "walk-to-run".to_string(),
+            phase_param: None,
+        };
+
+        let mut params = ParameterSet::new();
+        params.declare("walk-to-run");
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+        params.set("walk-to-run", 0.5);
+
+        let baked = tree.bake(&params, 20.0, 0.5);
+        assert_eq!(baked.samples.len(), 10);
+        assert!((baked.get_duration() - 0.5).abs() < EPSILON);
+
+        // Sample 3 is at 0.15 seconds, halfway between x = 1.5 and x = 4.5
+        assert!((baked.samples[3].local_poses[0].get_translation()[0] - 3.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_pose_buffers() {
+
+        // More joints than the old fixed-size pose arrays could hold
+        let joint_count = 100;
+        let skeleton = Shared::new(Skeleton::new((0 .. joint_count).map(|i| {
+            Joint::new(&format!("joint-{}", i), if i == 0 { None } else { Some(i as u8 - 1) }, mat4_id())
+        }).collect()));
+
+        let clip = |step: f32| {
+            let mut builder = AnimationClipBuilder::new().with_samples_per_second(10.0);
+            for i in 0 .. 10 {
+                let mut pose = QVTransform::identity();
+                pose.set_translation([i as f32 * step, 0.0, 0.0]);
+                builder.push_sample(vec![pose; joint_count]);
+            }
+            Shared::new(builder.build().unwrap())
+        };
+
+        let mut animations = HashMap::new();
+        animations.insert("walk".to_string(), clip(1.0));
+        animations.insert("run".to_string(), clip(3.0));
+
+        let input = |name: &str| Box::new(BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None });
+        let def = BlendTreeNodeDef::LerpNode {
+            inputs: (input("walk"), input("run")),
+            param: "walk-to-run".to_string(),
+            phase_param: None,
+        };
+
+        let mut params = ParameterSet::new();
+        params.declare("walk-to-run");
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+        params.set("walk-to-run", 0.5);
+
+        let mut buffers = PoseBuffers::new();
+        let mut output_poses = vec![QVTransform::identity(); joint_count];
+        for _ in 0 .. 2 {
+            tree.get_output_pose_with_buffers(0.2, &params, &mut buffers, &mut output_poses[..]);
+            assert!(output_poses.iter().all(|pose| (pose.get_translation()[0] - 4.0).abs() < EPSILON));
+        }
+        assert_eq!(buffers.poses.len(), 2);
+
+        // At either end of the blend, the active input is sampled without scratch poses
+        let mut buffers = PoseBuffers::new();
+        for &(blend, x) in [(0.0, 2.0), (1.0, 6.0)].iter() {
+            params.set("walk-to-run", blend);
+            tree.get_output_pose_with_buffers(0.2, &params, &mut buffers, &mut output_poses[..]);
+            assert!(output_poses.iter().all(|pose| (pose.get_translation()[0] - x).abs() < EPSILON));
+        }
+        assert!(buffers.poses.is_empty());
+    }
+
+    #[test]
+    fn test_speed_node() {
+
+        let (skeleton, animations) = make_animations(&[("walk", 10, 1.0)]);
+
+        let def = BlendTreeNodeDef::SpeedNode {
+            input: Box::new(BlendTreeNodeDef::ClipNode { clip_source: "walk".to_string(), loop_mode: None, phase_param: None }),
+            param: "speed".to_string(),
+        };
+
+        let mut params = ParameterSet::new();
+        params.declare("speed");
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+        params.set("speed", 0.5);
+
+        let mut poses = [QVTransform::identity(); 1];
+        tree.get_output_pose(0.6, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 3.0).abs() < EPSILON);
+
+        // Parameters that weren't declared when the tree was built use the missing value,
+        // even if they're declared later
+        let def = BlendTreeNodeDef::SpeedNode {
+            input: Box::new(BlendTreeNodeDef::ClipNode { clip_source: "walk".to_string(), loop_mode: None, phase_param: None }),
+            param: "speed".to_string(),
+        };
+        let mut tree = AnimBlendTree::from_def(def, &animations, make_animations(&[]).0, &ParameterSet::new()).unwrap();
+        tree.set_missing_param_value(0.25);
+        tree.get_output_pose(0.6, &params, &mut poses);
+        assert!((poses[0].get_translation()[0] - 1.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_collect_events() {
+
+        let (skeleton, mut animations) = make_animations(&[("walk", 10, 1.0)]);
+        let mut walk = (*animations["walk"]).clone();
+        walk.events.push((0.4, "step".to_string()));
+        animations.insert("walk".to_string(), Shared::new(walk));
+
+        let clip = |phase_param: Option<&str>| Box::new(BlendTreeNodeDef::ClipNode {
+            clip_source: "walk".to_string(),
+            loop_mode: None,
+            phase_param: phase_param.map(|phase_param| phase_param.to_string()),
+        });
+        let def = BlendTreeNodeDef::AdditiveNode {
+            inputs: (Box::new(BlendTreeNodeDef::SpeedNode { input: clip(None), param: "speed".to_string() }), clip(Some("phase"))),
+            param: "additive".to_string(),
+        };
+
+        let mut params = ParameterSet::new();
+        for param in ["speed", "phase", "additive"].iter() {
+            params.declare(param);
+        }
+        params.set("speed", 2.0);
+        let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+
+        // The clip under the speed node reaches its event at 0.2 seconds rather than 0.4,
+        // and the phase-driven clip never fires it
+        let collect = |prev_time: f32, curr_time: f32| {
+            let mut events = Vec::new();
+            tree.collect_events(prev_time, curr_time, &params, &mut events);
+            events
+        };
+        assert_eq!(collect(0.1, 0.2), vec!["step"]);
+        assert!(collect(0.3, 0.4).is_empty());
     }
 }
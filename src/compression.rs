@@ -0,0 +1,314 @@
+use serde::{Deserialize, Serialize};
+
+use animation::{AnimationClip, AnimationSample, ChannelMask, Interpolation};
+use math::*;
+use skeleton::Skeleton;
+use transform::Transform;
+
+/// A rotation quantized to its three smallest components, 16 bits each. The largest
+/// component is reconstructed from the others, since the quaternion has unit length.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CompressedRotation {
+    /// Index of the dropped largest component, with 0 for w and 1 to 3 for x, y and z
+    pub largest: u8,
+
+    /// The remaining components in order, quantized over [-1/sqrt(2), 1/sqrt(2)]
+    pub components: [u16; 3],
+}
+
+/// A joint pose quantized to 16 bits per rotation, translation and scale component
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CompressedPose {
+    pub rotation: CompressedRotation,
+
+    /// Offset from the bind pose translation, quantized over the joint's translation range
+    pub translation: [u16; 3],
+
+    /// Scale factor, quantized over the joint's scale range
+    #[serde(default)]
+    pub scale: u16,
+}
+
+/// The range of a joint's translation offsets from its bind pose over a clip, which
+/// quantized translations are relative to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TranslationRange {
+    pub min: Vector3<f32>,
+    pub extent: Vector3<f32>,
+}
+
+/// The range of a joint's scale factor over a clip, which quantized scales are relative to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ScaleRange {
+    pub min: f32,
+    pub extent: f32,
+}
+
+/// An AnimationClip with its poses quantized to roughly half the memory, created with
+/// `AnimationClip::compress`. Poses are reconstructed on top of the skeleton's bind pose,
+/// with translations stored as offsets from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressedAnimationClip<T: Transform> {
+    /// Quantized poses, by sample and then by joint
+    pub samples: Vec<Vec<CompressedPose>>,
+
+    /// The local bind pose of each joint, which decompressed poses start from
+    pub bind_poses: Vec<T>,
+
+    /// The range of each joint's translation offsets from its bind pose
+    pub translation_ranges: Vec<TranslationRange>,
+
+    /// The range of each joint's scale factor. Clips compressed without it take scale
+    /// from the bind pose.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scale_ranges: Vec<ScaleRange>,
+
+    pub samples_per_second: f32,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_times: Option<Vec<f32>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_masks: Option<Vec<ChannelMask>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub joint_interpolations: Option<Vec<Interpolation>>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<(f32, String)>,
+
+    /// Morph weight tracks, kept uncompressed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub morph_weights: Vec<(String, Vec<f32>)>,
+}
+
+const MAX_SMALLEST_COMPONENT: f32 = ::std::f32::consts::FRAC_1_SQRT_2;
+
+fn quantize(value: f32, min: f32, extent: f32) -> u16 {
+    if extent <= 0.0 {
+        return 0;
+    }
+    let t = ((value - min) / extent).clamp(0.0, 1.0);
+    (t * u16::MAX as f32).round() as u16
+}
+
+fn dequantize(value: u16, min: f32, extent: f32) -> f32 {
+    min + (value as f32 / u16::MAX as f32) * extent
+}
+
+fn compress_rotation(q: Quaternion<f32>) -> CompressedRotation {
+    let components = [q.0, q.1[0], q.1[1], q.1[2]];
+
+    let mut largest = 0;
+    for i in 1 .. 4 {
+        if components[i].abs() > components[largest].abs() {
+            largest = i;
+        }
+    }
+
+    // q and -q are the same rotation, so make the dropped component positive
+    let sign = if components[largest] < 0.0 { -1.0 } else { 1.0 };
+
+    let mut quantized = [0; 3];
+    for (q, component) in quantized.iter_mut().zip(components.iter().enumerate().filter(|&(i, _)| i != largest)) {
+        *q = quantize(sign * component.1, -MAX_SMALLEST_COMPONENT, 2.0 * MAX_SMALLEST_COMPONENT);
+    }
+
+    CompressedRotation { largest: largest as u8, components: quantized }
+}
+
+fn decompress_rotation(rotation: CompressedRotation) -> Quaternion<f32> {
+    let largest = rotation.largest as usize;
+    let mut components = [0.0; 4];
+
+    let mut smallest = rotation.components.iter();
+    let mut square_sum = 0.0;
+    for (i, component) in components.iter_mut().enumerate() {
+        if i != largest {
+            *component = dequantize(*smallest.next().unwrap(), -MAX_SMALLEST_COMPONENT, 2.0 * MAX_SMALLEST_COMPONENT);
+            square_sum += *component * *component;
+        }
+    }
+    components[largest] = (1.0 - square_sum).max(0.0).sqrt();
+
+    let len = components.iter().map(|c| c * c).sum::<f32>().sqrt();
+    (components[0] / len, [components[1] / len, components[2] / len, components[3] / len])
+}
+
+impl<T: Transform> CompressedAnimationClip<T> {
+
+    /// Quantize the poses of `clip`, with translations relative to the bind pose of `skeleton`
+    pub fn from_clip(clip: &AnimationClip<T>, skeleton: &Skeleton) -> Self {
+
+        let joint_count = clip.joint_count();
+
+        let skeleton_bind_poses = skeleton.get_local_bind_poses();
+        let bind_poses: Vec<T> = (0 .. joint_count)
+            .map(|joint_index| skeleton_bind_poses.get(joint_index).cloned().unwrap_or_else(T::identity))
+            .collect();
+
+        let offset = |joint_index: usize, pose: T| vec3_sub(pose.get_translation(), bind_poses[joint_index].get_translation());
+
+        let translation_ranges: Vec<TranslationRange> = (0 .. joint_count).map(|joint_index| {
+            let mut min = [f32::MAX; 3];
+            let mut max = [f32::MIN; 3];
+            for sample in clip.samples.iter() {
+                let offset = offset(joint_index, sample.local_poses[joint_index]);
+                for axis in 0 .. 3 {
+                    min[axis] = min[axis].min(offset[axis]);
+                    max[axis] = max[axis].max(offset[axis]);
+                }
+            }
+            TranslationRange { min, extent: vec3_sub(max, min) }
+        }).collect();
+
+        let scale_ranges: Vec<ScaleRange> = (0 .. joint_count).map(|joint_index| {
+            let mut min = f32::MAX;
+            let mut max = f32::MIN;
+            for sample in clip.samples.iter() {
+                let scale = sample.local_poses[joint_index].get_scale();
+                min = min.min(scale);
+                max = max.max(scale);
+            }
+            ScaleRange { min, extent: max - min }
+        }).collect();
+
+        let samples = clip.samples.iter().map(|sample| {
+            sample.local_poses.iter().enumerate().map(|(joint_index, &pose)| {
+                let offset = offset(joint_index, pose);
+                let range = translation_ranges[joint_index];
+                let scale_range = scale_ranges[joint_index];
+                CompressedPose {
+                    rotation: compress_rotation(pose.get_rotation()),
+                    translation: [
+                        quantize(offset[0], range.min[0], range.extent[0]),
+                        quantize(offset[1], range.min[1], range.extent[1]),
+                        quantize(offset[2], range.min[2], range.extent[2]),
+                    ],
+                    scale: quantize(pose.get_scale(), scale_range.min, scale_range.extent),
+                }
+            }).collect()
+        }).collect();
+
+        Self {
+            samples,
+            bind_poses,
+            translation_ranges,
+            scale_ranges,
+            samples_per_second: clip.samples_per_second,
+            sample_times: clip.sample_times.clone(),
+            channel_masks: clip.channel_masks.clone(),
+            joint_interpolations: clip.joint_interpolations.clone(),
+            events: clip.events.clone(),
+            morph_weights: clip.morph_weights.clone(),
+        }
+    }
+
+    /// Reconstruct the local poses of the sample at `sample_index`
+    ///
+    /// # Arguments
+    ///
+    /// * `sample_index` - The index of the sample to reconstruct
+    /// * `output_poses` - The output array slice of joint transforms, which must have
+    ///                    space for each joint in the clip
+    pub fn decompress_sample(&self, sample_index: usize, output_poses: &mut [T]) {
+        for (joint_index, (output_pose, pose)) in output_poses.iter_mut().zip(self.samples[sample_index].iter()).enumerate() {
+            let range = self.translation_ranges[joint_index];
+            let offset = [
+                dequantize(pose.translation[0], range.min[0], range.extent[0]),
+                dequantize(pose.translation[1], range.min[1], range.extent[1]),
+                dequantize(pose.translation[2], range.min[2], range.extent[2]),
+            ];
+
+            let mut joint_pose = self.bind_poses[joint_index];
+            joint_pose.set_rotation(decompress_rotation(pose.rotation));
+            joint_pose.set_translation(vec3_add(self.bind_poses[joint_index].get_translation(), offset));
+            if let Some(range) = self.scale_ranges.get(joint_index) {
+                joint_pose.set_scale(dequantize(pose.scale, range.min, range.extent));
+            }
+            *output_pose = joint_pose;
+        }
+    }
+
+    /// Reconstruct every sample, returning an uncompressed AnimationClip
+    pub fn decompress(&self) -> AnimationClip<T> {
+        let samples = (0 .. self.samples.len()).map(|sample_index| {
+            let mut local_poses = vec![T::identity(); self.bind_poses.len()];
+            self.decompress_sample(sample_index, &mut local_poses[..]);
+            AnimationSample { local_poses }
+        }).collect();
+
+        AnimationClip {
+            samples,
+            samples_per_second: self.samples_per_second,
+            sample_times: self.sample_times.clone(),
+            channel_masks: self.channel_masks.clone(),
+            joint_interpolations: self.joint_interpolations.clone(),
+            events: self.events.clone(),
+            morph_weights: self.morph_weights.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use animation::AnimationClipBuilder;
+    use math::*;
+    use skeleton::{Joint, Skeleton};
+    use transform::{Transform, QVTransform};
+
+    #[test]
+    fn test_compress_decompress() {
+
+        let skeleton = Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+        ]);
+
+        let mut builder = AnimationClipBuilder::new().with_samples_per_second(10.0);
+        for i in 0 .. 10 {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([i as f32, -0.5 * i as f32, 2.0]);
+            pose.set_rotation(quaternion::axis_angle(vec3_normalized([1.0, 2.0, -3.0]), 0.4 * i as f32));
+            builder.push_sample(vec![pose]);
+        }
+        let mut clip = builder.build().unwrap();
+        clip.events = vec![(0.5, "step".to_string())];
+
+        let compressed = clip.compress(&skeleton);
+        let decompressed = compressed.decompress();
+        assert_eq!(decompressed.samples.len(), 10);
+        assert_eq!(decompressed.events, clip.events);
+
+        for (original, decompressed) in clip.samples.iter().zip(decompressed.samples.iter()) {
+            let original = original.local_poses[0];
+            let decompressed = decompressed.local_poses[0];
+            assert!(vec3_len(vec3_sub(original.translation, decompressed.translation)) < 0.001);
+            let dot = original.rotation.0 * decompressed.rotation.0 + vec3_dot(original.rotation.1, decompressed.rotation.1);
+            assert!(dot.abs() > 0.9999);
+        }
+    }
+
+    #[test]
+    fn test_compress_animated_scale() {
+
+        let skeleton = Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+        ]);
+
+        let mut builder = AnimationClipBuilder::new().with_samples_per_second(10.0);
+        for i in 0 .. 10 {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([0.0, i as f32, 0.0]);
+            pose.set_scale(0.5 + 0.25 * i as f32);
+            builder.push_sample(vec![pose]);
+        }
+        let clip = builder.build().unwrap();
+
+        let decompressed = clip.compress(&skeleton).decompress();
+
+        for (original, decompressed) in clip.samples.iter().zip(decompressed.samples.iter()) {
+            assert!((original.local_poses[0].scale - decompressed.local_poses[0].scale).abs() < 0.001);
+        }
+    }
+}
@@ -0,0 +1,397 @@
+//! Constraints applied to a skeleton's poses after they're evaluated, e.g. after the blend
+//! tree, to make joints track gameplay targets.
+
+use std::collections::HashMap;
+
+use math::*;
+use skeleton::{JointIndex, Skeleton};
+use transform::Transform;
+
+/// A swing-twist limit on a joint's local rotation, relative to a rest rotation such as
+/// the joint's local bind pose rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JointLimit {
+    /// The local rotation the limits are measured from
+    pub rest_rotation: Quaternion<f32>,
+
+    /// The joint-space axis the joint twists about, e.g. along the bone
+    pub twist_axis: Vector3<f32>,
+
+    /// The furthest, in radians, the twist axis may swing from its rest direction
+    pub max_swing: f32,
+
+    /// The range of twist, in radians, about the twist axis
+    pub min_twist: f32,
+    pub max_twist: f32,
+}
+
+impl JointLimit {
+
+    /// Return the nearest rotation to `rotation` within the limit, found by clamping its
+    /// swing and twist from the rest rotation separately
+    pub fn clamp_rotation(&self, rotation: Quaternion<f32>) -> Quaternion<f32> {
+
+        let mut offset = quaternion_mul(quaternion_conj(self.rest_rotation), rotation);
+        if offset.0 < 0.0 {
+            offset = quaternion::scale(offset, -1.0);
+        }
+
+        // Split into swing * twist, with the twist about the twist axis
+        let twist_axis = vec3_normalized(self.twist_axis);
+        let twist_component = vec3_dot(offset.1, twist_axis);
+        let twist_angle = 2.0 * twist_component.atan2(offset.0);
+        let twist = quaternion::axis_angle(twist_axis, twist_angle);
+        let swing = quaternion_mul(offset, quaternion_conj(twist));
+
+        let twist = quaternion::axis_angle(twist_axis, twist_angle.clamp(self.min_twist, self.max_twist));
+
+        let sin_half_swing = vec3_len(swing.1);
+        let swing = if sin_half_swing > 0.000001 {
+            let swing_angle = 2.0 * sin_half_swing.atan2(swing.0.abs());
+            let swing_axis = vec3_scale(swing.1, swing.0.signum() / sin_half_swing);
+            quaternion::axis_angle(swing_axis, swing_angle.min(self.max_swing))
+        } else {
+            quaternion_id()
+        };
+
+        quaternion_mul(self.rest_rotation, quaternion_mul(swing, twist))
+    }
+}
+
+/// Rotation limits for some of a skeleton's joints
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JointLimits {
+    /// The limit on each limited joint, by joint index
+    pub limits: HashMap<JointIndex, JointLimit>,
+}
+
+impl JointLimits {
+
+    /// Clamp the rotation of `pose`, the local pose of the joint at `joint_index`, to that
+    /// joint's limit, if it has one
+    pub fn clamp_pose<T: Transform>(&self, joint_index: JointIndex, pose: &mut T) {
+        if let Some(limit) = self.limits.get(&joint_index) {
+            pose.set_rotation(limit.clamp_rotation(pose.get_rotation()));
+        }
+    }
+}
+
+/// Clamp the rotation of each local pose to its joint's limit, e.g. after driving joints
+/// procedurally. Joints without limits are left as they are.
+pub fn clamp_pose_to_limits<T: Transform>(local_poses: &mut [T], limits: &JointLimits) {
+    for (&joint_index, limit) in limits.limits.iter() {
+        if let Some(pose) = local_poses.get_mut(joint_index as usize) {
+            pose.set_rotation(limit.clamp_rotation(pose.get_rotation()));
+        }
+    }
+}
+
+/// An aim constraint, rotating a joint so that one of its axes points towards a target,
+/// e.g. so a head or eyes track a point of interest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LookAt {
+    /// The joint to rotate
+    pub joint_index: JointIndex,
+
+    /// The joint-space axis to point at the target
+    pub forward_axis: Vector3<f32>,
+
+    /// If given, the joint-space axis to roll as close as possible to model-space up (+y),
+    /// so the joint stays level while it turns. If None, the joint only turns, keeping
+    /// its animated roll.
+    pub up_axis: Option<Vector3<f32>>,
+
+    /// How much of the constraint to apply, from 0.0 (none) to 1.0 (fully), for blending
+    /// it in and out
+    pub weight: f32,
+
+    /// The furthest, in radians, the joint is rotated from its animated pose, turn and
+    /// roll together, so it doesn't snap round to targets behind it
+    pub max_angle: f32,
+}
+
+fn rotation_between(a: Vector3<f32>, b: Vector3<f32>) -> Option<(Vector3<f32>, f32)> {
+    let axis = vec3_cross(a, b);
+    let sin_angle = vec3_len(axis);
+    if sin_angle < 0.000001 {
+        return None;
+    }
+    Some((vec3_scale(axis, 1.0 / sin_angle), sin_angle.atan2(vec3_dot(a, b))))
+}
+
+/// Rotate the joint of `look_at` towards the model-space `target`, updating the global
+/// poses of the joint and its descendants, e.g. those output by
+/// `AnimationController::get_output_pose`, and keeping within any limit on the joint in
+/// `skeleton.joint_limits`. Returns the joint's new local pose, to write back to any local
+/// poses the global poses were calculated from.
+///
+/// # Arguments
+///
+/// * `global_poses` - The global poses of the skeleton's joints
+/// * `skeleton` - The skeleton the poses are for
+/// * `look_at` - The constraint to apply
+/// * `target` - The point to aim at, in model space
+pub fn apply_look_at(
+    global_poses: &mut [Matrix4<f32>],
+    skeleton: &Skeleton,
+    look_at: &LookAt,
+    target: Vector3<f32>) -> Matrix4<f32>
+{
+    let joint_index = look_at.joint_index as usize;
+    let global_pose = global_poses[joint_index];
+
+    let position = global_pose.transform_vector([0.0, 0.0, 0.0]);
+    let forward = vec3_normalized(vec3_sub(global_pose.transform_vector(look_at.forward_axis), position));
+
+    let joint = &skeleton.joints[joint_index];
+    let parent_pose = if joint.is_root() { mat4_id() } else { global_poses[joint.parent_index as usize] };
+
+    let target_direction = vec3_sub(target, position);
+    if vec3_len(target_direction) < 0.000001 {
+        return row_mat4_mul(mat4_inv(parent_pose), global_pose);
+    }
+
+    // Turn the forward axis towards the target
+    let mut rotation = match rotation_between(forward, vec3_normalized(target_direction)) {
+        Some((axis, angle)) => quaternion::axis_angle(axis, angle),
+        None => quaternion_id(),
+    };
+
+    // Roll about the new forward axis to bring the up axis level with model-space up
+    if let Some(up_axis) = look_at.up_axis {
+        let up = vec3_normalized(vec3_sub(global_pose.transform_vector(up_axis), position));
+        let forward = quaternion::rotate_vector(rotation, forward);
+        let up = quaternion::rotate_vector(rotation, up);
+        let level_up = vec3_sub([0.0, 1.0, 0.0], vec3_scale(forward, forward[1]));
+
+        if vec3_len(level_up) > 0.000001 {
+            let up = vec3_sub(up, vec3_scale(forward, vec3_dot(up, forward)));
+            if let Some((axis, angle)) = rotation_between(vec3_normalized(up), vec3_normalized(level_up)) {
+                rotation = quaternion_mul(quaternion::axis_angle(axis, angle), rotation);
+            }
+        }
+    }
+
+    // Limit the combined rotation's angle to the max angle, and scale it by the weight
+    let sin_half_angle = vec3_len(rotation.1);
+    if sin_half_angle > 0.000001 {
+        let sign = if rotation.0 < 0.0 { -1.0 } else { 1.0 };
+        let angle = 2.0 * sin_half_angle.atan2(sign * rotation.0);
+        let angle = angle.min(look_at.max_angle) * look_at.weight.clamp(0.0, 1.0);
+        rotation = quaternion::axis_angle(vec3_scale(rotation.1, sign / sin_half_angle), angle);
+    }
+
+    // Rotate the joint's axes in model space, keeping its position and scale
+    let mut new_global_pose = global_pose;
+    for axis in 0 .. 3 {
+        let column = quaternion::rotate_vector(rotation, [global_pose[0][axis], global_pose[1][axis], global_pose[2][axis]]);
+        new_global_pose[0][axis] = column[0];
+        new_global_pose[1][axis] = column[1];
+        new_global_pose[2][axis] = column[2];
+    }
+
+    // Keep within any limit on the joint
+    let mut local_pose = row_mat4_mul(mat4_inv(parent_pose), new_global_pose);
+    if skeleton.joint_limits.limits.contains_key(&look_at.joint_index) {
+        skeleton.joint_limits.clamp_pose(look_at.joint_index, &mut local_pose);
+        new_global_pose = row_mat4_mul(parent_pose, local_pose);
+    }
+
+    // Carry the joint's descendants round with it, which come after it in the skeleton
+    let change = row_mat4_mul(new_global_pose, mat4_inv(global_pose));
+    let mut in_subtree = vec![false; skeleton.joints.len()];
+    in_subtree[joint_index] = true;
+    global_poses[joint_index] = new_global_pose;
+
+    for (descendant_index, descendant) in skeleton.joints.iter().enumerate().skip(joint_index + 1) {
+        if !descendant.is_root() && in_subtree[descendant.parent_index as usize] {
+            in_subtree[descendant_index] = true;
+            global_poses[descendant_index] = row_mat4_mul(change, global_poses[descendant_index]);
+        }
+    }
+
+    local_pose
+}
+
+#[cfg(test)]
+mod test {
+
+    use math::*;
+    use skeleton::{Joint, Skeleton};
+    use transform::{Transform, QVTransform};
+
+    use super::{apply_look_at, clamp_pose_to_limits, JointLimit, JointLimits, LookAt};
+
+    fn assert_same_rotation(a: Quaternion<f32>, b: Quaternion<f32>) {
+        assert!(quaternion::dot(a, b).abs() > 0.99999, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_clamp_pose_to_limits() {
+
+        let limit = JointLimit {
+            rest_rotation: quaternion::axis_angle([0.0, 0.0, 1.0], 0.5),
+            twist_axis: [0.0, 1.0, 0.0],
+            max_swing: 0.5,
+            min_twist: -0.2,
+            max_twist: 0.3,
+        };
+
+        let offset = |axis: Vector3<f32>, angle: f32| quaternion_mul(limit.rest_rotation, quaternion::axis_angle(axis, angle));
+
+        // Within the limit
+        assert_same_rotation(limit.clamp_rotation(offset([1.0, 0.0, 0.0], 0.4)), offset([1.0, 0.0, 0.0], 0.4));
+        assert_same_rotation(limit.clamp_rotation(offset([0.0, 1.0, 0.0], -0.1)), offset([0.0, 1.0, 0.0], -0.1));
+
+        // Swing and twist clamped separately
+        assert_same_rotation(limit.clamp_rotation(offset([1.0, 0.0, 0.0], 1.0)), offset([1.0, 0.0, 0.0], 0.5));
+        assert_same_rotation(limit.clamp_rotation(offset([0.0, 1.0, 0.0], 1.0)), offset([0.0, 1.0, 0.0], 0.3));
+        assert_same_rotation(limit.clamp_rotation(offset([0.0, 1.0, 0.0], -1.0)), offset([0.0, 1.0, 0.0], -0.2));
+
+        let mut limits = JointLimits::default();
+        limits.limits.insert(1, limit);
+
+        let mut local_poses = [QVTransform::identity(); 2];
+        local_poses[0].set_rotation(offset([0.0, 1.0, 0.0], 1.0));
+        local_poses[1].set_rotation(offset([0.0, 1.0, 0.0], 1.0));
+        clamp_pose_to_limits(&mut local_poses[..], &limits);
+        assert_same_rotation(local_poses[0].rotation, offset([0.0, 1.0, 0.0], 1.0));
+        assert_same_rotation(local_poses[1].rotation, offset([0.0, 1.0, 0.0], 0.3));
+    }
+
+    #[test]
+    fn test_apply_look_at() {
+
+        // A neck with a head above it, and an eye in front of the head
+        let skeleton = Skeleton::new(vec![
+            Joint::new("neck", None, mat4_id()),
+            Joint::new("head", Some(0), mat4_id()),
+            Joint::new("eye", Some(1), mat4_id()),
+        ]);
+
+        let translation = |v: Vector3<f32>| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation(v);
+            pose
+        };
+
+        let animated_poses = [translation([0.0, 1.0, 0.0]), translation([0.0, 1.0, 0.0]), translation([0.0, 0.0, 1.0])];
+
+        let mut look_at = LookAt {
+            joint_index: 1,
+            forward_axis: [0.0, 0.0, 1.0],
+            up_axis: Some([0.0, 1.0, 0.0]),
+            weight: 1.0,
+            max_angle: ::std::f32::consts::PI,
+        };
+
+        let look = |look_at: &LookAt, target: Vector3<f32>| {
+            let mut local_poses = animated_poses;
+            let mut global_poses = [mat4_id(); 3];
+            skeleton.calculate_global_poses(&local_poses[..], &mut global_poses[..]);
+            local_poses[1] = QVTransform::from_matrix(apply_look_at(&mut global_poses[..], &skeleton, look_at, target));
+
+            // The local poses written back give the same global poses
+            let mut recalculated_poses = [mat4_id(); 3];
+            skeleton.calculate_global_poses(&local_poses[..], &mut recalculated_poses[..]);
+            for (pose, recalculated_pose) in global_poses.iter().zip(recalculated_poses.iter()) {
+                assert!(vec3_len(vec3_sub(pose.get_translation(), recalculated_pose.get_translation())) < 0.0001);
+            }
+
+            global_poses
+        };
+
+        // Turning the head to the right moves the eye with it, staying level
+        let global_poses = look(&look_at, [5.0, 2.0, 0.0]);
+        let eye_position = global_poses[2].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(eye_position, [1.0, 2.0, 0.0])) < 0.0001);
+        let head_up = global_poses[1].transform_vector([0.0, 1.0, 0.0]);
+        assert!(vec3_len(vec3_sub(head_up, [0.0, 3.0, 0.0])) < 0.0001);
+
+        // Limited to the max angle, and blended by the weight
+        look_at.max_angle = 0.25 * ::std::f32::consts::PI;
+        let eye_position = look(&look_at, [5.0, 2.0, 0.0])[2].transform_vector([0.0, 0.0, 0.0]);
+        let expected = [::std::f32::consts::FRAC_1_SQRT_2, 2.0, ::std::f32::consts::FRAC_1_SQRT_2];
+        assert!(vec3_len(vec3_sub(eye_position, expected)) < 0.0001);
+
+        look_at.weight = 0.0;
+        let eye_position = look(&look_at, [5.0, 2.0, 0.0])[2].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(eye_position, [0.0, 2.0, 1.0])) < 0.0001);
+    }
+
+    #[test]
+    fn test_apply_look_at_with_limit() {
+
+        let mut skeleton = Skeleton::new(vec![
+            Joint::new("neck", None, mat4_id()),
+            Joint::new("head", Some(0), mat4_id()),
+        ]);
+
+        // The head can only turn 30 degrees about its y-axis
+        skeleton.joint_limits.limits.insert(1, JointLimit {
+            rest_rotation: quaternion_id(),
+            twist_axis: [0.0, 1.0, 0.0],
+            max_swing: 0.0,
+            min_twist: -::std::f32::consts::FRAC_PI_6,
+            max_twist: ::std::f32::consts::FRAC_PI_6,
+        });
+
+        let mut neck_pose = mat4_id();
+        neck_pose[1][3] = 1.0;
+        let mut global_poses = [neck_pose, neck_pose];
+        global_poses[1][1][3] = 2.0;
+
+        let look_at = LookAt {
+            joint_index: 1,
+            forward_axis: [0.0, 0.0, 1.0],
+            up_axis: Some([0.0, 1.0, 0.0]),
+            weight: 1.0,
+            max_angle: ::std::f32::consts::PI,
+        };
+
+        let local_pose = apply_look_at(&mut global_poses[..], &skeleton, &look_at, [5.0, 2.0, 0.0]);
+        assert_eq!(local_pose.get_translation(), [0.0, 1.0, 0.0]);
+        let forward = vec3_sub(global_poses[1].transform_vector([0.0, 0.0, 1.0]), [0.0, 2.0, 0.0]);
+        assert!(vec3_len(vec3_sub(forward, [0.5, 0.0, 0.75f32.sqrt()])) < 0.0001);
+    }
+
+    #[test]
+    fn test_apply_look_at_roll() {
+
+        let skeleton = Skeleton::new(vec![
+            Joint::new("head", None, mat4_id()),
+        ]);
+
+        // A head already facing the target along +z, but rolled onto its side
+        let mut head_pose = QVTransform::identity();
+        head_pose.set_rotation(quaternion::axis_angle([0.0, 0.0, 1.0], ::std::f32::consts::FRAC_PI_2));
+
+        let mut look_at = LookAt {
+            joint_index: 0,
+            forward_axis: [0.0, 0.0, 1.0],
+            up_axis: None,
+            weight: 1.0,
+            max_angle: ::std::f32::consts::PI,
+        };
+
+        let look = |look_at: &LookAt| {
+            let mut global_poses = [head_pose.to_matrix()];
+            apply_look_at(&mut global_poses[..], &skeleton, look_at, [0.0, 0.0, 5.0]);
+            vec3_normalized(global_poses[0].transform_vector([0.0, 1.0, 0.0]))
+        };
+
+        // Without an up axis, the head keeps its roll
+        let rolled_up = vec3_normalized(head_pose.to_matrix().transform_vector([0.0, 1.0, 0.0]));
+        assert!(vec3_len(vec3_sub(look(&look_at), rolled_up)) < 0.0001);
+
+        // With one, it rolls level
+        look_at.up_axis = Some([0.0, 1.0, 0.0]);
+        assert!(vec3_len(vec3_sub(look(&look_at), [0.0, 1.0, 0.0])) < 0.0001);
+
+        // The roll is limited by the max angle too
+        look_at.max_angle = ::std::f32::consts::FRAC_PI_4;
+        let up = look(&look_at);
+        assert!((vec3_dot(up, rolled_up) - ::std::f32::consts::FRAC_1_SQRT_2).abs() < 0.0001);
+        assert!((vec3_dot(up, [0.0, 1.0, 0.0]) - ::std::f32::consts::FRAC_1_SQRT_2).abs() < 0.0001);
+    }
+}
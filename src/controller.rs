@@ -1,14 +1,20 @@
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::mem;
 
-use rustc_serialize::{Decodable, Decoder};
+use serde::{Deserialize, Serialize};
 
+use Shared;
 use animation::AnimationClip;
 use transform::{Transform, FromTransform};
-use blend_tree::{AnimBlendTree, BlendTreeNodeDef, ClipId};
+use blend_tree::{AnimBlendTree, BlendTreeError, BlendTreeNodeDef, ClipId, DefinitionId, ParamId, PoseBuffers};
+use math::{Matrix4, vec3_scale};
+use parameter::ParameterSet;
+pub use parameter::ParameterRange;
 use skeleton::Skeleton;
 
-const MAX_JOINTS: usize = 64;
+/// Tolerance within which clock times and parameter values are considered unchanged
+/// when deciding whether the cached pose can be reused
+const POSE_CACHE_EPSILON: f32 = 0.000001;
 
 /// A state that an AnimationController can be in, consisting
 /// of a blend tree and a collection of transitions to other states
@@ -21,8 +27,28 @@ pub struct AnimationState<T: Transform> {
     pub transitions: Vec<AnimationTransition>,
 }
 
+/// How an animation layer's pose is combined with the pose of the layers below it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayerMode {
+    /// Blend from the pose below towards the layer's pose by the layer's weight
+    Blend,
+
+    /// Apply the layer's pose on top of the pose below, as for an AdditiveNode, with its
+    /// effect scaled by the layer's weight
+    Additive,
+}
+
+/// A blend tree evaluated on top of the controller's current state (the base layer),
+/// affecting the joints within its mask
+struct AnimationLayer<T: Transform> {
+    blend_tree: AnimBlendTree<T>,
+    mode: LayerMode,
+    mask: Option<Vec<f32>>,
+    weight: f32,
+}
+
 /// Representation of a state transition to a target state, with a condition and a duration
-#[derive(Debug, Clone, RustcDecodable)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnimationTransition {
     /// The name of the target state to transition to
     pub target_state: String,
@@ -37,7 +63,7 @@ pub struct AnimationTransition {
 }
 
 /// Representation of a condition to check for an AnimationTransition
-#[derive(Debug, Clone, RustcDecodable)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransitionCondition {
 
     /// The name of the controller parameter to compare with
@@ -52,7 +78,7 @@ pub struct TransitionCondition {
 
 impl TransitionCondition {
     /// Returns true if the condition is satisfied
-    pub fn is_true(&self, parameters: &HashMap<String, f32>) -> bool {
+    pub fn is_true(&self, parameters: &ParameterSet) -> bool {
         match self.operator {
             Operator::LessThan => parameters[&self.parameter[..]] < self.value,
             Operator::GreaterThan => parameters[&self.parameter[..]] > self.value,
@@ -64,42 +90,43 @@ impl TransitionCondition {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Operator {
+    #[serde(rename = "<")]
     LessThan,
+    #[serde(rename = "<=")]
     LessThanEqual,
+    #[serde(rename = ">")]
     GreaterThan,
+    #[serde(rename = ">=")]
     GreaterThanEqual,
+    #[serde(rename = "=")]
     Equal,
+    #[serde(rename = "!=")]
     NotEqual,
 }
 
-impl Decodable for Operator {
-    fn decode<D: Decoder>(decoder: &mut D) -> Result<Operator, D::Error> {
-        match &try!(decoder.read_str())[..] {
-            "<" => Ok(Operator::LessThan),
-            ">" => Ok(Operator::GreaterThan),
-            "<=" => Ok(Operator::LessThanEqual),
-            ">=" => Ok(Operator::GreaterThanEqual),
-            "=" => Ok(Operator::Equal),
-            "!=" => Ok(Operator::NotEqual),
-            _ => Ok(Operator::Equal), // FIXME -- figure out how to throw a D::Error...
-        }
-    }
-}
-
 /// Definition struct for an AnimationController, which can be deserialized from JSON
 /// and converted to an AnimationController instance at runtime
-#[derive(Clone, Debug, RustcDecodable)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AnimationControllerDef {
 
     /// Identifying name for the controller definition
     pub name: String,
 
-    /// Declaration list of all parameters that are used by the AnimationController,
-    /// including state transition conditions and blend tree parameters
+    /// Declaration list of the parameters used by the AnimationController. Parameters
+    /// referenced by its blend trees are declared too even if they aren't listed, so only
+    /// those used just by transition conditions must be.
     pub parameters: Vec<String>,
 
+    /// Optional ranges for declared parameters, by name. Values set outside a parameter's
+    /// range are clamped to it.
+    pub parameter_ranges: Option<HashMap<String, ParameterRange>>,
+
+    /// Optional named blend sub-trees, which state and layer blend trees can reference
+    /// with a RefNode rather than repeating them
+    pub definitions: Option<HashMap<DefinitionId, BlendTreeNodeDef>>,
+
     /// List of animation state definitions
     pub states: Vec<AnimationStateDef>,
 
@@ -107,9 +134,51 @@ pub struct AnimationControllerDef {
     pub initial_state: String,
 }
 
+impl AnimationControllerDef {
+
+    /// Return the names of every parameter the controller declares: those listed in
+    /// `parameters`, followed by any others referenced by its state blend trees or
+    /// sub-tree definitions
+    pub fn declared_params(&self) -> Vec<ParamId> {
+        let mut declared = self.parameters.clone();
+        let blend_trees = self.states.iter().map(|state_def| &state_def.blend_tree)
+            .chain(self.definitions.iter().flat_map(|definitions| definitions.values()));
+        for blend_tree in blend_trees {
+            for parameter in blend_tree.referenced_params() {
+                if !declared.contains(&parameter) {
+                    declared.push(parameter);
+                }
+            }
+        }
+        declared
+    }
+}
+
+/// The state of a parameter eased towards its most recently set value by a critically
+/// damped spring
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ParameterSmoothing {
+    /// Time in seconds for the parameter to cover half the distance to its target
+    half_life: f32,
+    target: f32,
+    velocity: f32,
+}
+
+impl ParameterSmoothing {
+    /// Advance the spring by `delta_time` seconds from `value`, returning the new value
+    fn update(&mut self, value: f32, delta_time: f32) -> f32 {
+        let damping = 2.0 * ::std::f32::consts::LN_2 / self.half_life;
+        let offset = value - self.target;
+        let j = self.velocity + offset * damping;
+        let decay = (-damping * delta_time).exp();
+        self.velocity = decay * (self.velocity - j * damping * delta_time);
+        self.target + decay * (offset + j * delta_time)
+    }
+}
+
 /// Definition struct for an AnimationState, which can be deserialized from JSON
 /// and converted to an AnimationState instance at runtime
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AnimationStateDef {
 
     /// The identifying name for the state
@@ -122,42 +191,24 @@ pub struct AnimationStateDef {
     pub transitions: Vec<AnimationTransition>,
 }
 
-impl Decodable for AnimationStateDef {
-    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, D::Error> {
-        decoder.read_struct("root", 0, |decoder| {
-
-            let name = try!(decoder.read_struct_field("name", 0, |decoder| {
-                Ok(try!(decoder.read_str()))
-            }));
-
-            let blend_tree = try!(decoder.read_struct_field("blend_tree", 0, Decodable::decode));
-
-            let transitions = try!(decoder.read_struct_field("transitions", 0, |decoder| {
-                decoder.read_seq(|decoder, len| {
-                    let mut transitions = Vec::new();
-                    for i in 0 .. len {
-                        transitions.push(try!(decoder.read_seq_elt(i, Decodable::decode)));
-                    }
-                    Ok(transitions)
-                })
-            }));
-
-            Ok(Self { name, blend_tree, transitions })
-        })
-    }
-}
-
 
 /// A runtime representation of an Animation State Machine, consisting of one or more
 /// AnimationStates connected by AnimationTransitions, where the output animation
 /// pose depends on the current state or any active transitions between states.
 pub struct AnimationController<T: Transform> {
 
-    /// Parameters that will be referenced by blend tree nodes and animation states
-    parameters: HashMap<String, f32>,
+    /// Parameters that will be referenced by blend tree nodes and animation states,
+    /// declared with their ranges
+    parameters: ParameterSet,
+
+    /// Parameters that are eased towards their set values on `update`, by parameter name
+    parameter_smoothing: HashMap<String, ParameterSmoothing>,
 
     /// Shared reference to the skeleton this controller is using
-    skeleton: Rc<Skeleton>,
+    skeleton: Shared<Skeleton>,
+
+    /// Named blend sub-trees that blend tree RefNodes are resolved to
+    definitions: HashMap<DefinitionId, BlendTreeNodeDef>,
 
     /// Tracks seconds since controller started running
     local_clock: f64,
@@ -165,14 +216,59 @@ pub struct AnimationController<T: Transform> {
     /// Playback speed multiplier.
     playback_speed: f64,
 
+    /// Scale applied to joint translations in output poses, e.g. to convert between units
+    import_scale: f32,
+
     /// Mapping of all animation state names to their instances
     states: HashMap<String, AnimationState<T>>,
 
+    /// The name of the state the controller starts in, and returns to on `reset`
+    initial_state: String,
+
     /// The name of the current active AnimationState
     current_state: String,
 
     /// The current active AnimationTransition and its start time, if any
     transition: Option<(f64, AnimationTransition)>,
+
+    /// Layers applied on top of the current state's pose, from bottom to top
+    layers: Vec<AnimationLayer<T>>,
+
+    /// Local poses replacing the blended output for specific joints, by joint index
+    joint_overrides: HashMap<usize, T>,
+
+    /// The most recently evaluated blend tree output, if still valid
+    pose_cache: Option<PoseCache<T>>,
+
+    /// Scratch poses reused by every blend tree evaluation
+    pose_buffers: PoseBuffers<T>,
+
+    /// The local poses output by the last call to `get_output_pose`
+    last_local_poses: Vec<T>,
+
+    /// The global poses output by the last call to `get_output_pose`, as matrices
+    last_global_poses: Vec<Matrix4<f32>>,
+}
+
+/// The inputs and resulting local poses of the last blend tree evaluation
+struct PoseCache<T: Transform> {
+    elapsed_time: f64,
+    state: String,
+    transition_start_time: Option<f64>,
+    parameters: Vec<f32>,
+    local_poses: Vec<T>,
+}
+
+impl<T: Transform> PoseCache<T> {
+    fn is_valid(&self, elapsed_time: f64, state: &str, transition_start_time: Option<f64>, parameters: &ParameterSet) -> bool {
+        (self.elapsed_time - elapsed_time).abs() < POSE_CACHE_EPSILON as f64
+            && self.state == state
+            && self.transition_start_time == transition_start_time
+            && self.parameters.len() == parameters.len()
+            && self.parameters.iter().zip(parameters.values().iter()).all(|(cached, value)| {
+                (cached - value).abs() < POSE_CACHE_EPSILON
+            })
+    }
 }
 
 
@@ -180,19 +276,26 @@ pub struct AnimationController<T: Transform> {
 impl<T: Transform> AnimationController<T> {
 
     /// Create an AnimationController instance from its definition, the desired skeleton, and a
-    /// collection of currently loaded animation clips.
-    pub fn new(controller_def: AnimationControllerDef, skeleton: Rc<Skeleton>, animations: &HashMap<ClipId, Rc<AnimationClip<T>>>) -> Self {
+    /// collection of currently loaded animation clips. Returns the error from building the
+    /// first state's blend tree that can't be built, e.g. one referencing a missing clip.
+    pub fn new(controller_def: AnimationControllerDef, skeleton: Shared<Skeleton>, animations: &HashMap<ClipId, Shared<AnimationClip<T>>>) -> Result<Self, BlendTreeError> {
 
-        let mut parameters = HashMap::new();
+        let mut parameters = ParameterSet::new();
+        let parameter_ranges = controller_def.parameter_ranges.clone().unwrap_or_default();
 
-        for parameter in controller_def.parameters.iter() {
-            parameters.insert(parameter.clone(), 0.0);
-        };
+        for parameter in controller_def.declared_params().iter() {
+            match parameter_ranges.get(parameter) {
+                Some(&range) => parameters.declare_with_range(parameter, range),
+                None => parameters.declare(parameter),
+            };
+        }
+
+        let definitions = controller_def.definitions.unwrap_or_default();
 
         let mut states = HashMap::new();
         for state_def in controller_def.states.iter() {
 
-            let mut blend_tree = AnimBlendTree::from_def(state_def.blend_tree.clone(), animations, skeleton.clone());
+            let mut blend_tree = AnimBlendTree::from_def_with_definitions(state_def.blend_tree.clone(), &definitions, animations, skeleton.clone(), &parameters)?;
             blend_tree.synchronize(0.0, &parameters);
 
             states.insert(state_def.name.clone(), AnimationState {
@@ -202,29 +305,55 @@ impl<T: Transform> AnimationController<T> {
 
         }
 
-        Self {
+        Ok(Self {
             parameters,
+            parameter_smoothing: HashMap::new(),
             skeleton: skeleton.clone(),
+            definitions,
             local_clock: 0.0,
             playback_speed: 1.0,
+            import_scale: 1.0,
             states,
-            current_state: controller_def.initial_state,
+            current_state: controller_def.initial_state.clone(),
+            initial_state: controller_def.initial_state,
             transition: None,
-        }
+            layers: Vec::new(),
+            joint_overrides: HashMap::new(),
+            pose_cache: None,
+            pose_buffers: PoseBuffers::new(),
+            last_local_poses: Vec::new(),
+            last_global_poses: Vec::new(),
+        })
     }
 
-    /// Update the controller's local clock with the given time delta
+    /// Update the controller's local clock with the given time delta, and ease any smoothed
+    /// parameters towards their set values over the same (unscaled) time delta
     pub fn update(&mut self, delta_time: f64) {
         self.local_clock += delta_time * self.playback_speed;
+
+        for (name, smoothing) in self.parameter_smoothing.iter_mut() {
+            let value = smoothing.update(self.parameters[&name[..]], delta_time as f32);
+            self.parameters.set(name, value);
+        }
+    }
+
+    /// Update the controller as for `update`, returning whether the dominant clip (as for
+    /// `current_phase`) wrapped back to its start during the step, e.g. to count completed
+    /// cycles or re-randomize an idle variation at a loop boundary. This is correct even if
+    /// `delta_time` spans more than one loop of the clip.
+    pub fn advance(&mut self, delta_time: f64) -> bool {
+        let prev_time = self.local_clock;
+        self.update(delta_time);
+        self.dominant_state().blend_tree.has_wrapped(prev_time as f32, self.local_clock as f32, &self.parameters)
     }
 
     /// Checks if controller should transition to a different state, or if currently
-    /// in a transition, checks if the transition is complete
-    fn update_state(&mut self, ext_dt: f64) {
+    /// in a transition, checks if the transition is complete, at the given controller time
+    fn update_state(&mut self, elapsed_time: f64) {
         match self.transition.clone() {
             Some((ref start_time, ref transition)) => {
                 // If transition is finished, switch state to new transition
-                if self.local_clock + ext_dt >= start_time + transition.duration as f64{
+                if elapsed_time >= start_time + transition.duration as f64 {
                     self.current_state = transition.target_state.clone();
                     self.transition = None;
                 }
@@ -236,7 +365,7 @@ impl<T: Transform> AnimationController<T> {
                 for transition in current_state.transitions.iter() {
 
                     if transition.condition.is_true(&self.parameters) {
-                        self.transition = Some((self.local_clock + ext_dt, transition.clone()));
+                        self.transition = Some((elapsed_time, transition.clone()));
                         break;
                     }
                 }
@@ -244,14 +373,188 @@ impl<T: Transform> AnimationController<T> {
         }
     }
 
-    /// Set the playback speed for the controller
+    /// Return the controller's local clock time, in seconds
+    pub fn current_time(&self) -> f32 {
+        self.local_clock as f32
+    }
+
+    /// Return the normalized playback phase, in [0, 1), of the dominant clip: the target
+    /// state is used once an active transition is at least halfway complete, and within a
+    /// blend tree the more heavily weighted input of each blend node is followed
+    pub fn current_phase(&self) -> f32 {
+        self.dominant_state().blend_tree.get_phase(self.local_clock as f32, &self.parameters).unwrap_or(0.0)
+    }
+
+    /// Whether the current state's dominant clip is non-looping (`"loop": "clamp"`) and has
+    /// played through, e.g. to transition out of a one-shot attack state
+    pub fn is_finished(&self) -> bool {
+        self.states[&self.current_state[..]].blend_tree.is_finished(self.local_clock as f32, &self.parameters)
+    }
+
+    /// The current state, or the target state once an active transition is at least
+    /// halfway complete
+    fn dominant_state(&self) -> &AnimationState<T> {
+        let state_name = match self.transition {
+            Some((start_time, ref transition)) if (self.local_clock - start_time) >= transition.duration as f64 * 0.5 => &transition.target_state,
+            _ => &self.current_state,
+        };
+
+        &self.states[&state_name[..]]
+    }
+
+    /// Set the controller's local clock to the given time, in seconds
+    pub fn set_time(&mut self, time: f32) {
+        self.local_clock = time as f64;
+    }
+
+    /// Return the controller to how it started, e.g. to reuse it for a respawned or pooled
+    /// character: the clock is set to zero, any active transition is dropped in favor of
+    /// the initial state, every blend tree's clips are rewound, and smoothed parameters jump
+    /// straight to their set values. Parameter values, layers and joint overrides are kept.
+    pub fn reset(&mut self) {
+        self.local_clock = 0.0;
+        self.transition = None;
+        self.current_state = self.initial_state.clone();
+
+        for (name, smoothing) in self.parameter_smoothing.iter_mut() {
+            smoothing.velocity = 0.0;
+            self.parameters.set(name, smoothing.target);
+        }
+
+        let blend_trees = self.states.values_mut().map(|state| &mut state.blend_tree)
+            .chain(self.layers.iter_mut().map(|layer| &mut layer.blend_tree));
+        for blend_tree in blend_trees {
+            blend_tree.reset();
+            blend_tree.synchronize(0.0, &self.parameters);
+        }
+
+        self.pose_cache = None;
+    }
+
+    /// Move the controller's local clock by `n` frames of the dominant clip (as for
+    /// `current_phase`), forwards or backwards. The clock is snapped to the nearest frame
+    /// first, so stepping is exact and reversible. Does nothing if the dominant state
+    /// contains no clips.
+    pub fn step_frames(&mut self, n: i32) {
+        let samples_per_second = match self.dominant_state().blend_tree.get_samples_per_second(&self.parameters) {
+            Some(samples_per_second) if samples_per_second > 0.0 => samples_per_second as f64,
+            _ => return,
+        };
+
+        let frame = (self.local_clock * samples_per_second).round() + n as f64;
+        self.local_clock = frame / samples_per_second;
+    }
+
+    /// Return the names of clip events passed between the controller times `prev_time`
+    /// (exclusive) and `curr_time` (inclusive), as returned by `current_time`. Events are
+    /// collected from every clip in the current state, and in the target state of any
+    /// active transition.
+    pub fn drain_events(&self, prev_time: f32, curr_time: f32) -> Vec<&str> {
+        let mut events = Vec::new();
+
+        self.states[&self.current_state[..]].blend_tree.collect_events(prev_time, curr_time, &self.parameters, &mut events);
+
+        if let Some((_, ref transition)) = self.transition {
+            self.states[&transition.target_state[..]].blend_tree.collect_events(prev_time, curr_time, &self.parameters, &mut events);
+        }
+
+        events
+    }
+
+    /// Scale the translations of every joint in output poses by `scale`, e.g. 0.01 for
+    /// assets authored in centimeters used in a world in meters. Rotations are unaffected,
+    /// so the whole posed skeleton is scaled about the model-space origin. The skeleton's
+    /// inverse bind poses aren't scaled, so skin with `calculate_skinning_transforms`,
+    /// which scales them to match, and mesh vertices scaled by the same factor.
+    pub fn set_import_scale(&mut self, scale: f32) {
+        self.import_scale = scale;
+    }
+
+    /// Return the scale applied to joint translations in output poses
+    pub fn get_import_scale(&self) -> f32 {
+        self.import_scale
+    }
+
+    /// Calculate the skinning transform of each joint, its global pose from
+    /// `get_output_pose` concatenated with its inverse bind pose, with the inverse bind
+    /// pose translations scaled by the import scale so that a mesh at rest keeps its
+    /// bind pose
+    ///
+    /// # Arguments
+    ///
+    /// * `global_poses` - The global poses output by `get_output_pose`
+    /// * `output_transforms` - The output array slice of skinning transforms, which must
+    ///                         have space for each joint in the skeleton
+    pub fn calculate_skinning_transforms<TOutput: Transform>(&self, global_poses: &[TOutput], output_transforms: &mut [TOutput]) {
+        for ((joint, global_pose), output_transform) in self.skeleton.joints.iter()
+            .zip(global_poses.iter())
+            .zip(output_transforms.iter_mut())
+        {
+            let mut inverse_bind_pose = joint.inverse_bind_pose;
+            if self.import_scale != 1.0 {
+                inverse_bind_pose.set_translation(vec3_scale(inverse_bind_pose.get_translation(), self.import_scale));
+            }
+            *output_transform = global_pose.concat(TOutput::from_matrix(inverse_bind_pose));
+        }
+    }
+
+    /// Set the playback speed for the controller, a global time scale applied to every
+    /// `update` and extrapolated `get_output_pose` time delta, e.g. for slow motion or
+    /// hit-stop. Negative speeds play in reverse. Defaults to 1.0.
     pub fn set_playback_speed(&mut self, speed: f64) {
         self.playback_speed = speed;
     }
 
-    /// Set the value for the given controller parameter
+    /// Return the playback speed for the controller
+    pub fn get_playback_speed(&self) -> f64 {
+        self.playback_speed
+    }
+
+    /// Set the playback speed for the controller from an `f32` rate, as for
+    /// `set_playback_speed`, the same time scale that clip playback rates use
+    pub fn set_playback_rate(&mut self, rate: f32) {
+        self.set_playback_speed(rate as f64);
+    }
+
+    /// Set the value for the given controller parameter, clamped to the parameter's
+    /// range if one was declared. For smoothed parameters, this sets the value the
+    /// parameter is eased towards on `update`.
     pub fn set_param_value(&mut self, name: &str, value: f32) {
-        self.parameters.insert(name.to_string(), value); // :(
+        let value = self.parameters.clamp(name, value);
+        match self.parameter_smoothing.get_mut(name) {
+            Some(smoothing) => smoothing.target = value,
+            None => self.parameters.set(name, value),
+        }
+    }
+
+    /// Set the value of every parameter in `values`, as for `set_param_value`, leaving
+    /// the controller's other parameters as they were. Systems that each own some of the
+    /// parameters can keep their own ParameterSet and apply it whenever it changes, with
+    /// later calls overriding any parameters that earlier ones also set.
+    pub fn set_param_values(&mut self, values: &ParameterSet) {
+        for (name, value) in values.iter() {
+            self.set_param_value(name, value);
+        }
+    }
+
+    /// Ease the given parameter towards the values set with `set_param_value` with a
+    /// critically damped spring, rather than changing it immediately, so that blends don't
+    /// pop when gameplay values jump. `half_life` is the time in seconds for the parameter
+    /// to cover half the remaining distance; a half-life of zero or less stops smoothing,
+    /// jumping straight to the most recently set value.
+    pub fn set_parameter_smoothing(&mut self, name: &str, half_life: f32) {
+        if half_life > 0.0 {
+            let value = self.parameters.get(name).unwrap_or(0.0);
+            let smoothing = self.parameter_smoothing.entry(name.to_string()).or_insert(ParameterSmoothing {
+                half_life,
+                target: value,
+                velocity: 0.0,
+            });
+            smoothing.half_life = half_life;
+            self.parameters.set(name, value);
+        } else if let Some(smoothing) = self.parameter_smoothing.remove(name) {
+            self.parameters.set(name, smoothing.target);
+        }
     }
 
     /// Return the value for the given controller parameter
@@ -259,50 +562,215 @@ impl<T: Transform> AnimationController<T> {
         self.parameters[name]
     }
 
-    /// Return a read-only reference to the controller parameter map
-    pub fn get_parameters(&self) -> &HashMap<String, f32> {
+    /// Return a read-only reference to the controller parameters
+    pub fn get_parameters(&self) -> &ParameterSet {
         &self.parameters
     }
 
+    /// Replace the local pose of the given joint with `pose` in all future output poses,
+    /// until cleared with `clear_joint_override`
+    pub fn set_joint_override(&mut self, joint: usize, pose: T) {
+        self.joint_overrides.insert(joint, pose);
+    }
+
+    /// Return the local pose overriding the given joint, if any
+    pub fn get_joint_override(&self, joint: usize) -> Option<T> {
+        self.joint_overrides.get(&joint).cloned()
+    }
+
+    /// Stop overriding the local pose of the given joint
+    pub fn clear_joint_override(&mut self, joint: usize) {
+        self.joint_overrides.remove(&joint);
+    }
+
+    /// Add a layer on top of any existing layers, returning its index. Layers are evaluated
+    /// from bottom to top over the current state's pose, each combining its blend tree's pose
+    /// with the pose below according to `mode`, by `weight` multiplied by the joint's weight
+    /// in `mask`. The mask holds a weight per joint index, with joints beyond its end left
+    /// unaffected, or if None, the layer affects every joint. Any parameters the blend tree
+    /// uses that the controller doesn't have yet are declared, and its RefNodes are resolved
+    /// to the controller definition's `definitions`. Returns the error from building the
+    /// blend tree if it can't be built, leaving the layers unchanged.
+    pub fn add_layer(
+        &mut self,
+        blend_tree: BlendTreeNodeDef,
+        animations: &HashMap<ClipId, Shared<AnimationClip<T>>>,
+        mode: LayerMode,
+        mask: Option<Vec<f32>>,
+        weight: f32,
+    ) -> Result<usize, BlendTreeError> {
+
+        for parameter in blend_tree.referenced_params() {
+            self.parameters.declare(&parameter);
+        }
+
+        let mut blend_tree = AnimBlendTree::from_def_with_definitions(blend_tree, &self.definitions, animations, self.skeleton.clone(), &self.parameters)?;
+        blend_tree.synchronize(self.local_clock as f32, &self.parameters);
+
+        self.layers.push(AnimationLayer { blend_tree, mode, mask, weight });
+        self.pose_cache = None;
+        Ok(self.layers.len() - 1)
+    }
+
+    /// Set the weight of the layer at the given index, e.g. to fade it in or out
+    pub fn set_layer_weight(&mut self, layer: usize, weight: f32) {
+        if let Some(layer) = self.layers.get_mut(layer) {
+            if layer.weight != weight {
+                layer.weight = weight;
+                self.pose_cache = None;
+            }
+        }
+    }
+
+    /// Return the weight of the layer at the given index, if there is one
+    pub fn get_layer_weight(&self, layer: usize) -> Option<f32> {
+        self.layers.get(layer).map(|layer| layer.weight)
+    }
+
+    /// Discard the cached pose, so that the blend trees are evaluated on the next call to
+    /// `get_output_pose` even if the clock and parameters haven't changed
+    pub fn force_update(&mut self) {
+        self.pose_cache = None;
+    }
+
     /// Calculate global skeletal joint poses for the given time since last update
+    ///
+    /// If the clock, parameters and state are unchanged since the last call, the previously
+    /// blended local poses are reused rather than evaluating the blend trees again.
     pub fn get_output_pose<TOutput: Transform + FromTransform<T>>(&mut self, ext_dt: f64, output_poses: &mut [TOutput]) {
 
-        self.update_state(ext_dt);
+        debug_assert!(
+            output_poses.len() >= self.skeleton.joints.len(),
+            "Output poses ({}) are fewer than the skeleton's joints ({})",
+            output_poses.len(), self.skeleton.joints.len()
+        );
 
+        // Extrapolate the clock at the playback speed, as for `update`
         let elapsed_time = self.local_clock + ext_dt * self.playback_speed;
 
-        let mut local_poses = [ T::identity(); MAX_JOINTS ];
+        self.update_state(elapsed_time);
+        let transition_start_time = self.transition.as_ref().map(|&(start_time, _)| start_time);
+
+        let joint_count = self.skeleton.joints.len().max(output_poses.len());
+        let mut local_poses = self.pose_buffers.take(joint_count);
+
+        match self.pose_cache {
+            Some(ref cache) if cache.local_poses.len() == joint_count
+                && cache.is_valid(elapsed_time, &self.current_state, transition_start_time, &self.parameters) => {
+                local_poses.copy_from_slice(&cache.local_poses[..]);
+            }
+            _ => {
+                self.evaluate_local_poses(elapsed_time, &mut local_poses[..]);
+
+                // Reuse the previous cache's allocation
+                let mut cached_poses = self.pose_cache.take().map(|cache| cache.local_poses).unwrap_or_default();
+                cached_poses.clear();
+                cached_poses.extend_from_slice(&local_poses[..]);
+
+                self.pose_cache = Some(PoseCache {
+                    elapsed_time,
+                    state: self.current_state.clone(),
+                    transition_start_time,
+                    parameters: self.parameters.values().to_vec(),
+                    local_poses: cached_poses,
+                });
+            }
+        }
+
+        for (&joint, &pose) in self.joint_overrides.iter() {
+            if joint < local_poses.len() {
+                local_poses[joint] = pose;
+            }
+        }
+
+        self.calculate_global_poses(&local_poses[..], output_poses);
+
+        // Keep the results for `local_poses` and `global_poses`, returning the previous
+        // local poses to the pool for the next evaluation
+        local_poses.truncate(self.skeleton.joints.len());
+        let previous_local_poses = mem::replace(&mut self.last_local_poses, local_poses);
+        self.pose_buffers.give(previous_local_poses);
+
+        self.last_global_poses.clear();
+        self.last_global_poses.extend(output_poses[.. self.skeleton.joints.len()].iter().map(|pose| pose.to_matrix()));
+    }
+
+    /// The local poses of each joint from the last call to `get_output_pose`, after joint
+    /// overrides but before the import scale, e.g. for further procedural adjustment.
+    /// Empty until `get_output_pose` is first called.
+    pub fn local_poses(&self) -> &[T] {
+        &self.last_local_poses[..]
+    }
+
+    /// The global poses of each joint from the last call to `get_output_pose`, as matrices,
+    /// e.g. for rendering or attaching objects to joints. Empty until `get_output_pose` is
+    /// first called.
+    pub fn global_poses(&self) -> &[Matrix4<f32>] {
+        &self.last_global_poses[..]
+    }
+
+    /// Evaluate the blend trees for the current state, any active transition, and the layers
+    fn evaluate_local_poses(&mut self, elapsed_time: f64, local_poses: &mut [T]) {
 
         {
             let current_state = self.states.get_mut(&self.current_state[..]).unwrap();
             current_state.blend_tree.synchronize(elapsed_time as f32, &self.parameters);
-            current_state.blend_tree.get_output_pose(elapsed_time as f32, &self.parameters, &mut local_poses[..]);
+            current_state.blend_tree.get_output_pose_with_buffers(elapsed_time as f32, &self.parameters, &mut self.pose_buffers, local_poses);
         }
 
         if let Some((transition_start_time, ref transition)) = self.transition {
 
             // Blend with the target state ...
 
-            let mut target_poses = [ T::identity(); MAX_JOINTS ];
+            let mut target_poses = self.pose_buffers.take(local_poses.len());
 
             let target_state = self.states.get_mut(&transition.target_state[..]).unwrap();
             target_state.blend_tree.synchronize(elapsed_time as f32, &self.parameters);
-            target_state.blend_tree.get_output_pose(elapsed_time as f32, &self.parameters, &mut target_poses[..]);
+            target_state.blend_tree.get_output_pose_with_buffers(elapsed_time as f32, &self.parameters, &mut self.pose_buffers, &mut target_poses[..]);
 
-            let blend_parameter = ((self.local_clock + ext_dt - transition_start_time) / transition.duration as f64) as f32;
+            let blend_parameter = ((elapsed_time - transition_start_time) / transition.duration as f64) as f32;
 
-            for i in 0 .. output_poses.len() {
-                let pose_1 = &mut local_poses[i];
-                let pose_2 = target_poses[i];
-                *pose_1 = pose_1.lerp(pose_2, blend_parameter);
+            for (local_pose, target_pose) in local_poses.iter_mut().zip(target_poses.iter()) {
+                *local_pose = local_pose.lerp(*target_pose, blend_parameter);
             }
 
+            self.pose_buffers.give(target_poses);
         }
 
-        self.calculate_global_poses(&local_poses[..], output_poses);
+        for layer in self.layers.iter_mut() {
+
+            if layer.weight <= 0.0 {
+                continue;
+            }
+
+            let mut layer_poses = self.pose_buffers.take(local_poses.len());
+
+            layer.blend_tree.synchronize(elapsed_time as f32, &self.parameters);
+            layer.blend_tree.get_output_pose_with_buffers(elapsed_time as f32, &self.parameters, &mut self.pose_buffers, &mut layer_poses[..]);
+
+            for (joint_index, (local_pose, layer_pose)) in local_poses.iter_mut().zip(layer_poses.iter()).enumerate() {
+
+                let weight = match layer.mask {
+                    Some(ref mask) => layer.weight * mask.get(joint_index).cloned().unwrap_or(0.0),
+                    None => layer.weight,
+                };
+
+                if weight <= 0.0 {
+                    continue;
+                }
+
+                *local_pose = match layer.mode {
+                    LayerMode::Blend => local_pose.lerp(*layer_pose, weight),
+                    LayerMode::Additive => local_pose.concat(T::identity().lerp(*layer_pose, weight)),
+                };
+            }
+
+            self.pose_buffers.give(layer_poses);
+        }
     }
 
-    /// Calculate global poses from the controller's skeleton and the given local poses
+    /// Calculate global poses from the controller's skeleton and the given local poses,
+    /// with their translations scaled by the import scale
     fn calculate_global_poses<TOutput: Transform + FromTransform<T>>(
         &self,
         local_poses: &[T],
@@ -317,8 +785,505 @@ impl<T: Transform> AnimationController<T> {
                 TOutput::identity()
             };
 
-            let local_pose = local_poses[joint_index];
+            let mut local_pose = local_poses[joint_index];
+            if self.import_scale != 1.0 {
+                local_pose.set_translation(vec3_scale(local_pose.get_translation(), self.import_scale));
+            }
+
             global_poses[joint_index] = parent_pose.concat(TOutput::from_transform(local_pose));
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    use std::collections::HashMap;
+
+    use serde_json;
+
+    use Shared;
+    use animation::{AnimationClip, AnimationClipBuilder, LoopMode};
+    use blend_tree::{BlendTreeError, BlendTreeNodeDef};
+    use math::*;
+    use parameter::ParameterSet;
+    use skeleton::{Joint, Skeleton};
+    use transform::{Transform, QVTransform};
+
+    use super::{AnimationController, AnimationControllerDef, AnimationStateDef, AnimationTransition, LayerMode, Operator, ParameterRange, TransitionCondition};
+
+    static EPSILON: f32 = 0.000001;
+
+    /// A controller for a two-joint chain, playing a one second clip that moves
+    /// the root joint along the x-axis by one unit per sample
+    fn make_controller() -> AnimationController<QVTransform> {
+        make_controller_with_loop_mode(None)
+    }
+
+    fn make_controller_with_loop_mode(loop_mode: Option<LoopMode>) -> AnimationController<QVTransform> {
+
+        let skeleton = Shared::new(Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+            Joint::new("child", Some(0), mat4_id()),
+        ]));
+
+        let mut builder = AnimationClipBuilder::new().with_samples_per_second(10.0);
+        for i in 0 .. 10 {
+            let mut root_pose = QVTransform::identity();
+            root_pose.set_translation([i as f32, 0.0, 0.0]);
+            let mut child_pose = QVTransform::identity();
+            child_pose.set_translation([0.0, 1.0, 0.0]);
+            builder.push_sample(vec![root_pose, child_pose]);
+        }
+        let clip = builder.build().unwrap();
+
+        let mut animations = HashMap::new();
+        animations.insert("walk".to_string(), Shared::new(clip));
+
+        let controller_def = AnimationControllerDef {
+            name: "controller".to_string(),
+            parameters: vec![],
+            parameter_ranges: None,
+            definitions: None,
+            states: vec![AnimationStateDef {
+                name: "walking".to_string(),
+                blend_tree: BlendTreeNodeDef::ClipNode { clip_source: "walk".to_string(), loop_mode, phase_param: None },
+                transitions: vec![],
+            }],
+            initial_state: "walking".to_string(),
+        };
+
+        AnimationController::new(controller_def, skeleton, &animations).unwrap()
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn test_controllers_can_be_sent_between_threads() {
+
+        fn assert_send<S: Send>() {}
+        assert_send::<AnimationController<QVTransform>>();
+
+        // Build on a worker thread and animate on this one
+        let mut controller = ::std::thread::spawn(make_controller).join().unwrap();
+        controller.update(0.1);
+        let mut poses = [mat4_id(); 2];
+        controller.get_output_pose(0.1, &mut poses);
+    }
+
+    #[test]
+    fn test_layers() {
+
+        let mut controller = make_controller();
+        controller.update(0.3);
+
+        let single_sample_clip = |root: Vector3<f32>, child: Vector3<f32>| {
+            let mut root_pose = QVTransform::identity();
+            root_pose.set_translation(root);
+            let mut child_pose = QVTransform::identity();
+            child_pose.set_translation(child);
+            let mut builder = AnimationClipBuilder::new().with_samples_per_second(10.0);
+            builder.push_sample(vec![root_pose, child_pose]);
+            Shared::new(builder.build().unwrap())
+        };
+
+        let mut animations = HashMap::new();
+        animations.insert("raise".to_string(), single_sample_clip([0.0, 0.0, 0.0], [0.0, 3.0, 0.0]));
+        animations.insert("lean".to_string(), single_sample_clip([0.0, 0.0, 1.0], [0.0, 0.0, 0.0]));
+
+        let clip = |name: &str| BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None };
+
+        // Halfway towards the raised child, leaving the root as it was
+        let raise = controller.add_layer(clip("raise"), &animations, LayerMode::Blend, Some(vec![0.0, 1.0]), 0.5).unwrap();
+        controller.add_layer(clip("lean"), &animations, LayerMode::Additive, None, 1.0).unwrap();
+
+        let mut global_poses = [mat4_id(); 2];
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+        let child_position = global_poses[1].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(child_position, [3.0, 2.0, 1.0])) < 0.0001);
+
+        // Changing a weight is picked up even though the clock hasn't moved
+        controller.set_layer_weight(raise, 0.0);
+        assert_eq!(controller.get_layer_weight(raise), Some(0.0));
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+        let child_position = global_poses[1].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(child_position, [3.0, 1.0, 1.0])) < 0.0001);
+
+        // A layer whose blend tree can't be built is reported rather than added
+        assert_eq!(controller.add_layer(clip("wave"), &animations, LayerMode::Blend, None, 1.0), Err(BlendTreeError::MissingClip("wave".to_string())));
+        assert_eq!(controller.layers.len(), 2);
+    }
+
+    #[test]
+    fn test_parameter_ranges() {
+
+        let mut controller = make_controller();
+        controller.parameters.declare_with_range("walk-to-run", ParameterRange { min: 0.0, max: 1.0 });
+
+        controller.set_param_value("walk-to-run", 1.5);
+        assert_eq!(controller.get_param_value("walk-to-run"), 1.0);
+        controller.set_param_value("walk-to-run", -0.5);
+        assert_eq!(controller.get_param_value("walk-to-run"), 0.0);
+        controller.set_param_value("walk-to-run", 0.25);
+        assert_eq!(controller.get_param_value("walk-to-run"), 0.25);
+
+        // Parameters without a declared range are stored as given
+        controller.set_param_value("speed", 1.5);
+        assert_eq!(controller.get_param_value("speed"), 1.5);
+    }
+
+    #[test]
+    fn test_set_param_values() {
+
+        let mut controller = make_controller();
+        controller.set_param_value("health", 0.5);
+
+        let mut locomotion = ParameterSet::new();
+        locomotion.set("speed", 2.0);
+        locomotion.set("lean", 0.25);
+
+        let mut aiming = ParameterSet::new();
+        aiming.set("lean", -0.5);
+        aiming.set("aim", 1.0);
+
+        controller.set_param_values(&locomotion);
+        controller.set_param_values(&aiming);
+
+        assert_eq!(controller.get_param_value("health"), 0.5);
+        assert_eq!(controller.get_param_value("speed"), 2.0);
+        assert_eq!(controller.get_param_value("lean"), -0.5);
+        assert_eq!(controller.get_param_value("aim"), 1.0);
+    }
+
+    #[test]
+    fn test_parameter_smoothing() {
+
+        let mut controller = make_controller();
+        controller.set_parameter_smoothing("speed", 0.5);
+        controller.set_param_value("speed", 1.0);
+        assert_eq!(controller.get_param_value("speed"), 0.0);
+
+        // Eases towards the target without overshooting
+        let mut previous_value = 0.0;
+        for _ in 0 .. 30 {
+            controller.update(0.1);
+            let value = controller.get_param_value("speed");
+            assert!(value >= previous_value && value <= 1.0);
+            previous_value = value;
+        }
+        assert!((previous_value - 1.0).abs() < 0.01);
+
+        // Disabling smoothing jumps to the target
+        controller.set_param_value("speed", 2.0);
+        controller.set_parameter_smoothing("speed", 0.0);
+        assert_eq!(controller.get_param_value("speed"), 2.0);
+        controller.set_param_value("speed", 3.0);
+        assert_eq!(controller.get_param_value("speed"), 3.0);
+    }
+
+    #[test]
+    fn test_reset() {
+
+        let mut controller = make_controller();
+        controller.set_parameter_smoothing("speed", 0.5);
+        controller.set_param_value("speed", 1.0);
+        controller.update(0.35);
+        controller.transition = Some((0.3, AnimationTransition {
+            target_state: "walking".to_string(),
+            condition: TransitionCondition { parameter: "speed".to_string(), operator: Operator::GreaterThan, value: 0.5 },
+            duration: 0.2,
+        }));
+
+        let mut global_poses = [mat4_id(); 2];
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+
+        controller.reset();
+        assert_eq!(controller.current_time(), 0.0);
+        assert!(controller.transition.is_none());
+        assert_eq!(controller.get_param_value("speed"), 1.0);
+
+        // Smoothing continues from the set value, rather than the spring's old velocity
+        controller.update(0.1);
+        assert!((controller.get_param_value("speed") - 1.0).abs() < EPSILON);
+
+        controller.reset();
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+        let root_position = global_poses[0].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(root_position) < EPSILON);
+    }
+
+    #[test]
+    fn test_joint_override() {
+
+        let mut controller = make_controller();
+        controller.update(0.2);
+
+        let mut override_pose = QVTransform::identity();
+        override_pose.set_translation([0.0, 0.0, 5.0]);
+        controller.set_joint_override(1, override_pose);
+
+        let mut global_poses = [mat4_id(); 2];
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+        let child_position = global_poses[1].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(child_position, [2.0, 0.0, 5.0])) < EPSILON);
+
+        controller.clear_joint_override(1);
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+        let child_position = global_poses[1].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(child_position, [2.0, 1.0, 0.0])) < EPSILON);
+    }
+
+    #[test]
+    fn test_current_phase() {
+
+        let mut controller = make_controller();
+        assert!(controller.current_phase().abs() < EPSILON);
+
+        controller.update(0.25);
+        assert!((controller.current_time() - 0.25).abs() < EPSILON);
+        assert!((controller.current_phase() - 0.25).abs() < EPSILON);
+
+        controller.update(1.0);
+        assert!((controller.current_phase() - 0.25).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_step_frames() {
+
+        let mut controller = make_controller();
+        controller.set_time(0.52);
+
+        // The clip has 10 samples per second, so the clock snaps to 0.5 before stepping
+        controller.step_frames(3);
+        assert!((controller.current_time() - 0.8).abs() < EPSILON);
+        controller.step_frames(-3);
+        assert!((controller.current_time() - 0.5).abs() < EPSILON);
+
+        for _ in 0 .. 1000 {
+            controller.step_frames(1);
+        }
+        controller.step_frames(-1000);
+        assert_eq!(controller.current_time(), 0.5);
+
+        let mut global_poses = [mat4_id(); 2];
+        controller.step_frames(2);
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+        let root_position = global_poses[0].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(root_position, [7.0, 0.0, 0.0])) < 0.0001);
+    }
+
+    #[test]
+    fn test_is_finished() {
+
+        // The one second clip finishes once played through, unless it loops
+        let mut controller = make_controller_with_loop_mode(Some(LoopMode::Clamp));
+        controller.update(0.5);
+        assert!(!controller.is_finished());
+        controller.update(0.5);
+        assert!(controller.is_finished());
+        controller.update(2.0);
+        assert!(controller.is_finished());
+
+        let mut controller = make_controller();
+        controller.update(3.0);
+        assert!(!controller.is_finished());
+    }
+
+    #[test]
+    fn test_advance() {
+
+        // The clip loops every second
+        let mut controller = make_controller();
+        assert!(!controller.advance(0.6));
+        assert!(controller.advance(0.6));
+        assert!(!controller.advance(0.6));
+
+        // Past several loops in one step
+        assert!(controller.advance(2.5));
+
+        // Backwards past the start
+        controller.set_playback_speed(-1.0);
+        assert!(!controller.advance(0.2));
+        assert!(controller.advance(0.9));
+
+        let mut controller = make_controller_with_loop_mode(Some(LoopMode::Clamp));
+        assert!(!controller.advance(3.0));
+    }
+
+    #[test]
+    fn test_import_scale() {
+
+        let mut controller = make_controller();
+        controller.set_import_scale(0.01);
+        assert_eq!(controller.get_import_scale(), 0.01);
+        controller.update(0.5);
+
+        let mut global_poses = [mat4_id(); 2];
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+        assert!(vec3_len(vec3_sub(global_poses[0].get_translation(), [0.05, 0.0, 0.0])) < EPSILON);
+        assert!(vec3_len(vec3_sub(global_poses[1].get_translation(), [0.05, 0.01, 0.0])) < EPSILON);
+    }
+
+    #[test]
+    fn test_skinning_transforms_at_rest() {
+
+        let root_bind_pose = QVTransform {
+            translation: [0.0, 1.0, 0.0],
+            scale: 1.0,
+            rotation: quaternion::axis_angle([0.0, 1.0, 0.0], 0.5),
+        }.to_matrix();
+        let mut child_local_pose = mat4_id();
+        child_local_pose.set_translation([0.0, 2.0, 1.0]);
+
+        let skeleton = Shared::new(Skeleton::new(vec![
+            Joint::new("root", None, root_bind_pose),
+            Joint::new("child", Some(0), row_mat4_mul(root_bind_pose, child_local_pose)),
+        ]));
+
+        let controller_def = AnimationControllerDef {
+            name: "controller".to_string(),
+            parameters: vec![],
+            parameter_ranges: None,
+            definitions: None,
+            states: vec![AnimationStateDef {
+                name: "rest".to_string(),
+                blend_tree: BlendTreeNodeDef::PoseNode { pose_source: None },
+                transitions: vec![],
+            }],
+            initial_state: "rest".to_string(),
+        };
+        let animations: HashMap<String, Shared<AnimationClip<QVTransform>>> = HashMap::new();
+        let mut controller = AnimationController::new(controller_def, skeleton, &animations).unwrap();
+
+        // In the bind pose, skinning leaves the mesh as it is, whatever the import scale
+        for &import_scale in [1.0, 0.01].iter() {
+            controller.set_import_scale(import_scale);
+            let mut global_poses = [mat4_id(); 2];
+            controller.get_output_pose(0.0, &mut global_poses[..]);
+
+            let mut skinning_transforms = [mat4_id(); 2];
+            controller.calculate_skinning_transforms(&global_poses[..], &mut skinning_transforms[..]);
+            for skinning_transform in skinning_transforms.iter() {
+                for (row, identity_row) in skinning_transform.iter().zip(mat4_id::<f32>().iter()) {
+                    for (x, identity_x) in row.iter().zip(identity_row.iter()) {
+                        assert!((x - identity_x).abs() < 0.0001);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_local_and_global_poses() {
+
+        let mut controller = make_controller();
+        assert!(controller.local_poses().is_empty());
+        assert!(controller.global_poses().is_empty());
+
+        controller.update(0.3);
+        let mut global_poses = [mat4_id(); 2];
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+
+        assert_eq!(controller.local_poses().len(), 2);
+        assert!(vec3_len(vec3_sub(controller.local_poses()[1].get_translation(), [0.0, 1.0, 0.0])) < EPSILON);
+
+        assert_eq!(controller.global_poses().len(), 2);
+        let child_position = controller.global_poses()[1].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(child_position, [3.0, 1.0, 0.0])) < 0.0001);
+        assert_eq!(controller.global_poses(), &global_poses[..]);
+    }
+
+    #[test]
+    fn test_playback_speed() {
+
+        let mut controller = make_controller();
+        controller.set_playback_speed(0.5);
+        controller.update(0.4);
+        assert!((controller.current_time() - 0.2).abs() < EPSILON);
+
+        // Extrapolated time is scaled too
+        let mut global_poses = [mat4_id(); 2];
+        controller.get_output_pose(0.2, &mut global_poses[..]);
+        let root_position = global_poses[0].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(root_position, [3.0, 0.0, 0.0])) < 0.0001);
+
+        controller.set_playback_speed(-1.0);
+        assert_eq!(controller.get_playback_speed(), -1.0);
+        controller.update(0.1);
+        assert!((controller.current_time() - 0.1).abs() < EPSILON);
+
+        // The same global time scale, as an f32 rate
+        controller.set_playback_rate(2.0);
+        assert_eq!(controller.get_playback_speed(), 2.0);
+        controller.update(0.1);
+        assert!((controller.current_time() - 0.3).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_pose_cache() {
+
+        let mut controller = make_controller();
+        controller.update(0.2);
+
+        let mut global_poses = [mat4_id(); 2];
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+
+        // Tamper with the cached poses, so we can tell whether they were reused
+        let mut marker_pose = QVTransform::identity();
+        marker_pose.set_translation([0.0, 7.0, 0.0]);
+        controller.pose_cache.as_mut().unwrap().local_poses[0] = marker_pose;
+
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+        let root_position = global_poses[0].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(root_position, [0.0, 7.0, 0.0])) < EPSILON);
+
+        controller.force_update();
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+        let root_position = global_poses[0].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(root_position, [2.0, 0.0, 0.0])) < EPSILON);
+
+        // Advancing the clock invalidates the cache
+        controller.pose_cache.as_mut().unwrap().local_poses[0] = marker_pose;
+        controller.update(0.1);
+        controller.get_output_pose(0.0, &mut global_poses[..]);
+        let root_position = global_poses[0].transform_vector([0.0, 0.0, 0.0]);
+        assert!(vec3_len(vec3_sub(root_position, [3.0, 0.0, 0.0])) < EPSILON);
+    }
+
+    #[test]
+    fn test_decode_controller_def() {
+
+        let source = r#"{
+            "name": "human-controller",
+            "parameters": ["forward-speed", "walk-to-run"],
+            "parameter_ranges": {
+                "walk-to-run": { "min": 0.0, "max": 1.0 }
+            },
+            "states": [{
+                "name": "stand-idle",
+                "blend_tree": {
+                    "type": "ClipNode",
+                    "clip_source": "stand-idle"
+                },
+                "transitions": [{
+                    "target_state": "walking-forward",
+                    "duration": 0.5,
+                    "condition": {
+                        "parameter": "forward-speed",
+                        "operator": ">=",
+                        "value": 0.1
+                    }
+                }]
+            }],
+            "initial_state": "stand-idle"
+        }"#;
+
+        let def: AnimationControllerDef = serde_json::from_str(source).unwrap();
+        assert_eq!(def.states.len(), 1);
+        assert_eq!(def.parameter_ranges.as_ref().unwrap()["walk-to-run"], ParameterRange { min: 0.0, max: 1.0 });
+
+        match def.states[0].transitions[0].condition.operator {
+            Operator::GreaterThanEqual => {},
+            ref operator => panic!("Unexpected operator {:?}", operator),
+        }
+    }
+}
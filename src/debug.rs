@@ -0,0 +1,209 @@
+//! Debug drawing of skeletons through any line and text renderer implementing
+//! `SkeletonDebugDraw`, with an implementation for gfx_debug_draw available with the
+//! `debug` feature.
+
+#[cfg(feature = "debug")]
+use gfx;
+#[cfg(feature = "debug")]
+use gfx_debug_draw;
+
+use math::*;
+use skeleton::{Joint, Skeleton};
+use transform::Transform;
+
+/// A renderer of debug lines and text, e.g. an engine's own debug drawing, that skeletons can
+/// be drawn with
+pub trait SkeletonDebugDraw {
+    /// Draw a line between two points in world space
+    fn line(&mut self, a: Vector3<f32>, b: Vector3<f32>, color: [f32; 4]);
+
+    /// Draw text at a point in world space
+    fn text(&mut self, position: Vector3<f32>, text: &str, color: [f32; 4]);
+}
+
+#[cfg(feature = "debug")]
+impl<R: gfx::Resources, F: gfx::Factory<R>> SkeletonDebugDraw for gfx_debug_draw::DebugRenderer<R, F> {
+    fn line(&mut self, a: Vector3<f32>, b: Vector3<f32>, color: [f32; 4]) {
+        self.draw_line(a, b, color);
+    }
+
+    fn text(&mut self, position: Vector3<f32>, text: &str, color: [f32; 4]) {
+        self.draw_text_at_position(text, position, color);
+    }
+}
+
+/// How a joint's bone is drawn by `Skeleton::draw_skeleton_styled`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoneStyle {
+    /// Color of the line to the parent joint, and of any leaf extension
+    pub color: [f32; 4],
+}
+
+impl Default for BoneStyle {
+    fn default() -> Self {
+        Self { color: [0.2, 0.2, 0.2, 1.0] }
+    }
+}
+
+/// Which parts of a skeleton `Skeleton::draw_skeleton_styled` draws, besides the bones.
+/// By default, the axes and leaf extensions are drawn, but not the labels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkeletonDrawOptions {
+    /// Label each joint with its name
+    pub labels: bool,
+
+    /// Draw the x, y and z axes of each joint
+    pub axes: bool,
+
+    /// Extend each leaf joint along its y-axis
+    pub leaf_extensions: bool,
+}
+
+impl Default for SkeletonDrawOptions {
+    fn default() -> Self {
+        Self { labels: false, axes: true, leaf_extensions: true }
+    }
+}
+
+impl Skeleton {
+
+    /// Draw the skeleton in the given global poses with gfx_debug_draw, as lines between
+    /// each joint and its parent, with the axes of each joint, and optionally its name
+    #[cfg(feature = "debug")]
+    pub fn draw<R: gfx::Resources, F: gfx::Factory<R>, T: Transform> (
+        &self,
+        global_poses: &[T],
+        debug_renderer: &mut gfx_debug_draw::DebugRenderer<R, F>,
+        draw_labels: bool)
+    {
+        self.draw_skeleton(global_poses, debug_renderer, draw_labels);
+    }
+
+    /// Draw the skeleton in the given global poses with any SkeletonDebugDraw renderer, as
+    /// lines between each joint and its parent, with the axes of each joint, and optionally
+    /// its name
+    pub fn draw_skeleton<T: Transform, D: SkeletonDebugDraw + ?Sized> (
+        &self,
+        global_poses: &[T],
+        debug_draw: &mut D,
+        draw_labels: bool)
+    {
+        let options = SkeletonDrawOptions { labels: draw_labels, ..SkeletonDrawOptions::default() };
+        self.draw_skeleton_styled(global_poses, debug_draw, &options, |_, _| Some(BoneStyle::default()));
+    }
+
+    /// Like `draw_skeleton`, with `options` choosing which parts are drawn, and `style`
+    /// choosing the style of each joint's bone by joint index, or None to skip the joint
+    /// entirely, e.g. to highlight an IK chain or a selected subtree
+    pub fn draw_skeleton_styled<T, D, S> (
+        &self,
+        global_poses: &[T],
+        debug_draw: &mut D,
+        options: &SkeletonDrawOptions,
+        mut style: S)
+    where
+        T: Transform,
+        D: SkeletonDebugDraw + ?Sized,
+        S: FnMut(usize, &Joint) -> Option<BoneStyle>,
+    {
+
+        for (joint_index, joint) in self.joints.iter().enumerate() {
+
+            let bone_style = match style(joint_index, joint) {
+                Some(bone_style) => bone_style,
+                None => continue,
+            };
+
+            let joint_position = global_poses[joint_index].transform_vector([0.0, 0.0, 0.0]);
+            let leaf_end = global_poses[joint_index].transform_vector([0.0, 1.0, 0.0]);
+
+            if !joint.is_root() {
+
+                let parent_position = global_poses[joint.parent_index as usize].transform_vector([0.0, 0.0, 0.0]);
+
+                // Draw bone (between joint and parent joint)
+
+                debug_draw.line(parent_position, joint_position, bone_style.color);
+
+                if options.leaf_extensions && !self.joints.iter().any(|j| j.parent_index as usize == joint_index) {
+
+                    // Draw extension along joint's y-axis...
+                    debug_draw.line(joint_position, leaf_end, bone_style.color);
+                }
+            }
+
+            if options.labels {
+                // Label joint
+                debug_draw.text(leaf_end, &joint.name[..], [1.0, 1.0, 1.0, 1.0]);
+            }
+
+            if options.axes {
+                // Draw joint-relative axes
+                let p_x_axis = global_poses[joint_index].transform_vector([1.0, 0.0, 0.0]);
+                let p_y_axis = global_poses[joint_index].transform_vector([0.0, 1.0, 0.0]);
+                let p_z_axis = global_poses[joint_index].transform_vector([0.0, 0.0, 1.0]);
+
+                debug_draw.line(joint_position, p_x_axis, [1.0, 0.2, 0.2, 1.0]);
+                debug_draw.line(joint_position, p_y_axis, [0.2, 1.0, 0.2, 1.0]);
+                debug_draw.line(joint_position, p_z_axis, [0.2, 0.2, 1.0, 1.0]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use math::*;
+    use skeleton::{Joint, Skeleton};
+
+    use super::{BoneStyle, SkeletonDebugDraw, SkeletonDrawOptions};
+
+    /// Records the lines and text drawn, rather than rendering them
+    #[derive(Default)]
+    struct RecordingDraw {
+        lines: Vec<(Vector3<f32>, Vector3<f32>, [f32; 4])>,
+        text: Vec<String>,
+    }
+
+    impl SkeletonDebugDraw for RecordingDraw {
+        fn line(&mut self, a: Vector3<f32>, b: Vector3<f32>, color: [f32; 4]) {
+            self.lines.push((a, b, color));
+        }
+
+        fn text(&mut self, _position: Vector3<f32>, text: &str, _color: [f32; 4]) {
+            self.text.push(text.to_string());
+        }
+    }
+
+    #[test]
+    fn test_draw_skeleton() {
+
+        let skeleton = Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+            Joint::new("child", Some(0), mat4_id()),
+        ]);
+
+        let mut child_pose = mat4_id();
+        child_pose[0][3] = 2.0;
+        let global_poses = [mat4_id(), child_pose];
+
+        let mut draw = RecordingDraw::default();
+        skeleton.draw_skeleton(&global_poses[..], &mut draw, true);
+
+        // Three axes per joint, plus the child's bone and leaf extension
+        assert_eq!(draw.lines.len(), 8);
+        assert!(draw.lines.contains(&([0.0, 0.0, 0.0], [2.0, 0.0, 0.0], BoneStyle::default().color)));
+        assert_eq!(draw.text, ["root", "child"]);
+
+        // Only the child's bone, highlighted, without axes or leaf extension
+        let highlight = BoneStyle { color: [1.0, 1.0, 0.0, 1.0] };
+        let options = SkeletonDrawOptions { labels: false, axes: false, leaf_extensions: false };
+        let mut draw = RecordingDraw::default();
+        skeleton.draw_skeleton_styled(&global_poses[..], &mut draw, &options, |joint_index, _| {
+            if joint_index == 1 { Some(highlight) } else { None }
+        });
+        assert_eq!(draw.lines, [([0.0, 0.0, 0.0], [2.0, 0.0, 0.0], highlight.color)]);
+        assert!(draw.text.is_empty());
+    }
+}
@@ -1,42 +1,89 @@
 //! A library for data-driven skeletal animation.
 
+// Argument docs align their continuation lines with the description
+#![allow(clippy::doc_overindented_list_items)]
+
 extern crate collada;
-#[macro_use]
+#[cfg(any(feature = "render", feature = "debug"))]
+#[cfg_attr(feature = "render", macro_use)]
 extern crate gfx;
+#[cfg(feature = "debug")]
 extern crate gfx_debug_draw;
+#[cfg(feature = "render")]
 extern crate gfx_texture;
 pub extern crate quaternion;
 pub extern crate dual_quaternion;
 extern crate vecmath;
 extern crate interpolation;
-extern crate rustc_serialize;
-extern crate float;
+extern crate serde;
+extern crate serde_json;
 
 pub mod animation;
+#[cfg(feature = "render")]
 pub mod skinned_renderer;
 pub mod blend_tree;
+pub mod compression;
+pub mod constraints;
 pub mod controller;
 pub mod manager;
+pub mod parameter;
 pub mod skeleton;
+pub mod skinning;
 pub mod math;
+pub mod debug;
 mod transform;
 
+/// The pointer that blend trees, controllers and the asset manager share clips and
+/// skeletons through: `Rc`, or `Arc` with the `sync` feature, so that clips loaded on
+/// worker threads can be shared with trees on other threads
+#[cfg(not(feature = "sync"))]
+pub type Shared<T> = std::rc::Rc<T>;
+
+/// With the `sync` feature, clips and skeletons are shared through `Arc`
+#[cfg(feature = "sync")]
+pub type Shared<T> = std::sync::Arc<T>;
+
 pub use animation::{
     AnimationClip,
+    AnimationClipBuilder,
     AnimationSample,
+    ChannelMask,
+    Handedness,
+    Interpolation,
+    LoopMode,
+    MirrorAxis,
+    PoseAccumulator,
+    blend_poses,
+    pose_distance,
+    clip_distance,
 };
 
+pub use compression::CompressedAnimationClip;
+
+pub use constraints::{JointLimit, JointLimits, LookAt, apply_look_at, clamp_pose_to_limits};
+
 pub use transform::{Transform, QVTransform, FromTransform};
 
 pub use skeleton::{
+    Joint,
+    JointIndex,
     Skeleton,
+    SkeletonError,
+    Socket,
+    ColladaError,
 };
 
 pub use manager::{
     AssetManager,
     AssetDefs,
+    AssetError,
 };
 
-pub use controller::AnimationController;
+pub use controller::{AnimationController, LayerMode};
+
+pub use debug::{BoneStyle, SkeletonDebugDraw, SkeletonDrawOptions};
+
+pub use parameter::{ParameterRange, ParameterSet};
 
-pub use skinned_renderer::{SkinnedRenderer, HasShaderSources};
+#[cfg(feature = "render")]
+pub use skinned_renderer::{SkinnedRenderer, SkinnedRendererError, SkinnedVertex, HasShaderSources, JointInfluences, TextureView};
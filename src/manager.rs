@@ -1,47 +1,204 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+#[cfg(not(target_arch = "wasm32"))]
 use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
 use std::io::Read;
-use std::rc::Rc;
 
-use rustc_serialize::{Decodable, json};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json;
 
+use Shared;
 use animation::{AnimationClip, AnimationClipDef, DifferenceClipDef};
+use blend_tree::{BlendTreeDef, BlendTreeError, BlendTreeNodeDef, ClipId, ParamId};
+use skeleton::{ColladaError, Skeleton, SkeletonDef, parse_collada_bytes};
 use transform::Transform;
 use controller::AnimationControllerDef;
 
 /// A collection of asset definitions, to be loaded from a JSON definition file
-#[derive(Debug, RustcDecodable)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AssetDefs {
+    skeletons: Option<Vec<SkeletonDef>>,
     animation_clips: Option<Vec<AnimationClipDef>>,
     difference_clips: Option<Vec<DifferenceClipDef>>,
+    blend_trees: Option<Vec<BlendTreeDef>>,
     animation_controllers: Option<Vec<AnimationControllerDef>>,
 }
 
+/// An error encountered while loading assets
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetError {
+    /// The file at the given path couldn't be opened or read
+    Io { path: String, message: String },
+
+    /// The JSON file at the given path couldn't be parsed, or doesn't describe a valid asset
+    Parse { path: String, message: String },
+
+    /// The named difference clip references a clip that isn't loaded
+    MissingClip { name: String, clip: ClipId },
+
+    /// The COLLADA source of the named skeleton or clip contains no skeleton
+    MissingSkeleton { name: String, source: String },
+
+    /// The named skeleton or clip couldn't be loaded from its COLLADA source
+    Collada { name: String, source: String, reason: &'static str },
+
+    /// The named blend tree or animation controller references clips, parameters or
+    /// sub-tree definitions that aren't available, or is nested too deeply
+    InvalidBlendTree { name: String, errors: Vec<BlendTreeError> },
+
+    /// The initial state of the named animation controller, or the target state of one of
+    /// its transitions, isn't one of its states
+    MissingState { name: String, state: String },
+
+    /// A transition condition of the named animation controller compares a parameter that
+    /// the controller doesn't declare
+    UnknownParam { name: String, param: ParamId },
+}
+
 ///
 /// Asset manager - manages memory for loaded assets...?
 ///
 pub struct AssetManager<T: Transform> {
-    pub animation_clips: HashMap<String, Rc<AnimationClip<T>>>,
+    pub skeletons: HashMap<String, Shared<Skeleton>>,
+    pub animation_clips: HashMap<String, Shared<AnimationClip<T>>>,
+    pub blend_tree_defs: HashMap<String, BlendTreeNodeDef>,
     pub controller_defs: HashMap<String, AnimationControllerDef>
 }
 
+impl AssetError {
+    /// The error for the named asset failing to load from its COLLADA source
+    fn from_source(name: &str, source: &str, error: ColladaError) -> Self {
+        match error {
+            ColladaError::Io(message) => AssetError::Io { path: source.to_string(), message },
+            ColladaError::MissingSkeleton => AssetError::MissingSkeleton { name: name.to_string(), source: source.to_string() },
+            error => AssetError::Collada { name: name.to_string(), source: source.to_string(), reason: error.reason() },
+        }
+    }
+}
+
+impl<T: Transform> Default for AssetManager<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Transform> AssetManager<T> {
 
     pub fn new() -> Self {
         Self {
+            skeletons: HashMap::new(),
             animation_clips: HashMap::new(),
+            blend_tree_defs: HashMap::new(),
             controller_defs: HashMap::new(),
         }
     }
 
-    pub fn load_assets(&mut self, path: &str) {
+    /// Load every skeleton, clip, blend tree and controller listed in the manifest at the
+    /// given path, continuing past entries that fail to load.
+    ///
+    /// All entries that loaded successfully are added to the manager. Blend trees are only
+    /// added if every clip they reference is available, and they're no deeper than
+    /// `MAX_BLEND_TREE_DEPTH`. Controllers are only added if they pass the same checks,
+    /// and their initial state, the target states of their transitions, and the parameters
+    /// of their transition conditions exist; see `validate_controller_def`.
+    /// Returns the errors for all entries that failed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_manifest(&mut self, path: &str) -> Result<(), Vec<AssetError>> {
 
-        let asset_defs: AssetDefs = AssetManager::<T>::load_def_from_path(path).unwrap();
+        let asset_defs: AssetDefs = AssetManager::<T>::load_def_from_path(path).map_err(|error| vec![error])?;
+
+        let mut errors = Vec::new();
+
+        for skeleton_def in asset_defs.skeletons.unwrap_or_default().iter() {
+            match Skeleton::from_def(skeleton_def) {
+                Ok(skeleton) => {
+                    self.skeletons.insert(skeleton_def.name.clone(), Shared::new(skeleton));
+                }
+                Err(error) => errors.push(AssetError::from_source(&skeleton_def.name, &skeleton_def.source, error)),
+            }
+        }
+
+        for clip_def in asset_defs.animation_clips.unwrap_or_default().iter() {
+            match AnimationClip::try_from_def(clip_def) {
+                Ok(clip) => {
+                    self.animation_clips.insert(clip_def.name.clone(), Shared::new(clip));
+                }
+                Err(error) => errors.push(AssetError::from_source(&clip_def.name, &clip_def.source, error)),
+            }
+        }
+
+        for difference_clip_def in asset_defs.difference_clips.unwrap_or_default().iter() {
+
+            let clip = {
+                let source_clip = self.animation_clips.get(&difference_clip_def.source_clip[..]);
+                let reference_clip = self.animation_clips.get(&difference_clip_def.reference_clip[..]);
+
+                match (source_clip, reference_clip) {
+                    (Some(source_clip), Some(reference_clip)) => AnimationClip::as_difference_clip(source_clip, reference_clip),
+                    (None, _) => {
+                        errors.push(AssetError::MissingClip {
+                            name: difference_clip_def.name.clone(),
+                            clip: difference_clip_def.source_clip.clone(),
+                        });
+                        continue;
+                    }
+                    (_, None) => {
+                        errors.push(AssetError::MissingClip {
+                            name: difference_clip_def.name.clone(),
+                            clip: difference_clip_def.reference_clip.clone(),
+                        });
+                        continue;
+                    }
+                }
+            };
+
+            self.animation_clips.insert(difference_clip_def.name.clone(), Shared::new(clip));
+        }
+
+        for blend_tree_def in asset_defs.blend_trees.unwrap_or_default().into_iter() {
+
+            // Standalone blend trees don't declare their parameters, so ignore unknown ones
+            let tree_errors: Vec<BlendTreeError> = match blend_tree_def.blend_tree.validate(&self.animation_clips, &HashSet::new()) {
+                Ok(()) => Vec::new(),
+                Err(errors) => errors.into_iter().filter(|error| !matches!(*error, BlendTreeError::UnknownParam(_))).collect(),
+            };
+
+            if tree_errors.is_empty() {
+                self.blend_tree_defs.insert(blend_tree_def.name, blend_tree_def.blend_tree);
+            } else {
+                errors.push(AssetError::InvalidBlendTree {
+                    name: blend_tree_def.name,
+                    errors: tree_errors,
+                });
+            }
+        }
+
+        for controller_def in asset_defs.animation_controllers.unwrap_or_default().into_iter() {
+            let controller_errors = self.validate_controller_def(&controller_def);
+            if controller_errors.is_empty() {
+                self.controller_defs.insert(controller_def.name.clone(), controller_def);
+            } else {
+                errors.extend(controller_errors);
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Load the clips, difference clips and controllers listed in the definition file at
+    /// the given path, stopping at the first entry that fails to load. Entries loaded
+    /// before the failure remain in the manager.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_assets(&mut self, path: &str) -> Result<(), AssetError> {
+
+        let asset_defs: AssetDefs = AssetManager::<T>::load_def_from_path(path)?;
 
         if let Some(animation_clips) = asset_defs.animation_clips {
             for clip_def in animation_clips.iter() {
-                let clip = AnimationClip::from_def(clip_def);
-                self.animation_clips.insert(clip_def.name.clone(), Rc::new(clip));
+                let clip = AnimationClip::try_from_def(clip_def)
+                    .map_err(|error| AssetError::from_source(&clip_def.name, &clip_def.source, error))?;
+                self.animation_clips.insert(clip_def.name.clone(), Shared::new(clip));
             }
         }
 
@@ -49,39 +206,331 @@ impl<T: Transform> AssetManager<T> {
             for difference_clip_def in difference_clips.iter() {
 
                 let clip = {
-                    let ref source_clip = self.animation_clips[&difference_clip_def.source_clip[..]];
-                    let ref reference_clip = self.animation_clips[&difference_clip_def.reference_clip[..]];
+                    let get_clip = |clip_id: &ClipId| self.animation_clips.get(&clip_id[..]).ok_or_else(|| AssetError::MissingClip {
+                        name: difference_clip_def.name.clone(),
+                        clip: clip_id.clone(),
+                    });
+                    let source_clip = get_clip(&difference_clip_def.source_clip)?;
+                    let reference_clip = get_clip(&difference_clip_def.reference_clip)?;
                     AnimationClip::as_difference_clip(source_clip, reference_clip)
                 };
 
-                self.animation_clips.insert(difference_clip_def.name.clone(), Rc::new(clip));
+                self.animation_clips.insert(difference_clip_def.name.clone(), Shared::new(clip));
             }
         }
 
         if let Some(animation_controllers) = asset_defs.animation_controllers {
-            for controller_def in animation_controllers.iter() {
-                self.controller_defs.insert(controller_def.name.clone(), controller_def.clone());
+            for controller_def in animation_controllers.into_iter() {
+                if let Some(error) = self.validate_controller_def(&controller_def).into_iter().next() {
+                    return Err(error);
+                }
+                self.controller_defs.insert(controller_def.name.clone(), controller_def);
             }
         }
+
+        Ok(())
     }
 
-    pub fn load_def_from_path<D>(path: &str) -> Result<D, &'static str>
-        where D: Decodable
-    {
-        let file_result = File::open(path);
+    /// Check an animation controller definition against the clips loaded so far, returning
+    /// every problem found: an `InvalidBlendTree` error for problems with its state blend
+    /// trees, and a `MissingState` or `UnknownParam` error for each missing state or
+    /// undeclared transition parameter. Its parameters are those from `declared_params`, so
+    /// blend tree parameters needn't be listed, as for `AnimationController::new`.
+    pub fn validate_controller_def(&self, controller_def: &AnimationControllerDef) -> Vec<AssetError> {
+
+        let parameters: HashSet<ParamId> = controller_def.declared_params().into_iter().collect();
+        let definitions = controller_def.definitions.clone().unwrap_or_default();
+        let has_state = |state: &str| controller_def.states.iter().any(|state_def| state_def.name == state);
+
+        let mut tree_errors = Vec::new();
+        for state_def in controller_def.states.iter() {
+            if let Err(state_errors) = state_def.blend_tree.validate_with_definitions(&definitions, &self.animation_clips, &parameters) {
+                for error in state_errors.into_iter() {
+                    if !tree_errors.contains(&error) {
+                        tree_errors.push(error);
+                    }
+                }
+            }
+        }
 
-        let mut file = match file_result {
-            Ok(file) => file,
-            Err(_) => return Err("Failed to open definition file at path.")
+        let mut errors = Vec::new();
+        if !tree_errors.is_empty() {
+            errors.push(AssetError::InvalidBlendTree { name: controller_def.name.clone(), errors: tree_errors });
+        }
+
+        let mut add_error = |error: AssetError| {
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
         };
 
-        let mut json_string = String::new();
-        match file.read_to_string(&mut json_string) {
-            Ok(_) => {},
-            Err(_) => return Err("Failed to read definition file.")
+        if !has_state(&controller_def.initial_state) {
+            add_error(AssetError::MissingState { name: controller_def.name.clone(), state: controller_def.initial_state.clone() });
+        }
+
+        for transition in controller_def.states.iter().flat_map(|state_def| state_def.transitions.iter()) {
+            if !has_state(&transition.target_state) {
+                add_error(AssetError::MissingState { name: controller_def.name.clone(), state: transition.target_state.clone() });
+            }
+            if !parameters.contains(&transition.condition.parameter) {
+                add_error(AssetError::UnknownParam { name: controller_def.name.clone(), param: transition.condition.parameter.clone() });
+            }
+        }
+
+        errors
+    }
+
+    /// Load an AnimationClip serialized as JSON, adding it to the manager with the given
+    /// name. Returns an error if the file can't be parsed, or the clip has no samples,
+    /// samples with differing joint counts, or a sample time for each sample missing.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_clip_json(&mut self, name: &str, path: &str) -> Result<(), AssetError>
+        where T: DeserializeOwned
+    {
+        let clip = AssetManager::<T>::load_def_from_path(path)?;
+        self.insert_json_clip(name, path, clip)
+    }
+
+    /// Like `load_clip_json`, but parsing the clip from JSON bytes rather than reading a
+    /// file, e.g. for assets fetched over the network. Errors name `name` as their path.
+    pub fn load_clip_from_bytes(&mut self, name: &str, bytes: &[u8]) -> Result<(), AssetError>
+        where T: DeserializeOwned
+    {
+        let clip = AssetManager::<T>::load_def_from_bytes(name, bytes)?;
+        self.insert_json_clip(name, name, clip)
+    }
+
+    /// Load an AnimationClip from the bytes of a COLLADA document, as the clips listed
+    /// in a manifest are loaded from their source files, and add it to the manager under
+    /// the definition's name. The definition's `source` is only used in errors.
+    pub fn load_collada_clip_from_bytes(&mut self, clip_def: &AnimationClipDef, bytes: &[u8]) -> Result<(), AssetError> {
+        let clip = parse_collada_bytes(bytes)
+            .and_then(|collada_document| AnimationClip::from_def_and_document(clip_def, &collada_document))
+            .map_err(|error| AssetError::from_source(&clip_def.name, &clip_def.source, error))?;
+        self.animation_clips.insert(clip_def.name.clone(), Shared::new(clip));
+        Ok(())
+    }
+
+    /// Check a clip loaded from JSON, and add it to the manager with the given name
+    fn insert_json_clip(&mut self, name: &str, path: &str, clip: AnimationClip<T>) -> Result<(), AssetError> {
+
+        let invalid = |message: &str| Err(AssetError::Parse { path: path.to_string(), message: message.to_string() });
+
+        let joint_count = match clip.samples.first() {
+            Some(sample) => sample.local_poses.len(),
+            None => return invalid("Clip has no samples."),
         };
 
-        Ok(json::decode(&json_string[..]).unwrap())
+        if clip.samples.iter().any(|sample| sample.local_poses.len() != joint_count) {
+            return invalid("Clip samples have differing joint counts.");
+        }
+
+        if clip.sample_times.as_ref().is_some_and(|sample_times| sample_times.len() != clip.samples.len()) {
+            return invalid("Clip sample times don't match its samples.");
+        }
+
+        self.animation_clips.insert(name.to_string(), Shared::new(clip));
+        Ok(())
+    }
+
+    /// Load and parse the JSON definition file at the given path
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_def_from_path<D>(path: &str) -> Result<D, AssetError>
+        where D: DeserializeOwned
+    {
+        let io_error = |error: ::std::io::Error| AssetError::Io { path: path.to_string(), message: error.to_string() };
+
+        let mut file = File::open(path).map_err(io_error)?;
+
+        let mut json_bytes = Vec::new();
+        file.read_to_end(&mut json_bytes).map_err(io_error)?;
+
+        AssetManager::<T>::load_def_from_bytes(path, &json_bytes[..])
+    }
+
+    /// Parse a JSON definition from bytes, without reading a file, e.g. for definitions
+    /// fetched over the network. Errors name `path` as the source of the bytes.
+    pub fn load_def_from_bytes<D>(path: &str, bytes: &[u8]) -> Result<D, AssetError>
+        where D: DeserializeOwned
+    {
+        serde_json::from_slice(bytes).map_err(|error| AssetError::Parse { path: path.to_string(), message: error.to_string() })
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    use serde_json;
+
+    use animation::AnimationClipDef;
+    use blend_tree::BlendTreeNodeDef;
+    use transform::QVTransform;
+
+    use super::{AssetManager, AssetError};
+
+    #[test]
+    fn test_load_manifest_collects_errors() {
+
+        let path = env::temp_dir().join("skeletal_animation_test_manifest.json");
+        File::create(&path).unwrap().write_all(br#"{
+            "skeletons": [{ "name": "guy", "source": "missing/guy.dae" }],
+            "animation_clips": [{ "name": "walk", "source": "missing/walk.dae" }],
+            "difference_clips": [{ "name": "walk-additive", "source_clip": "walk", "reference_clip": "walk" }],
+            "blend_trees": [{ "name": "walk-tree", "blend_tree": { "type": "ClipNode", "clip_source": "walk" } }],
+            "animation_controllers": [{
+                "name": "empty-controller",
+                "parameters": [],
+                "states": [],
+                "initial_state": "none"
+            }]
+        }"#).unwrap();
+
+        let mut asset_manager = AssetManager::<QVTransform>::new();
+        let errors = asset_manager.load_manifest(path.to_str().unwrap()).unwrap_err();
+
+        assert_eq!(errors.len(), 5);
+        match errors[1] {
+            AssetError::Io { ref path, .. } => assert_eq!(path, "missing/walk.dae"),
+            ref error => panic!("Unexpected error {:?}", error),
+        }
+        assert_eq!(errors[4], AssetError::MissingState { name: "empty-controller".to_string(), state: "none".to_string() });
+        assert!(!asset_manager.controller_defs.contains_key("empty-controller"));
+
+        let errors = asset_manager.load_manifest("missing/manifest.json").unwrap_err();
+        match errors[..] {
+            [AssetError::Io { ref path, .. }] => assert_eq!(path, "missing/manifest.json"),
+            ref errors => panic!("Unexpected errors {:?}", errors),
+        }
+
+        // load_assets stops at the first error
+        match asset_manager.load_assets(path.to_str().unwrap()) {
+            Err(AssetError::Io { ref path, .. }) => assert_eq!(path, "missing/walk.dae"),
+            ref result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_load_manifest_validates_controllers() {
+
+        let path = env::temp_dir().join("skeletal_animation_test_controller_manifest.json");
+        File::create(&path).unwrap().write_all(br#"{
+            "animation_controllers": [{
+                "name": "locomotion",
+                "parameters": ["grounded"],
+                "states": [{
+                    "name": "walk",
+                    "blend_tree": {
+                        "type": "LerpNode",
+                        "inputs": [{ "type": "PoseNode" }, { "type": "PoseNode" }],
+                        "param": "walk-to-run"
+                    },
+                    "transitions": [
+                        { "target_state": "walk", "condition": { "parameter": "walk-to-run", "operator": ">", "value": 0.5 }, "duration": 0.2 },
+                        { "target_state": "walk", "condition": { "parameter": "grounded", "operator": "=", "value": 0.0 }, "duration": 0.2 },
+                        { "target_state": "fall", "condition": { "parameter": "grunded", "operator": "=", "value": 0.0 }, "duration": 0.2 }
+                    ]
+                }],
+                "initial_state": "walk"
+            }]
+        }"#).unwrap();
+
+        // Parameters used by the blend tree are declared without being listed, but the
+        // transitions' other parameters and target states must exist
+        let mut asset_manager = AssetManager::<QVTransform>::new();
+        assert_eq!(asset_manager.load_manifest(path.to_str().unwrap()), Err(vec![
+            AssetError::MissingState { name: "locomotion".to_string(), state: "fall".to_string() },
+            AssetError::UnknownParam { name: "locomotion".to_string(), param: "grunded".to_string() },
+        ]));
+        assert!(asset_manager.controller_defs.is_empty());
+
+        assert_eq!(asset_manager.load_assets(path.to_str().unwrap()), Err(
+            AssetError::MissingState { name: "locomotion".to_string(), state: "fall".to_string() },
+        ));
+        assert!(asset_manager.controller_defs.is_empty());
+    }
+
+    #[test]
+    fn test_load_clip_json() {
+
+        let path = env::temp_dir().join("skeletal_animation_test_clip.json");
+        File::create(&path).unwrap().write_all(br#"{
+            "samples_per_second": 10.0,
+            "samples": [
+                { "local_poses": [{ "translation": [0.0, 0.0, 0.0], "scale": 1.0, "rotation": [1.0, [0.0, 0.0, 0.0]] }] },
+                { "local_poses": [{ "translation": [1.0, 0.0, 0.0], "scale": 1.0, "rotation": [1.0, [0.0, 0.0, 0.0]] }] }
+            ],
+            "events": [[0.1, "step"]]
+        }"#).unwrap();
+
+        let mut asset_manager = AssetManager::<QVTransform>::new();
+        asset_manager.load_clip_json("walk", path.to_str().unwrap()).unwrap();
+
+        let clip = &asset_manager.animation_clips["walk"];
+        assert_eq!(clip.samples.len(), 2);
+        assert_eq!(clip.samples[1].local_poses[0].translation, [1.0, 0.0, 0.0]);
+        assert_eq!(clip.events, vec![(0.1, "step".to_string())]);
+        assert!(clip.sample_times.is_none());
+
+        // Round trips through serialization
+        File::create(&path).unwrap().write_all(serde_json::to_string(&**clip).unwrap().as_bytes()).unwrap();
+        asset_manager.load_clip_json("walk-copy", path.to_str().unwrap()).unwrap();
+        assert_eq!(asset_manager.animation_clips["walk-copy"].samples.len(), 2);
+
+        File::create(&path).unwrap().write_all(br#"{ "samples_per_second": 10.0, "samples": [] }"#).unwrap();
+        match asset_manager.load_clip_json("empty", path.to_str().unwrap()) {
+            Err(AssetError::Parse { ref message, .. }) => assert_eq!(message, "Clip has no samples."),
+            ref result => panic!("Unexpected result {:?}", result),
+        }
+
+        File::create(&path).unwrap().write_all(b"{ not json").unwrap();
+        assert!(matches!(asset_manager.load_clip_json("bad", path.to_str().unwrap()), Err(AssetError::Parse { .. })));
     }
 
+    #[test]
+    fn test_load_from_bytes() {
+
+        let mut asset_manager = AssetManager::<QVTransform>::new();
+
+        asset_manager.load_clip_from_bytes("walk", br#"{
+            "samples_per_second": 10.0,
+            "samples": [
+                { "local_poses": [{ "translation": [0.0, 0.0, 0.0], "scale": 1.0, "rotation": [1.0, [0.0, 0.0, 0.0]] }] },
+                { "local_poses": [{ "translation": [1.0, 0.0, 0.0], "scale": 1.0, "rotation": [1.0, [0.0, 0.0, 0.0]] }] }
+            ]
+        }"#).unwrap();
+        assert_eq!(asset_manager.animation_clips["walk"].samples.len(), 2);
+
+        match asset_manager.load_clip_from_bytes("empty", br#"{ "samples_per_second": 10.0, "samples": [] }"#) {
+            Err(AssetError::Parse { ref path, ref message }) => {
+                assert_eq!(path, "empty");
+                assert_eq!(message, "Clip has no samples.");
+            }
+            ref result => panic!("Unexpected result {:?}", result),
+        }
+
+        let def: BlendTreeNodeDef = AssetManager::<QVTransform>::load_def_from_bytes("tree", br#"{ "type": "ClipNode", "clip_source": "walk" }"#).unwrap();
+        assert_eq!(def, BlendTreeNodeDef::from_json_str(r#"{ "type": "ClipNode", "clip_source": "walk" }"#).unwrap());
+
+        // COLLADA clips are parsed from the bytes, with the definition's source named in errors
+        let clip_def = AnimationClipDef {
+            name: "run".to_string(),
+            source: "remote/run.dae".to_string(),
+            duration: None,
+            rotate_z: None,
+            handedness: None,
+            events: None,
+        };
+        let collada_error = |bytes: &[u8], asset_manager: &mut AssetManager<QVTransform>| match asset_manager.load_collada_clip_from_bytes(&clip_def, bytes) {
+            Err(AssetError::Collada { ref source, reason, .. }) if source == "remote/run.dae" => reason,
+            ref result => panic!("Unexpected result {:?}", result),
+        };
+        assert_eq!(collada_error(b"<COLLADA></COLLADA>", &mut asset_manager), "No animations found in COLLADA document.");
+        assert_eq!(collada_error(b"<COLLADA", &mut asset_manager), "Error while parsing COLLADA document.");
+        assert_eq!(collada_error(b"\xff", &mut asset_manager), "COLLADA document is not valid UTF-8.");
+    }
 }
@@ -1,5 +1,3 @@
-use std::mem;
-
 pub use vecmath::*;
 
 pub use quaternion::id as quaternion_id;
@@ -46,8 +44,41 @@ pub fn mat4_rotate_z(a: f32) -> Matrix4<f32> {
     ]
 }
 
+/// Convert a transform between right- and left-handed coordinates by mirroring the z-axis,
+/// i.e. `S * m * S` where `S` negates z. Translations have their z negated, and rotations
+/// turn the opposite way about the mirrored axes, as for a quaternion (w, x, y, z) becoming
+/// (w, -x, -y, z). The conversion is its own inverse.
+pub fn mat4_flip_handedness(m: Matrix4<f32>) -> Matrix4<f32> {
+    let mut m = m;
+    for i in [0, 1, 3].iter().cloned() {
+        m[2][i] = -m[2][i];
+        m[i][2] = -m[i][2];
+    }
+    m
+}
+
+/// Write `matrices` into `out` as a tightly-packed sequence of 16 floats per matrix, e.g. to
+/// copy a skinning palette into a mapped GPU buffer or pass it over FFI. Matrices here are
+/// stored row by row, with the translation in the last column, so `row_major` copies them as
+/// they are, while otherwise each matrix is transposed to column-major order. `out` is cleared
+/// first, so it can be reused between frames.
+pub fn flatten_matrices(matrices: &[Matrix4<f32>], row_major: bool, out: &mut Vec<f32>) {
+    out.clear();
+    out.reserve(matrices.len() * 16);
+    for matrix in matrices.iter() {
+        let matrix = if row_major { *matrix } else { mat4_transposed(*matrix) };
+        for row in matrix.iter() {
+            out.extend_from_slice(row);
+        }
+    }
+}
+
+/// Extract the rotation of `m` as a unit quaternion. The basis vectors are orthonormalized
+/// (Gram-Schmidt) first, so any scale or slight skew in the matrix is discarded.
 pub fn matrix_to_quaternion(m: &Matrix4<f32>) -> Quaternion<f32> {
 
+    let m = orthonormalize_basis(m);
+
     let mut q = [0.0, 0.0, 0.0, 0.0];
 
     let next = [1, 2, 0];
@@ -89,7 +120,34 @@ pub fn matrix_to_quaternion(m: &Matrix4<f32>) -> Quaternion<f32> {
 
     }
 
-    (q[3], [q[0], q[1], q[2]])
+    let len_squared = q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3];
+
+    if !len_squared.is_normal() {
+        // Degenerate basis, no meaningful rotation to extract
+        return quaternion_id();
+    }
+
+    let inv_len = 1.0 / len_squared.sqrt();
+    (q[3] * inv_len, [q[0] * inv_len, q[1] * inv_len, q[2] * inv_len])
+}
+
+/// Gram-Schmidt orthonormalization of the upper 3x3 basis vectors of `m`
+fn orthonormalize_basis(m: &Matrix4<f32>) -> Matrix3<f32> {
+
+    let u0 = [m[0][0], m[0][1], m[0][2]];
+    let u1 = [m[1][0], m[1][1], m[1][2]];
+    let u2 = [m[2][0], m[2][1], m[2][2]];
+
+    let e0 = normalize_or_zero(u0);
+    let e1 = normalize_or_zero(vec3_sub(u1, vec3_scale(e0, vec3_dot(u1, e0))));
+    let e2 = normalize_or_zero(vec3_sub(vec3_sub(u2, vec3_scale(e0, vec3_dot(u2, e0))), vec3_scale(e1, vec3_dot(u2, e1))));
+
+    [e0, e1, e2]
+}
+
+fn normalize_or_zero(v: Vector3<f32>) -> Vector3<f32> {
+    let len = vec3_len(v);
+    if len > 0.0 { vec3_scale(v, 1.0 / len) } else { [0.0, 0.0, 0.0] }
 }
 
 ///
@@ -127,20 +185,146 @@ pub fn quaternion_to_matrix(q: Quaternion<f32>) -> Matrix4<f32> {
 
 }
 
+/// The order in which the rotations about each axis of a set of Euler angles are applied,
+/// e.g. `XYZ` rotates about x first, then y, then z (all about the fixed, extrinsic axes,
+/// or equivalently z, then y, then x about the rotating, intrinsic axes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EulerOrder {
+    XYZ,
+    XZY,
+    YXZ,
+    YZX,
+    ZXY,
+    ZYX,
+}
+
+impl EulerOrder {
+
+    /// The indices of the axes in the order their rotations are applied
+    fn axes(self) -> [usize; 3] {
+        match self {
+            EulerOrder::XYZ => [0, 1, 2],
+            EulerOrder::XZY => [0, 2, 1],
+            EulerOrder::YXZ => [1, 0, 2],
+            EulerOrder::YZX => [1, 2, 0],
+            EulerOrder::ZXY => [2, 0, 1],
+            EulerOrder::ZYX => [2, 1, 0],
+        }
+    }
+}
+
+/// The rotation of `angle` radians about `axis`, which needn't be normalized
+pub fn quaternion_from_axis_angle(axis: Vector3<f32>, angle: f32) -> Quaternion<f32> {
+    quaternion::axis_angle(vec3_normalized(axis), angle)
+}
+
+/// The unit axis and angle in radians, in [0, pi], of the rotation `q`, which should be
+/// normalized. The axis is arbitrarily +x for the identity rotation.
+pub fn quaternion_to_axis_angle(q: Quaternion<f32>) -> (Vector3<f32>, f32) {
+
+    // q and -q are the same rotation; take the one with the smaller angle
+    let q = if q.0 < 0.0 { quaternion::scale(q, -1.0) } else { q };
+
+    let sin_half_angle = vec3_len(q.1);
+    if sin_half_angle < 0.000001 {
+        return ([1.0, 0.0, 0.0], 0.0);
+    }
+
+    (vec3_scale(q.1, 1.0 / sin_half_angle), 2.0 * sin_half_angle.atan2(q.0))
+}
+
+/// The rotation applying rotations of `x`, `y` and `z` radians about the x, y and z axes
+/// in the given order
+pub fn quaternion_from_euler(order: EulerOrder, x: f32, y: f32, z: f32) -> Quaternion<f32> {
+
+    let angles = [x, y, z];
+    let mut unit_axes = [[0.0; 3]; 3];
+    for (axis, unit_axis) in unit_axes.iter_mut().enumerate() {
+        unit_axis[axis] = 1.0;
+    }
+
+    order.axes().iter().fold(quaternion_id(), |rotation, &axis| {
+        quaternion_mul(quaternion::axis_angle(unit_axes[axis], angles[axis]), rotation)
+    })
+}
+
+/// The Euler angles `[x, y, z]` in radians of the rotation `q`, for the given order, such
+/// that `quaternion_from_euler` gives back the same rotation. The middle rotation of the
+/// order is in [-pi/2, pi/2], and the first and last in [-pi, pi].
+///
+/// When the middle rotation is (within about 0.01 degrees of) a quarter turn, the first and
+/// last axes line up (gimbal lock) and only their combined angle is determined; the last
+/// rotation is then taken to be zero, and the first rotation holds the combined angle.
+pub fn quaternion_to_euler(order: EulerOrder, q: Quaternion<f32>) -> Vector3<f32> {
+
+    let [i, j, k] = order.axes();
+
+    // Rotation matrix with columns the rotated unit axes, so m[row][column]
+    let mut m = [[0.0; 3]; 3];
+    for column in 0 .. 3 {
+        let mut unit_axis = [0.0; 3];
+        unit_axis[column] = 1.0;
+        let rotated = quaternion::rotate_vector(q, unit_axis);
+        for row in 0 .. 3 {
+            m[row][column] = rotated[row];
+        }
+    }
+
+    // Odd permutations of the axes flip the signs of the off-diagonal terms
+    let sign = if (j + 3 - i) % 3 == 1 { 1.0 } else { -1.0 };
+
+    let cos_middle = (m[i][i] * m[i][i] + m[j][i] * m[j][i]).sqrt();
+    let middle = (-sign * m[k][i]).atan2(cos_middle);
+
+    let (first, last) = if cos_middle > 0.0001 {
+        ((sign * m[k][j]).atan2(m[k][k]), (sign * m[j][i]).atan2(m[i][i]))
+    } else {
+        ((-sign * m[j][k]).atan2(m[j][j]), 0.0)
+    };
+
+    let mut angles = [0.0; 3];
+    angles[i] = first;
+    angles[j] = middle;
+    angles[k] = last;
+    angles
+}
+
 pub fn inv_sqrt(x: f32) -> f32 {
 
     let x2: f32 = x * 0.5;
     let mut y: f32 = x;
 
-    let mut i: i32 = unsafe { mem::transmute(y) };
+    let mut i: i32 = y.to_bits() as i32;
     i = 0x5f3759df - (i >> 1);
-    y = unsafe { mem::transmute(i) };
+    y = f32::from_bits(i as u32);
 
     y = y * (1.5 - (x2 * y * y));
     y
 
 }
 
+/// Smooth 1D value noise in the range [-1, 1], varying over roughly one cycle per unit of `x`.
+/// The same seed and `x` always give the same value, on every platform.
+pub fn value_noise(seed: u32, x: f32) -> f32 {
+
+    let lattice_value = |i: i32| -> f32 {
+        // Integer hash of the seed and lattice point, mapped to [-1, 1]
+        let mut h = seed.wrapping_mul(0x9e3779b9) ^ (i as u32).wrapping_mul(0x85ebca6b);
+        h ^= h >> 16;
+        h = h.wrapping_mul(0x7feb352d);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x846ca68b);
+        h ^= h >> 16;
+        (h as f32 / u32::MAX as f32) * 2.0 - 1.0
+    };
+
+    let i = x.floor();
+    let t = x - i;
+    let t = t * t * (3.0 - 2.0 * t);
+    let i = i as i32;
+    lattice_value(i) * (1.0 - t) + lattice_value(i.wrapping_add(1)) * t
+}
+
 pub fn solve_ik_2d(length_1: f32, length_2: f32, target: [f32; 2]) -> Option<[f32; 2]>
 {
     let x = target[0];
@@ -178,8 +362,130 @@ pub fn solve_ik_2d(length_1: f32, length_2: f32, target: [f32; 2]) -> Option<[f3
 #[cfg(test)]
 mod test {
 
+    use super::*;
+
     static EPSILON: f32 = 0.000001;
 
+    #[test]
+    fn test_matrix_to_quaternion_with_scale() {
+
+        let angle = 0.7f32;
+        let axis = vec3_normalized([1.0, 2.0, 3.0]);
+        let q: Quaternion<f32> = ((angle / 2.0).cos(), vec3_scale(axis, (angle / 2.0).sin()));
+
+        let mut m = quaternion_to_matrix(q);
+        for (basis, &scale) in m.iter_mut().zip([2.5, 0.5, 3.0].iter()) {
+            for x in basis.iter_mut().take(3) {
+                *x *= scale;
+            }
+        }
+
+        let extracted = matrix_to_quaternion(&m);
+        let len = (extracted.0 * extracted.0 + vec3_square_len(extracted.1)).sqrt();
+        assert!((len - 1.0).abs() < 0.0001);
+
+        let dot = extracted.0 * q.0 + vec3_dot(extracted.1, q.1);
+        assert!((dot.abs() - 1.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_flatten_matrices() {
+
+        let mut m = mat4_id();
+        m[0][3] = 5.0;
+        let mut out = vec![1.0; 3];
+
+        flatten_matrices(&[m, mat4_id()], true, &mut out);
+        assert_eq!(out.len(), 32);
+        assert_eq!(out[3], 5.0);
+        assert_eq!(out[16 .. 32], [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+
+        // Column-major puts the translation in the last four floats of each matrix
+        flatten_matrices(&[m], false, &mut out);
+        assert_eq!(out.len(), 16);
+        assert_eq!(out[3], 0.0);
+        assert_eq!(out[12], 5.0);
+    }
+
+    #[test]
+    fn test_flip_handedness() {
+
+        let mut m = quaternion_to_matrix(quaternion::axis_angle(vec3_normalized([1.0, 2.0, 3.0]), 0.8));
+        m[0][3] = 1.0;
+        m[1][3] = 2.0;
+        m[2][3] = 3.0;
+
+        // Mirroring a point then transforming it is the same as transforming then mirroring
+        let flipped = mat4_flip_handedness(m);
+        let mirror = |v: Vector3<f32>| [v[0], v[1], -v[2]];
+        let transform = |m: Matrix4<f32>, v: Vector3<f32>| {
+            let v = row_mat4_transform(m, [v[0], v[1], v[2], 1.0]);
+            [v[0], v[1], v[2]]
+        };
+        let v = [0.3, -0.5, 0.8];
+        assert!(vec3_len(vec3_sub(transform(flipped, mirror(v)), mirror(transform(m, v)))) < EPSILON);
+
+        // The rotation's quaternion has its x and y negated
+        let q = matrix_to_quaternion(&m);
+        let flipped_q = matrix_to_quaternion(&flipped);
+        assert!(vec4_len(vec4_sub([flipped_q.0, flipped_q.1[0], flipped_q.1[1], flipped_q.1[2]], [q.0, -q.1[0], -q.1[1], q.1[2]])) < 0.00001);
+
+        assert_eq!(mat4_flip_handedness(flipped), m);
+    }
+
+    #[test]
+    fn test_axis_angle() {
+
+        let q = quaternion_from_axis_angle([0.0, 0.0, 2.0], 1.2);
+        let (axis, angle) = quaternion_to_axis_angle(q);
+        assert!(vec3_len(vec3_sub(axis, [0.0, 0.0, 1.0])) < EPSILON);
+        assert!((angle - 1.2).abs() < 0.00001);
+
+        // The negated quaternion is the same rotation
+        let (axis, angle) = quaternion_to_axis_angle(quaternion::scale(q, -1.0));
+        assert!(vec3_len(vec3_sub(axis, [0.0, 0.0, 1.0])) < EPSILON);
+        assert!((angle - 1.2).abs() < 0.00001);
+
+        assert_eq!(quaternion_to_axis_angle(quaternion_id()), ([1.0, 0.0, 0.0], 0.0));
+    }
+
+    #[test]
+    fn test_euler() {
+
+        let orders = [EulerOrder::XYZ, EulerOrder::XZY, EulerOrder::YXZ, EulerOrder::YZX, EulerOrder::ZXY, EulerOrder::ZYX];
+        let v = [0.3, -0.5, 0.8];
+
+        // XYZ is x first, then y, then z
+        let q = quaternion_from_euler(EulerOrder::XYZ, 0.4, -0.7, 1.1);
+        let expected = quaternion::rotate_vector(quaternion::axis_angle([0.0, 0.0, 1.0], 1.1),
+            quaternion::rotate_vector(quaternion::axis_angle([0.0, 1.0, 0.0], -0.7),
+            quaternion::rotate_vector(quaternion::axis_angle([1.0, 0.0, 0.0], 0.4), v)));
+        assert!(vec3_len(vec3_sub(quaternion::rotate_vector(q, v), expected)) < 0.00001);
+
+        for &order in orders.iter() {
+
+            // Round trip, with the middle angle in range
+            let q = quaternion_from_euler(order, 0.4, -0.7, 1.1);
+            let angles = quaternion_to_euler(order, q);
+            assert!(vec3_len(vec3_sub(angles, [0.4, -0.7, 1.1])) < 0.0001, "{:?}: {:?}", order, angles);
+
+            // At and near gimbal lock, the angles may differ, but give the same rotation
+            for &offset in [0.0, 0.00005, 0.001].iter() {
+                let middle = std::f32::consts::FRAC_PI_2 - offset;
+                let mut angles = [0.4, 0.4, 0.4];
+                angles[order.axes()[1]] = middle;
+
+                let q = quaternion_from_euler(order, angles[0], angles[1], angles[2]);
+                let round_trip = quaternion_to_euler(order, q);
+                let q_round_trip = quaternion_from_euler(order, round_trip[0], round_trip[1], round_trip[2]);
+
+                let error = vec3_len(vec3_sub(quaternion::rotate_vector(q, v), quaternion::rotate_vector(q_round_trip, v)));
+                assert!(error < 0.001, "{:?} at {}: {:?} gives error {}", order, middle, round_trip, error);
+                assert!(round_trip.iter().all(|angle| angle.is_finite()));
+            }
+        }
+    }
+
     #[test]
     fn test_ik() {
 
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::ops::Index;
+
+use serde::{Deserialize, Serialize};
+
+use blend_tree::ParamId;
+
+/// Index of a parameter within a ParameterSet
+pub type ParamIndex = usize;
+
+/// The inclusive range of values allowed for a parameter
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParameterRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl ParameterRange {
+    /// Clamp the value to the range
+    pub fn clamp(&self, value: f32) -> f32 {
+        value.max(self.min).min(self.max)
+    }
+}
+
+/// A set of named parameter values, where each parameter is assigned a stable index when
+/// it's declared. Blend trees resolve the parameters they reference to indices when they're
+/// built, so evaluating them reads values by index rather than looking up names.
+/// Parameters may be declared with a range, which values are clamped to when set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParameterSet {
+    indices: HashMap<ParamId, ParamIndex>,
+    names: Vec<ParamId>,
+    values: Vec<f32>,
+    ranges: Vec<Option<ParameterRange>>,
+}
+
+impl ParameterSet {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a parameter with an initial value of 0.0 if it isn't already declared,
+    /// returning its index
+    pub fn declare(&mut self, name: &str) -> ParamIndex {
+        if let Some(&index) = self.indices.get(name) {
+            return index;
+        }
+        let index = self.values.len();
+        self.indices.insert(name.to_string(), index);
+        self.names.push(name.to_string());
+        self.values.push(0.0);
+        self.ranges.push(None);
+        index
+    }
+
+    /// Declare a parameter as for `declare`, with values clamped to the given range from
+    /// now on, including its current value. Replaces any range it was declared with before.
+    pub fn declare_with_range(&mut self, name: &str, range: ParameterRange) -> ParamIndex {
+        let index = self.declare(name);
+        self.ranges[index] = Some(range);
+        self.values[index] = range.clamp(self.values[index]);
+        index
+    }
+
+    /// Return the range the given parameter was declared with, or None if it doesn't
+    /// have one or isn't declared
+    pub fn get_range(&self, name: &str) -> Option<ParameterRange> {
+        self.index_of(name).and_then(|index| self.ranges[index])
+    }
+
+    /// Clamp the value to the given parameter's range, or return it as it is if the
+    /// parameter doesn't have one
+    pub fn clamp(&self, name: &str, value: f32) -> f32 {
+        self.get_range(name).map_or(value, |range| range.clamp(value))
+    }
+
+    /// Return the index of the given parameter, or None if it isn't declared
+    pub fn index_of(&self, name: &str) -> Option<ParamIndex> {
+        self.indices.get(name).cloned()
+    }
+
+    /// Set the value of the given parameter, clamped to its range, declaring it if necessary
+    pub fn set(&mut self, name: &str, value: f32) {
+        let index = self.declare(name);
+        self.set_by_index(index, value);
+    }
+
+    /// Return the value of the given parameter, or None if it isn't declared
+    pub fn get(&self, name: &str) -> Option<f32> {
+        self.index_of(name).map(|index| self.values[index])
+    }
+
+    /// Set the value of the parameter at the given index, clamped to its range
+    pub fn set_by_index(&mut self, index: ParamIndex, value: f32) {
+        self.values[index] = self.ranges[index].map_or(value, |range| range.clamp(value));
+    }
+
+    /// Return the value of the parameter at the given index, or None if it's out of range
+    pub fn get_by_index(&self, index: ParamIndex) -> Option<f32> {
+        self.values.get(index).cloned()
+    }
+
+    /// Return the values of all declared parameters, by index
+    pub fn values(&self) -> &[f32] {
+        &self.values[..]
+    }
+
+    /// Iterate over the names and values of all declared parameters, in index order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f32)> {
+        self.names.iter().map(|name| &name[..]).zip(self.values.iter().cloned())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl Index<&str> for ParameterSet {
+    type Output = f32;
+
+    /// Return the value of the given parameter, panicking if it isn't declared
+    fn index(&self, name: &str) -> &f32 {
+        match self.indices.get(name) {
+            Some(&index) => &self.values[index],
+            None => panic!("Undeclared parameter: {}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::{ParameterRange, ParameterSet};
+
+    #[test]
+    fn test_parameter_set() {
+
+        let mut params = ParameterSet::new();
+        let speed = params.declare("speed");
+        let blend = params.declare("blend");
+        assert_eq!(params.declare("speed"), speed);
+        assert_eq!(params.len(), 2);
+
+        params.set("blend", 0.5);
+        params.set_by_index(speed, 2.0);
+        assert_eq!(params.get("speed"), Some(2.0));
+        assert_eq!(params.get_by_index(blend), Some(0.5));
+        assert_eq!(params["blend"], 0.5);
+        assert_eq!(params.get("missing"), None);
+        assert_eq!(params.get_by_index(2), None);
+
+        // Setting an undeclared parameter declares it
+        params.set("direction", 1.0);
+        assert_eq!(params.index_of("direction"), Some(2));
+        assert_eq!(params.values(), &[2.0, 0.5, 1.0]);
+
+        let names: Vec<&str> = params.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["speed", "blend", "direction"]);
+    }
+    #[test]
+    fn test_parameter_ranges() {
+
+        let mut params = ParameterSet::new();
+        params.set("blend", 2.0);
+
+        // Declaring a range clamps the current value
+        let blend = params.declare_with_range("blend", ParameterRange { min: 0.0, max: 1.0 });
+        assert_eq!(params.get("blend"), Some(1.0));
+        assert_eq!(params.get_range("blend"), Some(ParameterRange { min: 0.0, max: 1.0 }));
+
+        // Setting clamps by name and by index
+        params.set("blend", -0.5);
+        assert_eq!(params.get("blend"), Some(0.0));
+        params.set_by_index(blend, 1.5);
+        assert_eq!(params.get("blend"), Some(1.0));
+        params.set("blend", 0.25);
+        assert_eq!(params.get("blend"), Some(0.25));
+        assert_eq!(params.clamp("blend", 3.0), 1.0);
+
+        // Parameters without a range are stored as given
+        params.set("speed", 1.5);
+        assert_eq!(params.get("speed"), Some(1.5));
+        assert_eq!(params.get_range("speed"), None);
+        assert_eq!(params.clamp("speed", 3.0), 3.0);
+    }
+}
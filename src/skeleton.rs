@@ -1,23 +1,163 @@
-use gfx;
-use gfx_debug_draw;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::str;
+
+use serde::{Deserialize, Serialize};
 
 use collada;
+use collada::document::ColladaDocument;
+use constraints::JointLimits;
 use math::*;
 use transform::{Transform, FromTransform};
 
 pub type JointIndex = u8;
 pub const ROOT_JOINT_PARENT_INDEX: JointIndex  = 255u8;
 
+/// Definition of a named skeleton loaded from a COLLADA source, which can be
+/// deserialized from JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkeletonDef {
+    pub name: String,
+    pub source: String,
+
+    /// Attachment sockets to add to the skeleton
+    pub sockets: Option<Vec<Socket>>,
+}
+
+/// A named attachment point for weapons, effects, cameras etc, offset from a parent joint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Socket {
+    pub name: String,
+
+    /// Name of the joint the socket is attached to
+    pub parent_joint: String,
+
+    /// Transform of the socket relative to its parent joint, laid out like the
+    /// joint pose matrices (translation in the last column)
+    pub local_offset: Matrix4<f32>,
+}
+
+/// A problem found when validating a Skeleton
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkeletonError {
+    /// The skeleton has no root joint
+    NoRoot,
+
+    /// The joint's parent index is beyond the end of the skeleton's joints
+    ParentOutOfRange(JointIndex),
+
+    /// The joint's parent doesn't come before it in the skeleton's joints
+    ParentNotBeforeJoint(JointIndex),
+}
+
+impl SkeletonError {
+    /// A short description of the problem, for reporting as a load error
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            SkeletonError::NoRoot => "Skeleton has no root joint.",
+            SkeletonError::ParentOutOfRange(_) => "Skeleton joint has a parent index out of range.",
+            SkeletonError::ParentNotBeforeJoint(_) => "Skeleton joint's parent doesn't come before it.",
+        }
+    }
+}
+
+/// A problem loading a skeleton or clip from a COLLADA source
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColladaError {
+    /// The COLLADA file couldn't be opened or read, with the reason why
+    Io(String),
+
+    /// The COLLADA source isn't valid UTF-8
+    NotUtf8,
+
+    /// The COLLADA source couldn't be parsed
+    Parse,
+
+    /// The COLLADA document contains no skeleton
+    MissingSkeleton,
+
+    /// The COLLADA document contains no animations
+    MissingAnimations,
+
+    /// The COLLADA document's skeleton is invalid
+    InvalidSkeleton(SkeletonError),
+
+    /// A socket is attached to the named joint, which is missing from the skeleton
+    MissingSocketJoint(String),
+}
+
+impl ColladaError {
+    /// A short description of the problem, for reporting as a load error
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            ColladaError::Io(_) => "Failed to read COLLADA file.",
+            ColladaError::NotUtf8 => "COLLADA document is not valid UTF-8.",
+            ColladaError::Parse => "Error while parsing COLLADA document.",
+            ColladaError::MissingSkeleton => "No skeleton found in COLLADA document.",
+            ColladaError::MissingAnimations => "No animations found in COLLADA document.",
+            ColladaError::InvalidSkeleton(ref error) => error.reason(),
+            ColladaError::MissingSocketJoint(_) => "Socket is attached to a joint missing from the skeleton.",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Skeleton {
     ///
-    /// All joints in the skeleton
+    /// All joints in the skeleton, in topological order: each joint's parent comes before
+    /// it, so global poses can be calculated in a single pass (see `validate`)
     ///
     pub joints: Vec<Joint>,
+
+    ///
+    /// Attachment sockets, offset from joints in the skeleton
+    ///
+    pub sockets: Vec<Socket>,
+
+    ///
+    /// Limits on the rotations of joints, respected by IK and look-at constraints
+    ///
+    pub joint_limits: JointLimits,
+}
+
+/// Parse a COLLADA document from its source text, as `ColladaDocument::from_path` does
+/// once it has read the file
+pub(crate) fn parse_collada_document(source: &str) -> Result<ColladaDocument, ColladaError> {
+    match source.parse() {
+        Ok(root_element) => Ok(ColladaDocument { root_element }),
+        Err(_) => Err(ColladaError::Parse),
+    }
+}
+
+/// Parse a COLLADA document from its bytes, which must be UTF-8
+pub(crate) fn parse_collada_bytes(bytes: &[u8]) -> Result<ColladaDocument, ColladaError> {
+    parse_collada_document(str::from_utf8(bytes).map_err(|_| ColladaError::NotUtf8)?)
+}
+
+/// Read and parse the COLLADA document at the given path, like `ColladaDocument::from_path`
+/// but telling failures to read the file apart from failures to parse it
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn read_collada_document(path: &Path) -> Result<ColladaDocument, ColladaError> {
+    let bytes = fs::read(path).map_err(|error| ColladaError::Io(error.to_string()))?;
+    parse_collada_bytes(&bytes)
 }
 
 impl Skeleton {
 
+    ///
+    /// Build a skeleton from the given joints, with no sockets or joint limits, e.g. with
+    /// joints created by `Joint::new`
+    ///
+    pub fn new(joints: Vec<Joint>) -> Self {
+        Self {
+            joints,
+            sockets: Vec::new(),
+            joint_limits: Default::default(),
+        }
+    }
+
     ///
     /// Build a skeleton fromm a Collada skeleton
     ///
@@ -29,7 +169,80 @@ impl Skeleton {
                     parent_index: j.parent_index,
                     inverse_bind_pose: j.inverse_bind_pose,
                 }
-            }).collect()
+            }).collect(),
+            sockets: Vec::new(),
+            joint_limits: Default::default(),
+        }
+    }
+
+    ///
+    /// Load and validate the first skeleton in a COLLADA file, without needing the collada
+    /// crate's types
+    ///
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_path(path: &Path) -> Result<Self, ColladaError> {
+        Self::from_collada_document(&read_collada_document(path)?)
+    }
+
+    ///
+    /// Like `from_path`, but parsing the given COLLADA source text rather than reading a
+    /// file, e.g. for assets fetched over the network
+    ///
+    pub fn from_collada_str(source: &str) -> Result<Self, ColladaError> {
+        Self::from_collada_document(&parse_collada_document(source)?)
+    }
+
+    pub(crate) fn from_collada_document(collada_document: &ColladaDocument) -> Result<Self, ColladaError> {
+        match collada_document.get_skeletons() {
+            Some(ref skeleton_set) if !skeleton_set.is_empty() => {
+                let skeleton = Self::from_collada(&skeleton_set[0]);
+                skeleton.validate().map_err(ColladaError::InvalidSkeleton)?;
+                Ok(skeleton)
+            }
+            _ => Err(ColladaError::MissingSkeleton),
+        }
+    }
+
+    ///
+    /// Load the first skeleton in the COLLADA source referenced by a SkeletonDef
+    ///
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_def(skeleton_def: &SkeletonDef) -> Result<Self, ColladaError> {
+        let mut skeleton = Self::from_path(Path::new(&skeleton_def.source[..]))?;
+
+        skeleton.sockets = skeleton_def.sockets.clone().unwrap_or_default();
+        if let Some(socket) = skeleton.sockets.iter().find(|socket| skeleton.get_joint_index(&socket.parent_joint).is_none()) {
+            return Err(ColladaError::MissingSocketJoint(socket.parent_joint.clone()));
+        }
+
+        Ok(skeleton)
+    }
+
+    ///
+    /// Check that the skeleton has at least one root joint, and that every other joint's
+    /// parent is in range and comes before it, as `calculate_global_poses` requires.
+    /// Skeletons may have several roots, e.g. for multi-part rigs or props, each of which
+    /// starts an independent chain of joints.
+    ///
+    pub fn validate(&self) -> Result<(), SkeletonError> {
+
+        let mut root_index = None;
+
+        for (joint_index, joint) in self.joints.iter().enumerate() {
+            let joint_index = joint_index as JointIndex;
+
+            if joint.is_root() {
+                root_index = root_index.or(Some(joint_index));
+            } else if joint.parent_index as usize >= self.joints.len() {
+                return Err(SkeletonError::ParentOutOfRange(joint_index));
+            } else if joint.parent_index >= joint_index {
+                return Err(SkeletonError::ParentNotBeforeJoint(joint_index));
+            }
+        }
+
+        match root_index {
+            Some(_) => Ok(()),
+            None => Err(SkeletonError::NoRoot),
         }
     }
 
@@ -39,6 +252,8 @@ impl Skeleton {
         global_poses: &mut [TOutput],
     ) {
 
+        self.debug_assert_pose_lengths(local_poses.len(), global_poses.len());
+
         for (joint_index, joint) in self.joints.iter().enumerate() {
 
             let parent_pose = if !joint.is_root() {
@@ -52,77 +267,140 @@ impl Skeleton {
         }
     }
 
-    pub fn get_joint_index(&self, joint_name: &str) -> Option<JointIndex> {
-        self.joints.iter().enumerate().find(|(_, joint)| joint.name == joint_name).map(|(index, _)| index as JointIndex)
-    }
-
-    pub fn draw<R: gfx::Resources, F: gfx::Factory<R>, T: Transform> (
+    /// Like `calculate_global_poses`, but only recalculate the global poses of `root_joint`
+    /// and its descendants, e.g. after re-solving IK for a chain, leaving the poses of other
+    /// joints in `global_poses` as they were. The parent of `root_joint` must already have
+    /// its global pose in `global_poses`.
+    pub fn calculate_global_poses_subtree<T: Transform, TOutput: Transform + FromTransform<T>>(
         &self,
-        global_poses: &[T],
-        debug_renderer: &mut gfx_debug_draw::DebugRenderer<R, F>,
-        draw_labels: bool)
-    {
+        local_poses: &[T],
+        root_joint: JointIndex,
+        global_poses: &mut [TOutput],
+    ) {
 
-        for (joint_index, joint) in self.joints.iter().enumerate() {
+        self.debug_assert_pose_lengths(local_poses.len(), global_poses.len());
 
-            let joint_position = global_poses[joint_index].transform_vector([0.0, 0.0, 0.0]);
-            let leaf_end = global_poses[joint_index].transform_vector([0.0, 1.0, 0.0]);
+        let root_joint = root_joint as usize;
 
-            if !joint.is_root() {
+        // Joints come after their parents, so the subtree is found in a single pass
+        let mut in_subtree = vec![false; self.joints.len()];
 
-                let parent_position = global_poses[joint.parent_index as usize].transform_vector([0.0, 0.0, 0.0]);
+        for (joint_index, joint) in self.joints.iter().enumerate().skip(root_joint) {
 
-                // Draw bone (between joint and parent joint)
+            in_subtree[joint_index] = joint_index == root_joint
+                || (!joint.is_root() && in_subtree[joint.parent_index as usize]);
 
-                debug_renderer.draw_line(
-                    [parent_position[0], parent_position[1], parent_position[2]],
-                    [joint_position[0], joint_position[1], joint_position[2]],
-                    [0.2, 0.2, 0.2, 1.0]
-                    );
+            if !in_subtree[joint_index] {
+                continue;
+            }
 
-                if !self.joints.iter().any(|j| j.parent_index as usize == joint_index) {
+            let parent_pose = if !joint.is_root() {
+                global_poses[joint.parent_index as usize]
+            } else {
+                TOutput::identity()
+            };
 
-                    // Draw extension along joint's y-axis...
-                    debug_renderer.draw_line(
-                        [joint_position[0], joint_position[1], joint_position[2]],
-                        [leaf_end[0], leaf_end[1], leaf_end[2]],
-                        [0.2, 0.2, 0.2, 1.0]
-                        );
-                }
+            let local_pose = local_poses[joint_index];
+            global_poses[joint_index] = parent_pose.concat(TOutput::from_transform(local_pose));
+        }
+    }
+
+    /// Check that local and global pose slices have a pose for every joint, rather than
+    /// failing with an out of bounds index partway through the hierarchy
+    fn debug_assert_pose_lengths(&self, local_pose_count: usize, global_pose_count: usize) {
+        debug_assert!(
+            local_pose_count >= self.joints.len(),
+            "Local poses ({}) are fewer than the skeleton's joints ({})",
+            local_pose_count, self.joints.len()
+        );
+        debug_assert!(
+            global_pose_count >= self.joints.len(),
+            "Global poses ({}) are fewer than the skeleton's joints ({})",
+            global_pose_count, self.joints.len()
+        );
+    }
+
+    /// Convert the skeleton's bind poses and socket offsets between right- and left-handed
+    /// coordinates, as with `mat4_flip_handedness`, to match clips loaded with
+    /// `AnimationClip::from_collada_with_handedness`
+    pub fn flip_handedness(&mut self) {
+        for joint in self.joints.iter_mut() {
+            joint.inverse_bind_pose = mat4_flip_handedness(joint.inverse_bind_pose);
+        }
+        for socket in self.sockets.iter_mut() {
+            socket.local_offset = mat4_flip_handedness(socket.local_offset);
+        }
+    }
+
+    /// Return the axis-aligned bounding box (min, max) of the joint origins in the given
+    /// global poses, with each joint inflated by `joint_radius`
+    pub fn get_bounds<T: Transform>(&self, global_poses: &[T], joint_radius: f32) -> (Vector3<f32>, Vector3<f32>) {
+
+        let mut bounds: Option<(Vector3<f32>, Vector3<f32>)> = None;
+
+        for pose in global_poses.iter().take(self.joints.len()) {
+            let position = pose.get_translation();
+            let joint_min = vec3_sub(position, [joint_radius; 3]);
+            let joint_max = vec3_add(position, [joint_radius; 3]);
+
+            bounds = Some(match bounds {
+                Some((min, max)) => (
+                    [min[0].min(joint_min[0]), min[1].min(joint_min[1]), min[2].min(joint_min[2])],
+                    [max[0].max(joint_max[0]), max[1].max(joint_max[1]), max[2].max(joint_max[2])],
+                ),
+                None => (joint_min, joint_max),
+            });
+        }
+
+        bounds.unwrap_or(([0.0; 3], [0.0; 3]))
+    }
+
+    /// Return the local (relative to parent) pose of each joint in the bind pose,
+    /// recovered from the joints' inverse bind matrices, e.g. to reset a character to its
+    /// rest pose or as the reference pose for additive blending
+    pub fn get_local_bind_poses<T: Transform>(&self) -> Vec<T> {
+        self.joints.iter().map(|joint| {
+            let global_pose = mat4_inv(joint.inverse_bind_pose);
+            if joint.is_root() {
+                T::from_matrix(global_pose)
+            } else {
+                let parent = &self.joints[joint.parent_index as usize];
+                T::from_matrix(row_mat4_mul(parent.inverse_bind_pose, global_pose))
             }
+        }).collect()
+    }
 
-            if draw_labels {
-                // Label joint
-                debug_renderer.draw_text_at_position(
-                    &joint.name[..],
-                    [leaf_end[0], leaf_end[1], leaf_end[2]],
-                    [1.0, 1.0, 1.0, 1.0]
-                    );
+    /// Return the skeleton's bind (rest) pose, as the local pose of each joint. The same
+    /// as `get_local_bind_poses`.
+    pub fn bind_pose<T: Transform>(&self) -> Vec<T> {
+        self.get_local_bind_poses()
+    }
+
+    /// Return the rest length of each joint's bone, the distance from the joint to its
+    /// parent in the bind pose, e.g. for setting up IK chains or retargeting. Root joints
+    /// have a length of zero.
+    pub fn bone_lengths(&self) -> Vec<f32> {
+        self.joints.iter().map(|joint| {
+            if joint.is_root() {
+                0.0
+            } else {
+                let parent = &self.joints[joint.parent_index as usize];
+                vec3_len(vec3_sub(joint.bind_pose().get_translation(), parent.bind_pose().get_translation()))
             }
+        }).collect()
+    }
 
-            // Draw joint-relative axes
-            let p_x_axis = global_poses[joint_index].transform_vector([1.0, 0.0, 0.0]);
-            let p_y_axis = global_poses[joint_index].transform_vector([0.0, 1.0, 0.0]);
-            let p_z_axis = global_poses[joint_index].transform_vector([0.0, 0.0, 1.0]);
-
-            debug_renderer.draw_line(
-                [joint_position[0], joint_position[1], joint_position[2]],
-                [p_x_axis[0], p_x_axis[1], p_x_axis[2]],
-                [1.0, 0.2, 0.2, 1.0]
-                );
-
-            debug_renderer.draw_line(
-                [joint_position[0], joint_position[1], joint_position[2]],
-                [p_y_axis[0], p_y_axis[1], p_y_axis[2]],
-                [0.2, 1.0, 0.2, 1.0]
-                );
-
-            debug_renderer.draw_line(
-                [joint_position[0], joint_position[1], joint_position[2]],
-                [p_z_axis[0], p_z_axis[1], p_z_axis[2]],
-                [0.2, 0.2, 1.0, 1.0]
-                );
-        }
+    /// Return the global (model-space) transform of the named socket, given the global
+    /// poses of the skeleton's joints, or None if there's no socket with that name
+    pub fn socket_world_transform<T: Transform>(&self, global_poses: &[T], socket_name: &str) -> Option<Matrix4<f32>> {
+        let socket = self.sockets.iter().find(|socket| socket.name == socket_name)?;
+        let joint_index = self.get_joint_index(&socket.parent_joint)? as usize;
+        let joint_pose = global_poses.get(joint_index)?;
+        Some(row_mat4_mul(joint_pose.to_matrix(), socket.local_offset))
+    }
+
+    pub fn get_joint_index(&self, joint_name: &str) -> Option<JointIndex> {
+        self.joints.iter().enumerate().find(|(_, joint)| joint.name == joint_name).map(|(index, _)| index as JointIndex)
     }
 }
 
@@ -134,7 +412,8 @@ pub struct Joint {
     pub name: String,
 
     ///
-    /// Index of parent joint in Skeleton's 'joints' vector
+    /// Index of parent joint in Skeleton's 'joints' vector, which must come before this
+    /// joint, or ROOT_JOINT_PARENT_INDEX for a root joint
     ///
     pub parent_index: JointIndex,
 
@@ -146,7 +425,211 @@ pub struct Joint {
 }
 
 impl Joint {
+
+    ///
+    /// Create a joint with the given parent, or a root joint if None, and its global
+    /// (model-space) bind pose, e.g. to build a Skeleton by hand
+    ///
+    pub fn new(name: &str, parent_index: Option<JointIndex>, bind_pose: Matrix4<f32>) -> Self {
+        Self {
+            name: name.to_string(),
+            parent_index: parent_index.unwrap_or(ROOT_JOINT_PARENT_INDEX),
+            inverse_bind_pose: mat4_inv(bind_pose),
+        }
+    }
+
     pub fn is_root(&self) -> bool {
         self.parent_index == ROOT_JOINT_PARENT_INDEX
     }
+
+    ///
+    /// Index of the parent joint, or None for a root joint
+    ///
+    pub fn parent(&self) -> Option<JointIndex> {
+        if self.is_root() { None } else { Some(self.parent_index) }
+    }
+
+    ///
+    /// Global (model-space) pose of the joint in the bind pose, the inverse of its
+    /// inverse bind pose
+    ///
+    pub fn bind_pose(&self) -> Matrix4<f32> {
+        mat4_inv(self.inverse_bind_pose)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::path::Path;
+
+    use math::*;
+
+    use transform::{Transform, QVTransform};
+
+    use super::{ColladaError, Joint, JointIndex, Skeleton, SkeletonError, Socket, ROOT_JOINT_PARENT_INDEX};
+
+    fn make_skeleton(parent_indices: &[JointIndex]) -> Skeleton {
+        Skeleton::new(parent_indices.iter().enumerate().map(|(i, &parent_index)| Joint {
+            name: format!("joint_{}", i),
+            parent_index,
+            inverse_bind_pose: mat4_id(),
+        }).collect())
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 0, 1, 0]).validate(), Ok(()));
+        assert_eq!(make_skeleton(&[]).validate(), Err(SkeletonError::NoRoot));
+        assert_eq!(make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 0, ROOT_JOINT_PARENT_INDEX, 2]).validate(), Ok(()));
+        assert_eq!(make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 5]).validate(), Err(SkeletonError::ParentOutOfRange(1)));
+        assert_eq!(make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 2, 0]).validate(), Err(SkeletonError::ParentNotBeforeJoint(1)));
+        assert_eq!(make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 1]).validate(), Err(SkeletonError::ParentNotBeforeJoint(1)));
+    }
+
+    #[test]
+    fn test_joint_new() {
+
+        let mut bind_pose = mat4_id();
+        bind_pose.set_translation([0.0, 2.0, 0.0]);
+
+        let skeleton = Skeleton::new(vec![
+            Joint::new("root", None, mat4_id()),
+            Joint::new("child", Some(0), bind_pose),
+        ]);
+
+        assert_eq!(skeleton.validate(), Ok(()));
+        assert!(skeleton.joints[0].is_root());
+        assert_eq!(skeleton.joints[0].parent(), None);
+        assert_eq!(skeleton.joints[1].parent(), Some(0));
+        assert_eq!(skeleton.joints[1].inverse_bind_pose.get_translation(), [0.0, -2.0, 0.0]);
+        assert_eq!(skeleton.joints[1].bind_pose().get_translation(), [0.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn test_calculate_global_poses_multiple_roots() {
+
+        // Two independent chains, the second root coming after the first chain
+        let skeleton = make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 0, ROOT_JOINT_PARENT_INDEX, 2]);
+
+        let translation = |x: f32, y: f32| {
+            let mut pose = QVTransform::identity();
+            pose.set_translation([x, y, 0.0]);
+            pose
+        };
+
+        let local_poses = [translation(1.0, 0.0), translation(0.0, 1.0), translation(5.0, 0.0), translation(0.0, 2.0)];
+        let mut global_poses = [mat4_id(); 4];
+        skeleton.calculate_global_poses(&local_poses[..], &mut global_poses[..]);
+
+        assert_eq!(global_poses[0].get_translation(), [1.0, 0.0, 0.0]);
+        assert_eq!(global_poses[1].get_translation(), [1.0, 1.0, 0.0]);
+        assert_eq!(global_poses[2].get_translation(), [5.0, 0.0, 0.0]);
+        assert_eq!(global_poses[3].get_translation(), [5.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn test_socket_world_transform() {
+
+        let mut skeleton = make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 0]);
+
+        let mut local_offset = mat4_id();
+        local_offset.set_translation([0.0, 0.0, 0.5]);
+        skeleton.sockets.push(Socket { name: "weapon".to_string(), parent_joint: "joint_1".to_string(), local_offset });
+
+        let mut root_pose = mat4_id();
+        root_pose.set_translation([1.0, 0.0, 0.0]);
+        let mut hand_pose = mat4_id();
+        hand_pose.set_translation([1.0, 2.0, 0.0]);
+
+        let socket_transform = skeleton.socket_world_transform(&[root_pose, hand_pose], "weapon").unwrap();
+        assert_eq!(socket_transform.get_translation(), [1.0, 2.0, 0.5]);
+        assert!(skeleton.socket_world_transform(&[root_pose, hand_pose], "camera").is_none());
+    }
+
+    #[test]
+    fn test_get_local_bind_poses() {
+
+        let mut skeleton = make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 0]);
+
+        let mut root_bind_pose = mat4_id();
+        root_bind_pose.set_translation([1.0, 0.0, 0.0]);
+        let mut child_bind_pose = mat4_id();
+        child_bind_pose.set_translation([1.0, 2.0, 0.0]);
+        skeleton.joints[0].inverse_bind_pose = mat4_inv(root_bind_pose);
+        skeleton.joints[1].inverse_bind_pose = mat4_inv(child_bind_pose);
+
+        let bind_poses: Vec<QVTransform> = skeleton.get_local_bind_poses();
+        assert_eq!(bind_poses.len(), 2);
+        assert!(vec3_len(vec3_sub(bind_poses[0].get_translation(), [1.0, 0.0, 0.0])) < 0.00001);
+        assert!(vec3_len(vec3_sub(bind_poses[1].get_translation(), [0.0, 2.0, 0.0])) < 0.00001);
+        assert!((bind_poses[1].rotation.0 - 1.0).abs() < 0.00001);
+
+        let bind_pose: Vec<QVTransform> = skeleton.bind_pose();
+        assert_eq!(bind_pose.len(), 2);
+        assert_eq!(bind_pose[1].get_translation(), bind_poses[1].get_translation());
+    }
+
+    #[test]
+    fn test_bone_lengths() {
+
+        let mut skeleton = make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 0, 1]);
+
+        let mut root_bind_pose = mat4_id();
+        root_bind_pose.set_translation([1.0, 0.0, 0.0]);
+        let mut child_bind_pose = mat4_id();
+        child_bind_pose.set_translation([1.0, 2.0, 0.0]);
+        let mut grandchild_bind_pose = mat4_id();
+        grandchild_bind_pose.set_translation([4.0, 6.0, 0.0]);
+        skeleton.joints[0].inverse_bind_pose = mat4_inv(root_bind_pose);
+        skeleton.joints[1].inverse_bind_pose = mat4_inv(child_bind_pose);
+        skeleton.joints[2].inverse_bind_pose = mat4_inv(grandchild_bind_pose);
+
+        let bone_lengths = skeleton.bone_lengths();
+        assert_eq!(bone_lengths.len(), 3);
+        assert_eq!(bone_lengths[0], 0.0);
+        assert!((bone_lengths[1] - 2.0).abs() < 0.00001);
+        assert!((bone_lengths[2] - 5.0).abs() < 0.00001);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Global poses (1) are fewer than the skeleton's joints (2)")]
+    fn test_calculate_global_poses_short_output() {
+        let skeleton = make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 0]);
+        let mut global_poses = [mat4_id(); 1];
+        skeleton.calculate_global_poses(&[mat4_id(); 2], &mut global_poses);
+    }
+
+    #[test]
+    fn test_from_collada_str() {
+        assert_eq!(Skeleton::from_collada_str("<COLLADA").err(), Some(ColladaError::Parse));
+        assert_eq!(Skeleton::from_collada_str("<COLLADA></COLLADA>").err(), Some(ColladaError::MissingSkeleton));
+        assert!(matches!(Skeleton::from_path(Path::new("missing/guy.dae")), Err(ColladaError::Io(_))));
+    }
+
+    #[test]
+    fn test_calculate_global_poses_subtree() {
+
+        let skeleton = make_skeleton(&[ROOT_JOINT_PARENT_INDEX, 0, 1, 0]);
+
+        let local_poses: Vec<Matrix4<f32>> = (0 .. 4).map(|i| {
+            let mut pose = mat4_id();
+            pose.set_translation([i as f32, 0.0, 0.0]);
+            pose
+        }).collect();
+
+        let mut global_poses = [mat4_id(); 4];
+        skeleton.calculate_global_poses(&local_poses[..], &mut global_poses[..]);
+
+        // Moving joint 1 moves its child, but not its sibling
+        let mut new_local_poses = local_poses.clone();
+        new_local_poses[1].set_translation([0.0, 5.0, 0.0]);
+        new_local_poses[3].set_translation([0.0, 0.0, 9.0]);
+        skeleton.calculate_global_poses_subtree(&new_local_poses[..], 1, &mut global_poses[..]);
+
+        assert_eq!(global_poses[1].get_translation(), [0.0, 5.0, 0.0]);
+        assert_eq!(global_poses[2].get_translation(), [2.0, 5.0, 0.0]);
+        assert_eq!(global_poses[3].get_translation(), [3.0, 0.0, 0.0]);
+    }
 }
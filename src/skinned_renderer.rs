@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::default::Default;
 use std::path::Path;
 
@@ -13,17 +14,81 @@ use transform::Transform;
 
 const MAX_JOINTS: usize = 64;
 
-pub struct SkinnedRenderBatch<R: gfx::Resources, T: Transform> {
-    skinning_transforms_buffer: gfx::handle::Buffer<R, T>,
+/// The maximum number of joints that can influence each vertex
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum JointInfluences {
+    Four,
+    Eight,
+}
+
+impl JointInfluences {
+    fn count(self) -> usize {
+        match self {
+            JointInfluences::Four => 4,
+            JointInfluences::Eight => 8,
+        }
+    }
+}
+
+/// A view of a diffuse texture, as created by the caller or loaded by the renderer
+pub type TextureView<R> = gfx::handle::ShaderResourceView<R, [f32; 4]>;
+
+/// An error creating a SkinnedRenderer from a COLLADA document
+#[derive(Debug)]
+pub enum SkinnedRendererError {
+    /// The skinning shaders failed to compile or link
+    Program(gfx::shade::ProgramError),
+
+    /// The pipeline state couldn't be created from the linked skinning shaders
+    Pipeline(gfx::PipelineStateError<String>),
+
+    /// The document doesn't contain a skeleton to skin its meshes by
+    MissingSkeleton,
+
+    /// A texture couldn't be loaded from the given path
+    Texture(String),
+
+    /// The document's meshes couldn't be added, as for `add_mesh_with_textures`
+    Mesh(&'static str),
+}
+
+impl From<gfx::shade::ProgramError> for SkinnedRendererError {
+    fn from(error: gfx::shade::ProgramError) -> Self {
+        SkinnedRendererError::Program(error)
+    }
+}
+
+impl From<&'static str> for SkinnedRendererError {
+    fn from(reason: &'static str) -> Self {
+        SkinnedRendererError::Mesh(reason)
+    }
+}
+
+pub struct SkinnedRenderBatch<R: gfx::Resources> {
     slice: gfx::Slice<R>,
     vertex_buffer: gfx::handle::Buffer<R, SkinnedVertex>,
-    texture: (gfx::handle::ShaderResourceView<R, [f32; 4]>, gfx::handle::Sampler<R>),
+    texture: (TextureView<R>, gfx::handle::Sampler<R>),
 }
 
+///
+/// Renders skinned meshes, with the skinning method selected by the transform type `T`:
+///
+/// * `Matrix4<f32>` - Linear Blend Skinning (LBS). Fastest, but volume collapses on
+///   twisting joints (the "candy-wrapper" artifact).
+/// * `DualQuaternion<f32>` - Dual-Quaternion Linear Blend Skinning (DLB), where skinning
+///   transforms are blended as dual quaternions in the shader, preserving volume on twisting joints.
+///
+/// The joint poses passed to `render` and `set_joint_matrices` must be of the same type;
+/// an `AnimationController` can output either via `FromTransform`.
+///
 pub struct SkinnedRenderer<R: gfx::Resources, T: Transform> {
     pso: gfx::PipelineState<R, pipe::Meta>,
     skeleton: Skeleton, // TODO Should this be a ref? Should this just be the joints?
-    render_batches: Vec<SkinnedRenderBatch<R, T>>,
+    render_batches: Vec<SkinnedRenderBatch<R>>,
+    skinning_transforms_buffer: gfx::handle::Buffer<R, T>,
+    sampler: gfx::handle::Sampler<R>,
+    joint_influences: JointInfluences,
+    import_scale: f32,
 }
 
 pub trait HasShaderSources<'a> {
@@ -55,16 +120,54 @@ impl<'a, R: gfx::Resources, T: Transform + HasShaderSources<'a>> SkinnedRenderer
         factory: &mut F,
         collada_document: collada::document::ColladaDocument,
         texture_paths: Vec<&str>, // TODO - read from the COLLADA document (if available)
-    ) -> Result<Self, gfx::shade::ProgramError> {
+    ) -> Result<Self, SkinnedRendererError> {
+        Self::from_collada_with_influences(factory, collada_document, texture_paths, JointInfluences::Four)
+    }
+
+    ///
+    /// Like `from_collada`, but with the given limit on joint influences per vertex.
+    /// Vertices with more influences keep only the most heavily weighted joints, with
+    /// their weights renormalized.
+    ///
+    pub fn from_collada_with_influences<F: gfx::Factory<R>>(
+        factory: &mut F,
+        collada_document: collada::document::ColladaDocument,
+        texture_paths: Vec<&str>,
+        joint_influences: JointInfluences,
+    ) -> Result<Self, SkinnedRendererError> {
+        let mut renderer = Self::new_for_document(factory, &collada_document, joint_influences)?;
+        renderer.add_mesh(factory, collada_document, texture_paths)?;
+        Ok(renderer)
+    }
+
+    ///
+    /// Like `from_collada_with_influences`, but with already created textures for each mesh
+    /// rather than paths to load them from, e.g. so that several characters can share one
+    /// texture atlas, or so that the caller controls texture loading and caching.
+    ///
+    pub fn from_collada_with_textures<F: gfx::Factory<R>>(
+        factory: &mut F,
+        collada_document: collada::document::ColladaDocument,
+        textures: Vec<TextureView<R>>,
+        joint_influences: JointInfluences,
+    ) -> Result<Self, SkinnedRendererError> {
+        let mut renderer = Self::new_for_document(factory, &collada_document, joint_influences)?;
+        renderer.add_mesh_with_textures(factory, collada_document, textures)?;
+        Ok(renderer)
+    }
+
+    /// Create a renderer with no meshes, for the skeleton of the given COLLADA document
+    fn new_for_document<F: gfx::Factory<R>>(
+        factory: &mut F,
+        collada_document: &collada::document::ColladaDocument,
+        joint_influences: JointInfluences,
+    ) -> Result<Self, SkinnedRendererError> {
         use gfx::format::Formatted;
 
         let program = {
-            let vs = T::vertex_shader_source();
+            let vs = with_joint_influences_define(T::vertex_shader_source(), joint_influences);
             let fs = T::fragment_shader_source();
-            match factory.link_program(vs, fs) {
-                Ok(program_handle) => program_handle,
-                Err(e) => return Err(e),
-            }
+            factory.link_program(&vs[..], fs)?
         };
 
         // TODO: Pass in format as parameter.
@@ -73,6 +176,8 @@ impl<'a, R: gfx::Resources, T: Transform + HasShaderSources<'a>> SkinnedRenderer
             vertex: (),
             u_model_view_proj: "u_model_view_proj",
             u_model_view: "u_model_view",
+            u_normal_matrix: "u_normal_matrix",
+            u_import_scale: "u_import_scale",
             u_skinning_transforms: "u_skinning_transforms",
             u_texture: "u_texture",
             out_color: ("out_color", format, gfx::state::ColorMask::all(), None),
@@ -83,7 +188,7 @@ impl<'a, R: gfx::Resources, T: Transform + HasShaderSources<'a>> SkinnedRenderer
             gfx::Primitive::TriangleList,
             gfx::state::Rasterizer::new_fill(),
             init
-        ).unwrap();
+        ).map_err(|error| SkinnedRendererError::Pipeline(error.into()))?;
 
         let sampler = factory.create_sampler(
             gfx::texture::SamplerInfo::new(
@@ -92,52 +197,141 @@ impl<'a, R: gfx::Resources, T: Transform + HasShaderSources<'a>> SkinnedRenderer
             )
         );
 
-        let obj_set = collada_document.get_obj_set().unwrap();
+        let skeleton = match collada_document.get_skeletons() {
+            Some(ref skeleton_set) if !skeleton_set.is_empty() => Skeleton::from_collada(&skeleton_set[0]),
+            _ => return Err(SkinnedRendererError::MissingSkeleton),
+        };
+
+        let skinning_transforms_buffer = factory.create_buffer::<T>(
+            MAX_JOINTS,
+            gfx::buffer::Role::Constant,
+            gfx::memory::Usage::Dynamic,
+            gfx::memory::Bind::empty()
+        ).unwrap();
+
+        Ok(Self {
+            pso,
+            render_batches: Vec::new(),
+            skeleton,
+            skinning_transforms_buffer,
+            sampler,
+            joint_influences,
+            import_scale: 1.0,
+        })
+    }
 
-        let skeleton_set = collada_document.get_skeletons().unwrap();
-        let skeleton = Skeleton::from_collada(&skeleton_set[0]);
+    ///
+    /// Add the meshes from another COLLADA document, skinned by the same skeleton as this renderer.
+    /// All meshes share a single set of skinning transforms, calculated once per render.
+    ///
+    /// The mesh's joints are matched to the renderer's skeleton by name, so the document's
+    /// skeleton may list them in a different order, or only include the joints the mesh is
+    /// bound to. Returns an error if the mesh is bound to a joint that is missing from the
+    /// renderer's skeleton, if there isn't a texture path for each mesh, or if a texture
+    /// can't be loaded.
+    ///
+    pub fn add_mesh<F: gfx::Factory<R>>(
+        &mut self,
+        factory: &mut F,
+        collada_document: collada::document::ColladaDocument,
+        texture_paths: Vec<&str>,
+    ) -> Result<(), SkinnedRendererError> {
 
-        let mut render_batches = Vec::new();
+        let textures = texture_paths.iter().map(|texture_path| {
+            gfx_texture::Texture::from_path(
+                factory,
+                Path::new(texture_path),
+                gfx_texture::Flip::None,
+                &gfx_texture::TextureSettings::new()
+            ).map(|texture| texture.view).map_err(SkinnedRendererError::Texture)
+        }).collect::<Result<Vec<_>, _>>()?;
 
-        for (i, object) in obj_set.objects.iter().enumerate().take(6) {
+        self.add_mesh_with_textures(factory, collada_document, textures)?;
+        Ok(())
+    }
 
-            let mut vertex_data: Vec<SkinnedVertex> = Vec::new();
-            let mut index_data: Vec<u32> = Vec::new();
+    ///
+    /// Like `add_mesh`, but with already created textures for each mesh rather than paths
+    /// to load them from. Returns an error if there isn't exactly one texture for each mesh.
+    ///
+    pub fn add_mesh_with_textures<F: gfx::Factory<R>>(
+        &mut self,
+        factory: &mut F,
+        collada_document: collada::document::ColladaDocument,
+        textures: Vec<TextureView<R>>,
+    ) -> Result<(), &'static str> {
 
-            get_vertex_index_data(&object, &mut vertex_data, &mut index_data);
+        let obj_set = match collada_document.get_obj_set() {
+            Some(obj_set) => obj_set,
+            None => return Err("No meshes found in COLLADA document."),
+        };
 
-            let (vbuf, slice) = factory.create_vertex_buffer_with_slice
-                (&vertex_data, &index_data[..]);
+        if textures.len() != obj_set.objects.len() {
+            return Err("Expected one texture for each mesh in the COLLADA document.");
+        }
 
-            let skinning_transforms_buffer = factory.create_buffer::<T>(
-                MAX_JOINTS,
-                gfx::buffer::Role::Constant,
-                gfx::memory::Usage::Dynamic,
-                gfx::memory::Bind::empty()
-            ).unwrap();
+        let bind_data_set = collada_document.get_bind_data_set();
 
-            let texture = gfx_texture::Texture::from_path(
-                factory,
-                &Path::new(&texture_paths[i]),
-                gfx_texture::Flip::None,
-                &gfx_texture::TextureSettings::new()
-            ).unwrap();
+        for (object, texture) in obj_set.objects.iter().zip(textures) {
 
-            render_batches.push(SkinnedRenderBatch {
-                slice,
-                vertex_buffer: vbuf,
-                skinning_transforms_buffer,
-                texture: (texture.view.clone(), sampler.clone()),
+            let bind_data = bind_data_set.as_ref().and_then(|bind_data_set| {
+                bind_data_set.bind_data.iter().find(|bind_data| bind_data.object_name == object.id)
             });
+
+            let influences = get_vertex_influences(bind_data, object.vertices.len(), &self.skeleton, self.joint_influences)?;
+
+            let mut vertex_data: Vec<SkinnedVertex> = Vec::new();
+            let mut index_data: Vec<u32> = Vec::new();
+
+            get_vertex_index_data(object, &influences, &mut vertex_data, &mut index_data);
+
+            self.add_mesh_from_vertices(factory, &vertex_data, &index_data, texture);
         }
 
-        Ok(Self {
-            pso,
-            render_batches,
-            skeleton: skeleton.clone(),
-        })
+        Ok(())
+    }
+
+    ///
+    /// Add a mesh from the caller's own vertex and index data, e.g. from a mesh format this
+    /// crate doesn't load, drawn as a list of triangles with the given texture. The vertices'
+    /// joint indices are indices into the renderer's `skeleton`.
+    ///
+    pub fn add_mesh_from_vertices<F: gfx::Factory<R>>(
+        &mut self,
+        factory: &mut F,
+        vertices: &[SkinnedVertex],
+        indices: &[u32],
+        texture: TextureView<R>,
+    ) {
+        let (vertex_buffer, slice) = factory.create_vertex_buffer_with_slice(vertices, indices);
+
+        self.render_batches.push(SkinnedRenderBatch {
+            slice,
+            vertex_buffer,
+            texture: (texture, self.sampler.clone()),
+        });
+    }
+
+    /// The skeleton the renderer's meshes are skinned by, loaded from its COLLADA document
+    pub fn skeleton(&self) -> &Skeleton {
+        &self.skeleton
     }
 
+    /// Scale mesh vertices and the translations of the inverse bind poses by `scale`, to
+    /// match the output poses of an `AnimationController` with the same import scale
+    pub fn set_import_scale(&mut self, scale: f32) {
+        self.import_scale = scale;
+    }
+
+    /// Return the scale applied to mesh vertices and inverse bind pose translations
+    pub fn get_import_scale(&self) -> f32 {
+        self.import_scale
+    }
+
+    ///
+    /// Update the skinning transforms and draw all meshes. Equivalent to calling
+    /// `set_joint_matrices` followed by `draw`.
+    ///
     pub fn render<C: gfx::CommandBuffer<R>, Rf: gfx::format::RenderFormat> (
         &mut self,
         encoder: &mut gfx::Encoder<R, C>,
@@ -149,18 +343,52 @@ impl<'a, R: gfx::Resources, T: Transform + HasShaderSources<'a>> SkinnedRenderer
     )
         where T: gfx::traits::Pod
     {
+        self.set_joint_matrices(encoder, joint_poses);
+        self.draw(encoder, out_color, out_depth, view, projection);
+    }
+
+    ///
+    /// Upload the skinning transforms for the given global joint poses. Only the constant
+    /// buffer holding the skinning transforms is updated; vertex buffers are left untouched.
+    ///
+    pub fn set_joint_matrices<C: gfx::CommandBuffer<R>>(
+        &mut self,
+        encoder: &mut gfx::Encoder<R, C>,
+        joint_poses: &[T]
+    )
+        where T: gfx::traits::Pod
+    {
+        let skinning_transforms = self.calculate_skinning_transforms(joint_poses);
+        encoder.update_buffer(&self.skinning_transforms_buffer, &skinning_transforms[..], 0).unwrap();
+    }
 
-        let skinning_transforms = self.calculate_skinning_transforms(&joint_poses);
+    ///
+    /// Draw all meshes with the skinning transforms last uploaded by `set_joint_matrices`
+    ///
+    pub fn draw<C: gfx::CommandBuffer<R>, Rf: gfx::format::RenderFormat> (
+        &self,
+        encoder: &mut gfx::Encoder<R, C>,
+        out_color: &gfx::handle::RenderTargetView<R, Rf>,
+        out_depth: &gfx::handle::DepthStencilView<R, gfx::format::DepthStencil>,
+        view: [[f32; 4]; 4],
+        projection: [[f32; 4]; 4],
+    ) {
+        // Inverse-transpose of the model-view rotation and scale, for transforming normals
+        let normal_matrix = mat3_transposed(mat3_inv([
+            [view[0][0], view[0][1], view[0][2]],
+            [view[1][0], view[1][1], view[1][2]],
+            [view[2][0], view[2][1], view[2][2]],
+        ]));
 
-        for material in self.render_batches.iter_mut() {
-            // FIXME -- should all be able to share the same buffer
-            encoder.update_buffer(&material.skinning_transforms_buffer, &skinning_transforms[..], 0).unwrap();
+        for material in self.render_batches.iter() {
 
             let data = pipe::Data {
                 vertex: material.vertex_buffer.clone(),
                 u_model_view_proj: projection,
                 u_model_view: view,
-                u_skinning_transforms: material.skinning_transforms_buffer.raw().clone(),
+                u_normal_matrix: normal_matrix,
+                u_import_scale: self.import_scale,
+                u_skinning_transforms: self.skinning_transforms_buffer.raw().clone(),
                 u_texture: material.texture.clone(),
                 out_color: out_color.raw().clone(),
                 out_depth: out_depth.clone(),
@@ -170,13 +398,21 @@ impl<'a, R: gfx::Resources, T: Transform + HasShaderSources<'a>> SkinnedRenderer
         }
     }
 
+    ///
+    /// Calculate the skinning transform of each joint, with the inverse bind pose
+    /// translations scaled by the import scale, as for
+    /// `AnimationController::calculate_skinning_transforms`
     ///
     /// TODO - don't allocate a new vector
     ///
     pub fn calculate_skinning_transforms(&self, global_poses: &[T]) -> Vec<T> {
         self.skeleton.joints.iter().enumerate().map(|(i, joint)| {
+            let mut inverse_bind_pose = joint.inverse_bind_pose;
+            if self.import_scale != 1.0 {
+                inverse_bind_pose.set_translation(vec3_scale(inverse_bind_pose.get_translation(), self.import_scale));
+            }
             // TODO avoid conversion...
-            global_poses[i].concat(T::from_matrix(joint.inverse_bind_pose))
+            global_poses[i].concat(T::from_matrix(inverse_bind_pose))
         }).collect()
     }
 }
@@ -185,6 +421,8 @@ gfx_pipeline_base!( pipe {
     vertex: gfx::VertexBuffer<SkinnedVertex>,
     u_model_view_proj: gfx::Global<[[f32; 4]; 4]>,
     u_model_view: gfx::Global<[[f32; 4]; 4]>,
+    u_normal_matrix: gfx::Global<[[f32; 3]; 3]>,
+    u_import_scale: gfx::Global<f32>,
     u_skinning_transforms: gfx::RawConstantBuffer,
     u_texture: gfx::TextureSampler<[f32; 4]>,
     out_color: gfx::RawRenderTarget,
@@ -204,14 +442,47 @@ gfx_pipeline!( pipe {
 });
 */
 
-gfx_vertex_struct!(SkinnedVertex {
+gfx_vertex_struct_meta!(
+    /// The vertex format of meshes drawn by a SkinnedRenderer, in the bind pose, with up to
+    /// eight joint influences. Joint indices are indices into the renderer's skeleton, and
+    /// unused influences have zero weight.
+    vertex_struct_meta SkinnedVertex {
+    /// Position in model space
     pos: [f32; 3] = "pos",
+    /// Normal in model space
     normal: [f32; 3] = "normal",
+    /// Texture coordinates
     uv: [f32; 2] = "uv",
+    /// Indices of the first four joints influencing the vertex
     joint_indices: [i32; 4] = "joint_indices",
+    /// Weights of the first four joint influences
     joint_weights: [f32; 4] = "joint_weights", // TODO last weight is redundant
+    /// Indices of the second four joints, only read with `JointInfluences::Eight`
+    joint_indices_2: [i32; 4] = "joint_indices_2",
+    /// Weights of the second four joint influences
+    joint_weights_2: [f32; 4] = "joint_weights_2",
 });
 
+impl SkinnedVertex {
+
+    /// Create a vertex skinned by the given (joint index, weight) influences, whose weights
+    /// should sum to one. If there are more than eight, only the most heavily weighted are
+    /// kept, renormalized. Renderers created with `JointInfluences::Four` only read the
+    /// first four influences.
+    pub fn new(pos: [f32; 3], normal: [f32; 3], uv: [f32; 2], influences: &[(i32, f32)]) -> Self {
+        let vertex = Self { pos, normal, uv, ..Default::default() };
+        vertex.with_influences(&limit_influences(influences.to_vec(), 8))
+    }
+
+    fn with_influences(mut self, (joints, weights): &VertexInfluences) -> Self {
+        self.joint_indices.copy_from_slice(&joints[0 .. 4]);
+        self.joint_weights.copy_from_slice(&weights[0 .. 4]);
+        self.joint_indices_2.copy_from_slice(&joints[4 .. 8]);
+        self.joint_weights_2.copy_from_slice(&weights[4 .. 8]);
+        self
+    }
+}
+
 impl Default for SkinnedVertex {
     fn default() -> Self {
         Self {
@@ -220,26 +491,93 @@ impl Default for SkinnedVertex {
             uv: [0.0; 2],
             joint_indices: [0; 4],
             joint_weights: [0.0; 4],
+            joint_indices_2: [0; 4],
+            joint_weights_2: [0.0; 4],
+        }
+    }
+}
+
+/// Joint indices and weights influencing a vertex, with unused influences having zero weight
+type VertexInfluences = ([i32; 8], [f32; 8]);
+
+/// Insert the GLSL define for the number of joint influences after the `#version` line
+fn with_joint_influences_define(source: &[u8], joint_influences: JointInfluences) -> Vec<u8> {
+    let version_end = source.iter().position(|&c| c == b'\n').map_or(source.len(), |i| i + 1);
+    let mut result = source[.. version_end].to_vec();
+    result.extend_from_slice(format!("#define MAX_JOINT_INFLUENCES {}\n", joint_influences.count()).as_bytes());
+    result.extend_from_slice(&source[version_end ..]);
+    result
+}
+
+/// Gather the joint influences for each vertex from the COLLADA bind data, keeping only
+/// the most heavily weighted joints (renormalized) when there are more than allowed.
+fn get_vertex_influences(
+    bind_data: Option<&collada::BindData>,
+    vertex_count: usize,
+    skeleton: &Skeleton,
+    joint_influences: JointInfluences,
+) -> Result<Vec<VertexInfluences>, &'static str> {
+
+    let mut influences: Vec<Vec<(i32, f32)>> = vec![Vec::new(); vertex_count];
+
+    if let Some(bind_data) = bind_data {
+        for vertex_weight in bind_data.vertex_weights.iter() {
+
+            let weight = bind_data.weights[vertex_weight.weight];
+            if weight == 0.0 {
+                continue;
+            }
+
+            let joint_name = &bind_data.joint_names[vertex_weight.joint as usize];
+            let joint_index = match skeleton.get_joint_index(&joint_name[..]) {
+                Some(joint_index) => joint_index as i32,
+                None => return Err("Mesh is bound to a joint missing from the renderer's skeleton."),
+            };
+
+            if let Some(vertex_influences) = influences.get_mut(vertex_weight.vertex) {
+                vertex_influences.push((joint_index, weight));
+            }
+        }
+    }
+
+    let max_influences = joint_influences.count();
+
+    Ok(influences.into_iter().map(|vertex_influences| limit_influences(vertex_influences, max_influences)).collect())
+}
+
+/// Keep only the `max_influences` most heavily weighted joint influences (renormalized)
+/// when there are more than that
+fn limit_influences(mut vertex_influences: Vec<(i32, f32)>, max_influences: usize) -> VertexInfluences {
+
+    if vertex_influences.len() > max_influences {
+        vertex_influences.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        vertex_influences.truncate(max_influences);
+
+        let total_weight: f32 = vertex_influences.iter().map(|&(_, weight)| weight).sum();
+        if total_weight > 0.0 {
+            for influence in vertex_influences.iter_mut() {
+                influence.1 /= total_weight;
+            }
         }
     }
+
+    let mut result: VertexInfluences = ([0; 8], [0.0; 8]);
+    for (i, &(joint_index, weight)) in vertex_influences.iter().enumerate() {
+        result.0[i] = joint_index;
+        result.1[i] = weight;
+    }
+    result
 }
 
-fn vtn_to_vertex(a: collada::VTNIndex, obj: &collada::Object) -> SkinnedVertex
+fn vtn_to_vertex(a: collada::VTNIndex, obj: &collada::Object, influences: &[VertexInfluences]) -> SkinnedVertex
 {
     let mut vertex: SkinnedVertex = Default::default();
     let position = obj.vertices[a.0];
 
     vertex.pos = [position.x as f32, position.y as f32, position.z as f32];
 
-    if obj.joint_weights.len() == obj.vertices.len() {
-        let weights = obj.joint_weights[a.0];
-        vertex.joint_weights = weights.weights;
-        vertex.joint_indices = [
-            weights.joints[0] as i32,
-            weights.joints[1] as i32,
-            weights.joints[2] as i32,
-            weights.joints[3] as i32,
-        ];
+    if let Some(influences) = influences.get(a.0) {
+        vertex = vertex.with_influences(influences);
     }
 
     if let Some(uv) = a.1 {
@@ -255,37 +593,34 @@ fn vtn_to_vertex(a: collada::VTNIndex, obj: &collada::Object) -> SkinnedVertex
     vertex
 }
 
-fn get_vertex_index_data(obj: &collada::Object, vertex_data: &mut Vec<SkinnedVertex>, index_data: &mut Vec<u32>) {
+fn get_vertex_index_data(obj: &collada::Object, influences: &[VertexInfluences], vertex_data: &mut Vec<SkinnedVertex>, index_data: &mut Vec<u32>) {
     for geom in obj.geometry.iter() {
         let mut i = vertex_data.len() as u32;
         let mut uvs: u32 = 0;
         let mut normals: u32 = 0;
         {
             let mut add = |a: collada::VTNIndex| {
-                if let Some(_) = a.1 { uvs += 1; }
-                if let Some(_) = a.2 { normals += 1; }
-                vertex_data.push(vtn_to_vertex(a, obj));
+                if a.1.is_some() { uvs += 1; }
+                if a.2.is_some() { normals += 1; }
+                vertex_data.push(vtn_to_vertex(a, obj, influences));
                 index_data.push(i);
                 i += 1;
             };
             for mesh in geom.mesh.iter() {
-                match mesh {
-                    &collada::PrimitiveElement::Triangles(ref triangles) => {
-                        for &(a, b, c) in &triangles.vertices {
+                match *mesh {
+                    collada::PrimitiveElement::Triangles(ref triangles) => {
+                        for &(a, b, c) in triangles.vertices.iter() {
                             add(a);
                             add(b);
                             add(c);
                         }
                     }
-                    &collada::PrimitiveElement::Polylist(ref polylist) => {
-                        for shape in &polylist.shapes {
-                            match shape {
-                                &collada::Shape::Triangle(a, b, c) => {
-                                    add(a);
-                                    add(b);
-                                    add(c);
-                                }
-                                _ => {}
+                    collada::PrimitiveElement::Polylist(ref polylist) => {
+                        for shape in polylist.shapes.iter() {
+                            if let collada::Shape::Triangle(a, b, c) = *shape {
+                                add(a);
+                                add(b);
+                                add(c);
                             }
                         }
                     }
@@ -294,3 +629,64 @@ fn get_vertex_index_data(obj: &collada::Object, vertex_data: &mut Vec<SkinnedVer
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    use collada;
+
+    use math::*;
+    use skeleton::{Joint, Skeleton};
+
+    use super::{get_vertex_influences, JointInfluences, SkinnedVertex};
+
+    static EPSILON: f32 = 0.000001;
+
+    #[test]
+    fn test_vertex_influences_truncated_and_renormalized() {
+
+        let joint_names: Vec<String> = (0 .. 6).map(|i| format!("joint_{}", i)).collect();
+
+        let skeleton = Skeleton::new(joint_names.iter().rev().map(|name| Joint::new(name, None, mat4_id())).collect());
+
+        let bind_data = collada::BindData {
+            object_name: "mesh".to_string(),
+            skeleton_name: "skeleton".to_string(),
+            joint_names,
+            vertex_weights: (0 .. 6).map(|i| collada::VertexWeight { vertex: 0, joint: i as u8, weight: i }).collect(),
+            weights: vec![0.05, 0.3, 0.1, 0.2, 0.25, 0.1],
+            inverse_bind_poses: vec![mat4_id(); 6],
+        };
+
+        let influences = get_vertex_influences(Some(&bind_data), 1, &skeleton, JointInfluences::Four).unwrap();
+        let (joints, weights) = influences[0];
+
+        // Joints are remapped by name to the reversed skeleton, heaviest first
+        assert_eq!(joints[0 .. 4], [4, 1, 2, 3]);
+        assert!((weights.iter().sum::<f32>() - 1.0).abs() < EPSILON);
+        assert!((weights[0] - 0.3 / 0.85).abs() < EPSILON);
+        assert_eq!(weights[4 .. 8], [0.0; 4]);
+
+        let influences = get_vertex_influences(Some(&bind_data), 1, &skeleton, JointInfluences::Eight).unwrap();
+        let (joints, weights) = influences[0];
+        assert_eq!(joints[0 .. 6], [5, 4, 3, 2, 1, 0]);
+        assert!((weights[1] - 0.3).abs() < EPSILON);
+    }
+
+    #[test]
+    fn test_skinned_vertex_new() {
+
+        let vertex = SkinnedVertex::new([1.0, 2.0, 3.0], [0.0, 1.0, 0.0], [0.5, 0.5], &[(2, 0.75), (5, 0.25)]);
+        assert_eq!(vertex.pos, [1.0, 2.0, 3.0]);
+        assert_eq!(vertex.joint_indices, [2, 5, 0, 0]);
+        assert_eq!(vertex.joint_weights, [0.75, 0.25, 0.0, 0.0]);
+        assert_eq!(vertex.joint_weights_2, [0.0; 4]);
+
+        // Beyond eight influences, the lightest is dropped and the rest renormalized
+        let influences: Vec<(i32, f32)> = (0 .. 9).map(|i| (i, if i == 4 { 0.02 } else { 0.1225 })).collect();
+        let vertex = SkinnedVertex::new([0.0; 3], [0.0; 3], [0.0; 2], &influences[..]);
+        assert!(!vertex.joint_indices.contains(&4) && !vertex.joint_indices_2.contains(&4));
+        let total_weight: f32 = vertex.joint_weights.iter().chain(vertex.joint_weights_2.iter()).sum();
+        assert!((total_weight - 1.0).abs() < EPSILON);
+    }
+}
@@ -0,0 +1,84 @@
+//! Linear blend skinning on the CPU, for uses that don't have a GPU context such as
+//! collision meshes or headless exporters.
+
+use math::*;
+
+/// Transform each vertex position by the weighted blend of its joints' skinning matrices.
+/// Weights are normalized over the influences with a skinning matrix, so they needn't
+/// sum to one.
+///
+/// # Arguments
+///
+/// * `positions` - The bind-pose vertex positions
+/// * `weights` - The weights of up to four joint influences for each vertex
+/// * `indices` - The indices into `skin_matrices` of each vertex's joint influences
+/// * `skin_matrices` - The skinning matrix for each joint, i.e. the joint's global pose
+///                     multiplied by its inverse bind pose
+/// * `out` - The output slice of skinned vertex positions, one for each input position
+pub fn skin_vertices(
+    positions: &[Vector3<f32>],
+    weights: &[[f32; 4]],
+    indices: &[[u16; 4]],
+    skin_matrices: &[Matrix4<f32>],
+    out: &mut [Vector3<f32>],
+) {
+    for (((position, vertex_weights), vertex_indices), skinned_position) in positions.iter()
+        .zip(weights.iter())
+        .zip(indices.iter())
+        .zip(out.iter_mut())
+    {
+        let p = [position[0], position[1], position[2], 1.0];
+
+        let mut blended = [0.0, 0.0, 0.0];
+        let mut total_weight = 0.0;
+
+        for (&weight, &index) in vertex_weights.iter().zip(vertex_indices.iter()) {
+            if weight == 0.0 {
+                continue;
+            }
+            if let Some(&skin_matrix) = skin_matrices.get(index as usize) {
+                let t = row_mat4_transform(skin_matrix, p);
+                blended = vec3_add(blended, vec3_scale([t[0], t[1], t[2]], weight));
+                total_weight += weight;
+            }
+        }
+
+        // Vertices without any influences are left in their bind pose
+        *skinned_position = if total_weight > 0.0 { vec3_scale(blended, 1.0 / total_weight) } else { *position };
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use math::*;
+
+    use super::skin_vertices;
+
+    static EPSILON: f32 = 0.000001;
+
+    #[test]
+    fn test_skin_vertices() {
+
+        let mut translate_x = mat4_id();
+        translate_x[0][3] = 2.0;
+
+        let mut translate_y = mat4_id();
+        translate_y[1][3] = 4.0;
+
+        let positions = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]];
+        let weights = [[1.0, 0.0, 0.0, 0.0], [0.5, 0.5, 0.0, 0.0], [0.0; 4], [2.0, 2.0, 0.0, 0.0], [0.5, 0.5, 0.0, 0.0]];
+        let indices = [[0, 0, 0, 0], [0, 1, 0, 0], [0; 4], [0, 1, 0, 0], [0, 7, 0, 0]];
+
+        let mut out = [[0.0; 3]; 5];
+        skin_vertices(&positions, &weights, &indices, &[translate_x, translate_y], &mut out);
+
+        assert!(vec3_len(vec3_sub(out[0], [3.0, 0.0, 0.0])) < EPSILON);
+        assert!(vec3_len(vec3_sub(out[1], [1.0, 3.0, 0.0])) < EPSILON);
+        assert!(vec3_len(vec3_sub(out[2], [0.0, 0.0, 1.0])) < EPSILON);
+
+        // Weights that don't sum to one are normalized, including over missing joints
+        assert!(vec3_len(vec3_sub(out[3], [1.0, 3.0, 0.0])) < EPSILON);
+        assert!(vec3_len(vec3_sub(out[4], [3.0, 0.0, 0.0])) < EPSILON);
+    }
+}
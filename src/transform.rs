@@ -1,4 +1,6 @@
 use interpolation;
+use serde::{Deserialize, Serialize};
+
 use math::*;
 
 pub trait Transform: Copy {
@@ -8,15 +10,21 @@ pub trait Transform: Copy {
     fn lerp(self, other: Self, parameter: f32) -> Self;
     fn transform_vector(self, v: Vector3<f32>) -> Vector3<f32>;
     fn to_matrix(self) -> Matrix4<f32>;
-    fn from_matrix(Matrix4<f32>) -> Self;
+    fn from_matrix(m: Matrix4<f32>) -> Self;
     fn set_rotation(&mut self, rotation: Quaternion<f32>);
     fn get_rotation(self) -> Quaternion<f32>;
     fn set_translation(&mut self, translation: Vector3<f32>);
     fn get_translation(self) -> Vector3<f32>;
+
+    /// Set the uniform scale factor, which is ignored by transforms without one
+    fn set_scale(&mut self, _scale: f32) {}
+
+    /// The uniform scale factor, which is 1 for transforms without one
+    fn get_scale(self) -> f32 { 1.0 }
 }
 
 /// Transformation represented by separate scaling, translation, and rotation factors.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct QVTransform {
     /// Translation
     pub translation: Vector3<f32>,
@@ -54,6 +62,14 @@ impl Transform for QVTransform {
         self.translation
     }
 
+    fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    fn get_scale(self) -> f32 {
+        self.scale
+    }
+
     fn concat(self, other: Self) -> Self {
         Self::from_matrix(self.to_matrix().concat(other.to_matrix()))
     }
@@ -70,15 +86,22 @@ impl Transform for QVTransform {
         }
     }
 
+    /// Transform the vector by the matrix from `to_matrix`, so that it's scaled and
+    /// rotated before being translated, the same as for global pose matrices
     fn transform_vector(self, v: Vector3<f32>) -> Vector3<f32> {
-        let v = quaternion::rotate_vector(self.rotation, v);
-        let v = vec3_add(v, self.translation);
-        vec3_scale(v, self.scale)
+        self.to_matrix().transform_vector(v)
     }
 
+    /// Compose the rotation, scale, and translation into an affine matrix
     fn to_matrix(self) -> Matrix4<f32> {
         let mut m = quaternion_to_matrix(self.rotation);
 
+        for basis in m.iter_mut().take(3) {
+            for x in basis.iter_mut().take(3) {
+                *x *= self.scale;
+            }
+        }
+
         m[0][3] = self.translation[0];
         m[1][3] = self.translation[1];
         m[2][3] = self.translation[2];
@@ -86,6 +109,8 @@ impl Transform for QVTransform {
         m
     }
 
+    /// Decompose an affine matrix into translation, rotation, and scale. Since the scale
+    /// factor is uniform, it is taken as the average length of the three basis vectors.
     fn from_matrix(m: Matrix4<f32>) -> Self {
 
         let rotation = matrix_to_quaternion(&m);
@@ -94,10 +119,14 @@ impl Transform for QVTransform {
                            m[1][3],
                            m[2][3]];
 
+        let scale = (vec3_len([m[0][0], m[0][1], m[0][2]])
+                     + vec3_len([m[1][0], m[1][1], m[1][2]])
+                     + vec3_len([m[2][0], m[2][1], m[2][2]])) / 3.0;
+
         Self {
-            rotation: rotation,
-            scale: 1.0,
-            translation: translation,
+            rotation,
+            scale,
+            translation,
         }
     }
 
@@ -254,10 +283,72 @@ mod test {
     use quaternion;
     use dual_quaternion;
 
-    use super::Transform;
+    use super::{Transform, QVTransform};
 
     static EPSILON: f32 = 0.000001;
 
+    #[test]
+    fn test_qv_transform_from_scaled_matrix() {
+
+        let q = quaternion::rotation_from_to([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+
+        let mut m = ::math::quaternion_to_matrix(q);
+        for basis in m.iter_mut().take(3) {
+            for x in basis.iter_mut().take(3) {
+                *x *= 2.0;
+            }
+        }
+        m[0][3] = 1.0;
+        m[1][3] = 2.0;
+        m[2][3] = 3.0;
+
+        let t = QVTransform::from_matrix(m);
+        assert!((t.scale - 2.0).abs() < EPSILON);
+        assert!(vecmath::vec3_len(vecmath::vec3_sub(t.translation, [1.0, 2.0, 3.0])) < EPSILON);
+
+        let dot = t.rotation.0 * q.0 + vecmath::vec3_dot(t.rotation.1, q.1);
+        assert!((dot.abs() - 1.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_qv_transform_to_matrix_round_trip() {
+
+        let t = QVTransform {
+            translation: [1.0, 2.0, 3.0],
+            scale: 0.5,
+            rotation: quaternion::rotation_from_to([1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        };
+
+        let m = t.to_matrix();
+        let length = vecmath::vec3_len(vecmath::vec3_sub(m.transform_vector([1.0, 0.0, 0.0]), t.translation));
+        assert!((length - 0.5).abs() < EPSILON);
+
+        let round_trip = QVTransform::from_matrix(m);
+        assert!((round_trip.scale - t.scale).abs() < EPSILON);
+        assert!(vecmath::vec3_len(vecmath::vec3_sub(round_trip.translation, t.translation)) < EPSILON);
+    }
+
+    #[test]
+    fn test_qv_transform_vector_matches_matrix() {
+
+        // Doubled in size and moved along x, with the translation itself unscaled
+        let mut t = QVTransform {
+            translation: [1.0, 0.0, 0.0],
+            scale: 2.0,
+            rotation: quaternion::id(),
+        };
+
+        let v = [1.0, 2.0, 3.0];
+        assert!(vecmath::vec3_len(vecmath::vec3_sub(t.transform_vector(v), [3.0, 4.0, 6.0])) < EPSILON);
+
+        // Also turned a half turn about z, taking the scaled [2, 4, 6] to [-2, -4, 6] before
+        // translating, and the same through the matrix
+        t.rotation = (0.0, [0.0, 0.0, 1.0]);
+        let expected = [-1.0, -4.0, 6.0];
+        assert!(vecmath::vec3_len(vecmath::vec3_sub(t.transform_vector(v), expected)) < EPSILON);
+        assert!(vecmath::vec3_len(vecmath::vec3_sub(t.to_matrix().transform_vector(v), expected)) < EPSILON);
+    }
+
     #[test]
     fn test_dual_quaternion_to_matrix() {
 
@@ -0,0 +1,149 @@
+//! Golden-pose tests of clip interpolation, LerpNode blending and global pose calculation,
+//! using a hand-built two-joint skeleton and hand-built clips with known poses.
+
+extern crate skeletal_animation;
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use skeletal_animation::blend_tree::{AnimBlendTree, BlendTreeNodeDef};
+use skeletal_animation::math::*;
+use skeletal_animation::{AnimationClip, AnimationClipBuilder, Joint, ParameterSet, QVTransform, Shared, Skeleton, Transform};
+
+static EPSILON: f32 = 0.0001;
+
+/// A root joint at the origin, with a child one unit up the y-axis
+fn make_skeleton() -> Shared<Skeleton> {
+
+    let mut child_bind_pose = mat4_id();
+    child_bind_pose[1][3] = 1.0;
+
+    Shared::new(Skeleton::new(vec![
+        Joint::new("root", None, mat4_id()),
+        Joint::new("child", Some(0), child_bind_pose),
+    ]))
+}
+
+/// A clip of three samples at two samples per second, with the root moving `step` along
+/// the x-axis per sample and turning half a turn about z per sample, and the child held two
+/// units up the y-axis from the root
+fn make_clip(step: f32) -> AnimationClip<QVTransform> {
+
+    let mut builder = AnimationClipBuilder::new().with_samples_per_second(2.0);
+
+    for i in 0 .. 3 {
+        let mut root_pose = QVTransform::identity();
+        root_pose.set_translation([i as f32 * step, 0.0, 0.0]);
+        root_pose.set_rotation(quaternion::axis_angle([0.0, 0.0, 1.0], i as f32 * PI));
+
+        let mut child_pose = QVTransform::identity();
+        child_pose.set_translation([0.0, 2.0, 0.0]);
+
+        builder.push_sample(vec![root_pose, child_pose]);
+    }
+
+    builder.build().unwrap()
+}
+
+fn assert_near(actual: Vector3<f32>, expected: Vector3<f32>) {
+    assert!(vec3_len(vec3_sub(actual, expected)) < EPSILON, "{:?} != {:?}", actual, expected);
+}
+
+#[test]
+fn test_interpolated_poses_at_known_times() {
+
+    let clip = make_clip(1.0);
+    let mut poses = [QVTransform::identity(); 2];
+
+    // Exactly on samples 1 and 2
+    clip.get_pose_at_time(0.5, &mut poses);
+    assert_near(poses[0].get_translation(), [1.0, 0.0, 0.0]);
+    clip.get_pose_at_time(1.0, &mut poses);
+    assert_near(poses[0].get_translation(), [2.0, 0.0, 0.0]);
+
+    // A quarter and three quarters of the way through the first two samples
+    clip.get_pose_at_time(0.125, &mut poses);
+    assert_near(poses[0].get_translation(), [0.25, 0.0, 0.0]);
+    clip.get_pose_at_time(0.375, &mut poses);
+    assert_near(poses[0].get_translation(), [0.75, 0.0, 0.0]);
+    assert_near(poses[1].get_translation(), [0.0, 2.0, 0.0]);
+
+    // Past the last sample, looping clips interpolate back towards the first sample...
+    clip.get_pose_at_time(1.25, &mut poses);
+    assert_near(poses[0].get_translation(), [1.0, 0.0, 0.0]);
+
+    // ...and clamped sampling holds the last sample
+    clip.get_pose_at_time_clamped(1.25, &mut poses);
+    assert_near(poses[0].get_translation(), [2.0, 0.0, 0.0]);
+}
+
+#[test]
+fn test_lerp_node() {
+
+    let skeleton = make_skeleton();
+
+    let mut animations = HashMap::new();
+    animations.insert("walk".to_string(), Shared::new(make_clip(1.0)));
+    animations.insert("run".to_string(), Shared::new(make_clip(3.0)));
+
+    let clip = |name: &str| Box::new(BlendTreeNodeDef::ClipNode { clip_source: name.to_string(), loop_mode: None, phase_param: None });
+    let def = BlendTreeNodeDef::LerpNode {
+        inputs: (clip("walk"), clip("run")),
+        param: "walk-to-run".to_string(),
+        phase_param: None,
+    };
+
+    let mut params = ParameterSet::new();
+    params.declare("walk-to-run");
+    let tree = AnimBlendTree::from_def(def, &animations, skeleton, &params).unwrap();
+
+    let mut poses = [QVTransform::identity(); 2];
+
+    // Each input alone
+    tree.get_output_pose(0.5, &params, &mut poses);
+    assert_near(poses[0].get_translation(), [1.0, 0.0, 0.0]);
+    params.set("walk-to-run", 1.0);
+    tree.get_output_pose(0.5, &params, &mut poses);
+    assert_near(poses[0].get_translation(), [3.0, 0.0, 0.0]);
+
+    // Halfway between x = 0.5 and x = 1.5, and between x = 1.5 and x = 4.5
+    params.set("walk-to-run", 0.5);
+    tree.get_output_pose(0.25, &params, &mut poses);
+    assert_near(poses[0].get_translation(), [1.0, 0.0, 0.0]);
+    tree.get_output_pose(0.75, &params, &mut poses);
+    assert_near(poses[0].get_translation(), [3.0, 0.0, 0.0]);
+    assert_near(poses[1].get_translation(), [0.0, 2.0, 0.0]);
+}
+
+#[test]
+fn test_calculate_global_poses() {
+
+    let skeleton = make_skeleton();
+    let clip = make_clip(1.0);
+
+    let mut global_poses = [mat4_id(); 2];
+
+    // Local poses are taken straight from the samples, as sampling normalizes rotations only
+    // approximately. Unrotated: the child is two units above the root
+    let local_poses = &clip.samples[0].local_poses[..];
+    skeleton.calculate_global_poses(local_poses, &mut global_poses);
+    assert_near(global_poses[0].transform_vector([0.0, 0.0, 0.0]), [0.0, 0.0, 0.0]);
+    assert_near(global_poses[1].transform_vector([0.0, 0.0, 0.0]), [0.0, 2.0, 0.0]);
+
+    // Turned half a turn about z, the child hangs below the root at x = 1, and points in
+    // the child's space turn with it
+    let local_poses = &clip.samples[1].local_poses[..];
+    skeleton.calculate_global_poses(local_poses, &mut global_poses);
+    assert_near(global_poses[0].transform_vector([0.0, 0.0, 0.0]), [1.0, 0.0, 0.0]);
+    assert_near(global_poses[1].transform_vector([0.0, 0.0, 0.0]), [1.0, -2.0, 0.0]);
+    assert_near(global_poses[1].transform_vector([1.0, 0.0, 0.0]), [0.0, -2.0, 0.0]);
+
+    // Concatenating the local poses directly agrees with the matrices
+    let child_global_pose = local_poses[0].concat(local_poses[1]);
+    assert_near(child_global_pose.transform_vector([0.0, 0.0, 0.0]), [1.0, -2.0, 0.0]);
+
+    // A full turn, with the child above the root at x = 2 again
+    let local_poses = &clip.samples[2].local_poses[..];
+    skeleton.calculate_global_poses(local_poses, &mut global_poses);
+    assert_near(global_poses[1].transform_vector([0.0, 0.0, 0.0]), [2.0, 2.0, 0.0]);
+}